@@ -0,0 +1,79 @@
+use chrono::Utc;
+use diesel::{insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::tracked_collection::TrackedCollection;
+use immortalis_backend_common::downloader::Downloader;
+use immortalis_backend_common::schema::tracked_collections;
+use immortalis_backend_common::tracking::check_collection;
+use immortalis_backend_common::utilities::{get_url_type, UrlType};
+
+use crate::AdminError;
+
+pub async fn list_collections(
+    db_connection: &mut AsyncPgConnection,
+) -> QueryResult<Vec<TrackedCollection>> {
+    tracked_collections::table
+        .order(tracked_collections::id)
+        .load::<TrackedCollection>(db_connection)
+        .await
+}
+
+/// starts tracking the collection at url, returns false if it is tracked already
+pub async fn add_collection(
+    db_connection: &mut AsyncPgConnection,
+    url: &str,
+) -> Result<bool, AdminError> {
+    match get_url_type(url) {
+        UrlType::Collection | UrlType::VideoOrCollection => (),
+        _ => {
+            return Err(AdminError::Rejected(format!(
+                "{} is not a collection url",
+                url
+            )))
+        }
+    }
+    Ok(insert_into(tracked_collections::table)
+        .values(tracked_collections::url.eq(url))
+        .on_conflict_do_nothing()
+        .execute(db_connection)
+        .await?
+        > 0)
+}
+
+/// stops tracking the collection. Videos and schedules discovered in it are kept
+pub async fn delete_collection(
+    db_connection: &mut AsyncPgConnection,
+    id: i32,
+) -> Result<TrackedCollection, AdminError> {
+    diesel::delete(tracked_collections::table.find(id))
+        .get_result::<TrackedCollection>(db_connection)
+        .await
+        .optional()?
+        .ok_or_else(|| AdminError::NotFound(format!("collection {}", id)))
+}
+
+/// runs the tracker for the collection right away, regardless of when it has been checked last
+pub async fn track_collection(
+    db_connection: &mut AsyncPgConnection,
+    downloader: &dyn Downloader,
+    id: i32,
+) -> Result<TrackedCollection, AdminError> {
+    let tracked_collection = tracked_collections::table
+        .find(id)
+        .first::<TrackedCollection>(db_connection)
+        .await
+        .optional()?
+        .ok_or_else(|| AdminError::NotFound(format!("collection {}", id)))?;
+
+    if !check_collection(db_connection, downloader, &tracked_collection).await {
+        return Err(AdminError::Rejected(format!(
+            "could not list collection {}",
+            tracked_collection.url
+        )));
+    }
+
+    Ok(update(tracked_collections::table.find(id))
+        .set(tracked_collections::last_checked.eq(Utc::now()))
+        .get_result::<TrackedCollection>(db_connection)
+        .await?)
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::file::File;
+use immortalis_backend_common::schema::{files, videos};
+use immortalis_backend_common::storage::content_addressed;
+use immortalis_backend_common::storage::Storage;
+use serde::Serialize;
+
+use crate::AdminError;
+
+/// a file along with the videos using it as their video or thumbnail
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FileEntry {
+    #[serde(flatten)]
+    pub file: File,
+    pub storage_key: String,
+    pub video_ids: Vec<i32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FileVerification {
+    pub id: uuid::Uuid,
+    pub storage_key: String,
+    pub expected_checksum: Option<String>,
+    pub actual_checksum: String,
+    pub expected_size: i64,
+    pub actual_size: i64,
+    /// false if the size differs, or the checksum if the file has one
+    pub valid: bool,
+}
+
+pub async fn get_file(
+    db_connection: &mut AsyncPgConnection,
+    id: uuid::Uuid,
+) -> Result<FileEntry, AdminError> {
+    let file = files::table
+        .find(id)
+        .first::<File>(db_connection)
+        .await
+        .optional()?
+        .ok_or_else(|| AdminError::NotFound(format!("file {}", id)))?;
+    let video_ids = videos::table
+        .filter(videos::file_id.eq(id).or(videos::thumbnail_id.eq(id)))
+        .select(videos::id)
+        .load::<i32>(db_connection)
+        .await?;
+    Ok(FileEntry {
+        storage_key: file.storage_key(),
+        file,
+        video_ids,
+    })
+}
+
+/// copies the stored object of the file to destination
+pub async fn download_file(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    id: uuid::Uuid,
+    destination: &Path,
+) -> Result<FileEntry, AdminError> {
+    let entry = get_file(db_connection, id).await?;
+    storage.get_file(&entry.storage_key, destination).await?;
+    Ok(entry)
+}
+
+/// fetches the stored object of the file to temp_file_storage_location and compares its size and checksum with the ones recorded in the database
+pub async fn verify_file(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    id: uuid::Uuid,
+    temp_file_storage_location: &Path,
+) -> Result<FileVerification, AdminError> {
+    let entry = get_file(db_connection, id).await?;
+    let temp_file = temp_file_storage_location.join(format!("verify-{}", id));
+    storage.get_file(&entry.storage_key, &temp_file).await?;
+    let checksum = content_addressed::checksum_file(&temp_file).await;
+    tokio::fs::remove_file(&temp_file).await?;
+    let (actual_checksum, actual_size) = checksum?;
+
+    Ok(FileVerification {
+        id,
+        valid: actual_size == entry.file.size
+            && entry
+                .file
+                .checksum
+                .as_ref()
+                .map_or(true, |checksum| *checksum == actual_checksum),
+        storage_key: entry.storage_key,
+        expected_checksum: entry.file.checksum,
+        actual_checksum,
+        expected_size: entry.file.size,
+        actual_size,
+    })
+}
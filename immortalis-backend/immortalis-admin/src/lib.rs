@@ -0,0 +1,59 @@
+pub mod collections;
+pub mod files;
+pub mod schedules;
+pub mod videos;
+
+use immortalis_backend_common::config::ConfigError;
+use immortalis_backend_common::storage::content_addressed::BlobError;
+use immortalis_backend_common::storage::StorageError;
+
+#[derive(Debug)]
+pub enum AdminError {
+    Database(diesel::result::Error),
+    Storage(StorageError),
+    NotFound(String),
+    /// the operation would interfere with a running archival or the input is invalid
+    Rejected(String),
+    Config(ConfigError),
+}
+
+impl std::fmt::Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminError::Database(e) => write!(f, "database error: {}", e),
+            AdminError::Storage(e) => write!(f, "storage error: {}", e),
+            AdminError::NotFound(what) => write!(f, "{} not found", what),
+            AdminError::Rejected(reason) => write!(f, "{}", reason),
+            AdminError::Config(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+impl From<diesel::result::Error> for AdminError {
+    fn from(e: diesel::result::Error) -> Self {
+        AdminError::Database(e)
+    }
+}
+
+impl From<StorageError> for AdminError {
+    fn from(e: StorageError) -> Self {
+        AdminError::Storage(e)
+    }
+}
+
+impl From<std::io::Error> for AdminError {
+    fn from(e: std::io::Error) -> Self {
+        AdminError::Storage(StorageError::Io(e))
+    }
+}
+
+impl From<BlobError> for AdminError {
+    fn from(e: BlobError) -> Self {
+        match e {
+            BlobError::Database(e) => AdminError::Database(e),
+            BlobError::Storage(e) => AdminError::Storage(e),
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{thread, time::Duration};
+
+use clap::{Args, Parser, Subcommand};
+use diesel::{Connection, PgConnection};
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use diesel_migrations::{HarnessWithOutput, MigrationHarness};
+use dotenvy::dotenv;
+use immortalis_admin::{collections, files, schedules, videos, AdminError};
+use immortalis_backend_common::config::{self, ServiceConfig};
+use immortalis_backend_common::database_models::scheduled_archival::MANUAL_PRIORITY;
+use immortalis_backend_common::database_models::video_status::VideoStatus;
+use immortalis_backend_common::downloader::yt_dlp::YtDlpDownloader;
+use immortalis_backend_common::env_var_config::{
+    EnvVarConfigAdmin, EnvVarConfigCommon, TelemetryConfig,
+};
+use immortalis_backend_common::export::bundle::write_bundle;
+use immortalis_backend_common::export::{write_catalogue, CatalogueFormat, ExportFilter};
+use immortalis_backend_common::import::import_directory;
+use immortalis_backend_common::migrations::MIGRATIONS;
+use immortalis_backend_common::storage::reconciliation::reconcile;
+use immortalis_backend_common::storage::{create_storage, Storage};
+use immortalis_backend_common::telemetry;
+use serde::Serialize;
+use tracing::{error, info};
+
+#[derive(Parser)]
+#[command(
+    name = "immortalis-admin",
+    about = "Operates an immortalis deployment. Results are printed to stdout as one JSON object per line"
+)]
+struct Cli {
+    /// TOML file with the configuration, the environment takes precedence over it. Defaults to the file named by CONFIG_FILE
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// runs pending migrations. It won't create the database if it doesn't exist already
+    Migrate,
+    #[command(subcommand)]
+    Schedule(ScheduleCommand),
+    #[command(subcommand)]
+    Collection(CollectionCommand),
+    #[command(subcommand)]
+    Video(VideoCommand),
+    #[command(subcommand)]
+    File(FileCommand),
+    #[command(subcommand)]
+    Storage(StorageCommand),
+    #[command(subcommand)]
+    Export(ExportCommand),
+    /// registers the videos of a directory downloaded with yt-dlp --write-info-json as archived
+    Import {
+        #[arg(long)]
+        directory: PathBuf,
+        /// copies the files into the storage instead of moving them, leaving the directory untouched
+        #[arg(long)]
+        copy: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ScheduleCommand {
+    /// lists schedules in the order they are archived
+    List {
+        /// only lists schedules older than --older-than-minutes that no worker is archiving
+        #[arg(long)]
+        stuck: bool,
+        #[arg(long, default_value_t = 60)]
+        older_than_minutes: i64,
+        #[arg(long, default_value_t = 100)]
+        limit: i64,
+    },
+    /// schedules the archival of a video
+    Add {
+        url: String,
+        #[arg(long, default_value_t = MANUAL_PRIORITY)]
+        priority: i16,
+    },
+    /// makes schedules due right away
+    Requeue {
+        #[arg(required_unless_present = "failed")]
+        ids: Vec<i32>,
+        /// requeues every video whose archival failed
+        #[arg(long, conflicts_with = "ids")]
+        failed: bool,
+    },
+    /// changes the priority of a schedule, higher priorities are archived first
+    Priority {
+        id: i32,
+        priority: i16,
+    },
+    Delete {
+        id: i32,
+    },
+}
+
+#[derive(Subcommand)]
+enum CollectionCommand {
+    List,
+    /// starts tracking a channel or playlist
+    Add {
+        url: String,
+    },
+    /// stops tracking a collection, its videos are kept
+    Delete {
+        id: i32,
+    },
+    /// runs the tracker for the collection right away
+    Track {
+        id: i32,
+    },
+}
+
+#[derive(Subcommand)]
+enum VideoCommand {
+    /// lists the most recently archived videos
+    List {
+        #[arg(long, value_parser = parse_video_status)]
+        status: Option<VideoStatus>,
+        #[arg(long)]
+        channel: Option<String>,
+        #[arg(long, default_value_t = 100)]
+        limit: i64,
+    },
+    Show {
+        id: i32,
+    },
+    /// deletes the video, its schedules and its files
+    Delete {
+        id: i32,
+        /// schedules the video again, so it is archived from scratch
+        #[arg(long)]
+        reschedule: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum FileCommand {
+    /// shows the file and the videos using it
+    Show { id: uuid::Uuid },
+    /// copies the stored file to output
+    Get {
+        id: uuid::Uuid,
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// compares the size and checksum of stored files with the ones recorded in the database
+    Verify {
+        #[arg(required = true)]
+        ids: Vec<uuid::Uuid>,
+    },
+}
+
+#[derive(Subcommand)]
+enum StorageCommand {
+    /// cross-checks the files table against the storage and reports missing objects, orphaned objects, stale temp files and stuck videos
+    Reconcile {
+        /// deletes orphans and reschedules videos with missing objects, instead of only reporting them
+        #[arg(long)]
+        repair: bool,
+        /// anything younger is ignored, as it may belong to an archival in progress
+        #[arg(long, default_value_t = 60)]
+        stale_after_minutes: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExportCommand {
+    /// writes the catalogue of videos and their files as JSON Lines or CSV
+    Catalogue {
+        /// jsonl or csv
+        #[arg(long, default_value = "jsonl")]
+        format: CatalogueFormat,
+        /// file to write to instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// writes a tar with the metadata, videos, thumbnails and checksums of the archived videos
+    Bundle {
+        #[arg(long)]
+        output: PathBuf,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+}
+
+/// selects the videos to export, all videos are exported if none of these are given
+#[derive(Args)]
+struct FilterArgs {
+    /// may be given multiple times
+    #[arg(long = "video-id")]
+    video_ids: Vec<i32>,
+    #[arg(long)]
+    tracked_collection_id: Option<i32>,
+    #[arg(long)]
+    channel: Option<String>,
+}
+
+impl From<FilterArgs> for ExportFilter {
+    fn from(args: FilterArgs) -> Self {
+        ExportFilter {
+            video_ids: args.video_ids,
+            tracked_collection_id: args.tracked_collection_id,
+            channel: args.channel,
+        }
+    }
+}
+
+fn parse_video_status(s: &str) -> Result<VideoStatus, String> {
+    match s {
+        "archived" => Ok(VideoStatus::Archived),
+        "scheduled_for_archival" => Ok(VideoStatus::ScheduledForArchival),
+        "being_archived" => Ok(VideoStatus::BeingArchived),
+        "archivation_failed" => Ok(VideoStatus::ArchivationFailed),
+        _ => Err(format!(
+            "unknown status {}, expected archived, scheduled_for_archival, being_archived or archivation_failed",
+            s
+        )),
+    }
+}
+
+fn main() -> Result<(), ()> {
+    dotenv().ok();
+    let cli = Cli::parse();
+    let config_file = cli.config.or_else(config::config_file_from_env);
+    let config_file = config_file.as_deref();
+    // logging is set up with this configuration, so problems loading it are printed directly
+    let telemetry_config =
+        load_config::<TelemetryConfig>(config_file).map_err(|e| eprintln!("{}", e))?;
+    let runtime = tokio::runtime::Runtime::new().expect("could not create runtime");
+    // the exporter of spans is set up within the runtime
+    let _runtime_guard = runtime.enter();
+    // logs are written to stderr, so results can be written to stdout
+    telemetry::init(env!("CARGO_PKG_NAME"), &telemetry_config, std::io::stderr);
+
+    let result = match cli.command {
+        Command::Migrate => migrate(config_file),
+        Command::Schedule(command) => runtime.block_on(schedule(command, config_file)),
+        Command::Collection(command) => runtime.block_on(collection(command, config_file)),
+        Command::Video(command) => runtime.block_on(video(command, config_file)),
+        Command::File(command) => runtime.block_on(file(command, config_file)),
+        Command::Storage(command) => runtime.block_on(storage(command, config_file)),
+        Command::Export(command) => runtime.block_on(export(command, config_file)),
+        Command::Import { directory, copy } => {
+            runtime.block_on(import(directory, copy, config_file))
+        }
+    };
+    telemetry::shutdown();
+    result.map_err(|e| error!("{}", e))
+}
+
+fn migrate(config_file: Option<&Path>) -> Result<(), AdminError> {
+    let env_var_config = load_config::<EnvVarConfigCommon>(config_file)?;
+
+    const MAX_ATTEMPTS: usize = 20;
+    const BACKOFF_DURATION_SECONDS: u64 = 10;
+
+    for _i in 0..MAX_ATTEMPTS {
+        let mut connection =
+            match PgConnection::establish(&env_var_config.general_config.database_url) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!(
+                        "Error connecting to Database, retrying in {} seconds. Error was: {}",
+                        BACKOFF_DURATION_SECONDS, e
+                    );
+                    thread::sleep(Duration::from_secs(BACKOFF_DURATION_SECONDS));
+                    continue;
+                }
+            };
+
+        let mut harness = HarnessWithOutput::write_to_stdout(&mut connection);
+        let applied = harness
+            .run_pending_migrations(MIGRATIONS)
+            .map_err(|e| AdminError::Rejected(format!("migration failed: {}", e)))?;
+        info!("{:#?}", applied);
+        return Ok(());
+    }
+    Err(AdminError::Rejected(format!(
+        "stopped retrying after {} attempts with {} seconds between each",
+        MAX_ATTEMPTS, BACKOFF_DURATION_SECONDS
+    )))
+}
+
+/// loads the configuration of a command from the config file and the environment
+fn load_config<T: ServiceConfig>(config_file: Option<&Path>) -> Result<T, AdminError> {
+    config::load(config_file).map_err(AdminError::Config)
+}
+
+/// the database connection of commands which only need the database
+async fn connect(config_file: Option<&Path>) -> Result<AsyncPgConnection, AdminError> {
+    let env_var_config = load_config::<EnvVarConfigCommon>(config_file)?;
+    connect_to(&env_var_config.general_config.database_url).await
+}
+
+async fn connect_to(database_url: &str) -> Result<AsyncPgConnection, AdminError> {
+    AsyncPgConnection::establish(database_url)
+        .await
+        .map_err(|e| AdminError::Rejected(format!("error connecting to database: {}", e)))
+}
+
+/// the configuration, database connection and storage of commands which access the storage
+async fn connect_with_storage(
+    config_file: Option<&Path>,
+) -> Result<(EnvVarConfigAdmin, AsyncPgConnection, Arc<dyn Storage>), AdminError> {
+    let env_var_config = load_config::<EnvVarConfigAdmin>(config_file)?;
+    let db_connection = connect_to(&env_var_config.general_config.database_url).await?;
+    let storage = create_storage(
+        env_var_config.use_s3,
+        &env_var_config.storage_config,
+        &env_var_config.storage_config.s3_internal_url,
+    );
+    Ok((env_var_config, db_connection, storage))
+}
+
+fn print<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string(value).expect("could not serialize result")
+    );
+}
+
+async fn schedule(command: ScheduleCommand, config_file: Option<&Path>) -> Result<(), AdminError> {
+    let db_connection = &mut connect(config_file).await?;
+    match command {
+        ScheduleCommand::List {
+            stuck,
+            older_than_minutes,
+            limit,
+        } => {
+            let stuck_before =
+                stuck.then(|| chrono::Utc::now() - chrono::Duration::minutes(older_than_minutes));
+            for entry in schedules::list_schedules(db_connection, stuck_before, limit).await? {
+                print(&entry);
+            }
+        }
+        ScheduleCommand::Add { url, priority } => {
+            let (url, inserted) = schedules::add_schedule(db_connection, &url, priority).await?;
+            if inserted {
+                info!("Scheduled {}", url);
+            } else {
+                info!("{} is scheduled already", url);
+            }
+        }
+        ScheduleCommand::Requeue { ids, failed } => {
+            let requeued = if failed {
+                schedules::requeue_failed(db_connection).await?
+            } else {
+                schedules::requeue(db_connection, &ids).await?
+            };
+            info!("Requeued {} schedules", requeued);
+        }
+        ScheduleCommand::Priority { id, priority } => {
+            schedules::set_priority(db_connection, id, priority).await?;
+        }
+        ScheduleCommand::Delete { id } => {
+            print(&schedules::delete_schedule(db_connection, id).await?);
+        }
+    }
+    Ok(())
+}
+
+async fn collection(
+    command: CollectionCommand,
+    config_file: Option<&Path>,
+) -> Result<(), AdminError> {
+    match command {
+        CollectionCommand::List => {
+            for collection in
+                collections::list_collections(&mut connect(config_file).await?).await?
+            {
+                print(&collection);
+            }
+        }
+        CollectionCommand::Add { url } => {
+            if collections::add_collection(&mut connect(config_file).await?, &url).await? {
+                info!("Tracking {}", url);
+            } else {
+                info!("{} is tracked already", url);
+            }
+        }
+        CollectionCommand::Delete { id } => {
+            print(&collections::delete_collection(&mut connect(config_file).await?, id).await?);
+        }
+        CollectionCommand::Track { id } => {
+            let env_var_config = load_config::<EnvVarConfigAdmin>(config_file)?;
+            let db_connection =
+                &mut connect_to(&env_var_config.general_config.database_url).await?;
+            let downloader = YtDlpDownloader::new(env_var_config.yt_dlp_config);
+            print(&collections::track_collection(db_connection, &downloader, id).await?);
+        }
+    }
+    Ok(())
+}
+
+async fn video(command: VideoCommand, config_file: Option<&Path>) -> Result<(), AdminError> {
+    match command {
+        VideoCommand::List {
+            status,
+            channel,
+            limit,
+        } => {
+            let db_connection = &mut connect(config_file).await?;
+            for video in
+                videos::list_videos(db_connection, status, channel.as_deref(), limit).await?
+            {
+                print(&video);
+            }
+        }
+        VideoCommand::Show { id } => {
+            print(&videos::get_video(&mut connect(config_file).await?, id).await?);
+        }
+        VideoCommand::Delete { id, reschedule } => {
+            let (_, mut db_connection, storage) = connect_with_storage(config_file).await?;
+            print(
+                &videos::delete_video(&mut db_connection, storage.as_ref(), id, reschedule).await?,
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn file(command: FileCommand, config_file: Option<&Path>) -> Result<(), AdminError> {
+    match command {
+        FileCommand::Show { id } => {
+            print(&files::get_file(&mut connect(config_file).await?, id).await?);
+        }
+        FileCommand::Get { id, output } => {
+            let (_, mut db_connection, storage) = connect_with_storage(config_file).await?;
+            files::download_file(&mut db_connection, storage.as_ref(), id, &output).await?;
+            info!("Wrote file {} to {}", id, output.display());
+        }
+        FileCommand::Verify { ids } => {
+            let (env_var_config, mut db_connection, storage) =
+                connect_with_storage(config_file).await?;
+            let mut invalid = 0;
+            for id in ids {
+                let verification = files::verify_file(
+                    &mut db_connection,
+                    storage.as_ref(),
+                    id,
+                    &env_var_config.storage_config.temp_file_storage_location,
+                )
+                .await?;
+                if !verification.valid {
+                    invalid += 1;
+                }
+                print(&verification);
+            }
+            if invalid > 0 {
+                return Err(AdminError::Rejected(format!(
+                    "{} files don't match their checksum or size",
+                    invalid
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn storage(command: StorageCommand, config_file: Option<&Path>) -> Result<(), AdminError> {
+    match command {
+        StorageCommand::Reconcile {
+            repair,
+            stale_after_minutes,
+        } => {
+            let (env_var_config, mut db_connection, storage) =
+                connect_with_storage(config_file).await?;
+            let report = reconcile(
+                &mut db_connection,
+                storage.as_ref(),
+                &env_var_config.storage_config.temp_file_storage_location,
+                chrono::Duration::minutes(stale_after_minutes),
+                repair,
+            )
+            .await
+            .map_err(|e| AdminError::Rejected(format!("reconciliation failed: {}", e)))?;
+            if report.is_none() {
+                return Err(AdminError::Rejected(
+                    "another process is reconciling at the moment".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn export(command: ExportCommand, config_file: Option<&Path>) -> Result<(), AdminError> {
+    match command {
+        ExportCommand::Catalogue {
+            format,
+            output,
+            filter,
+        } => {
+            let db_connection = &mut connect(config_file).await?;
+            let filter = ExportFilter::from(filter);
+            let written = match output {
+                Some(output) => {
+                    let file = std::fs::File::create(&output)?;
+                    write_catalogue(db_connection, format, &filter, &mut BufWriter::new(file)).await
+                }
+                None => {
+                    write_catalogue(
+                        db_connection,
+                        format,
+                        &filter,
+                        &mut std::io::stdout().lock(),
+                    )
+                    .await
+                }
+            }
+            .map_err(|e| AdminError::Rejected(format!("export of the catalogue failed: {}", e)))?;
+            info!("Exported {} videos", written);
+        }
+        ExportCommand::Bundle { output, filter } => {
+            let (env_var_config, mut db_connection, storage) =
+                connect_with_storage(config_file).await?;
+            let manifest = write_bundle(
+                &mut db_connection,
+                storage.as_ref(),
+                &ExportFilter::from(filter),
+                &output,
+                &env_var_config.storage_config.temp_file_storage_location,
+            )
+            .await
+            .map_err(|e| AdminError::Rejected(format!("export of the bundle failed: {}", e)))?;
+            info!(
+                "Exported {} videos with {} bytes of media to {}",
+                manifest.video_count,
+                manifest.media_size,
+                output.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn import(
+    directory: PathBuf,
+    copy: bool,
+    config_file: Option<&Path>,
+) -> Result<(), AdminError> {
+    let (env_var_config, mut db_connection, storage) = connect_with_storage(config_file).await?;
+    let downloader = YtDlpDownloader::new(env_var_config.yt_dlp_config);
+
+    let summary = import_directory(
+        &mut db_connection,
+        storage.as_ref(),
+        Some(&downloader),
+        &directory,
+        &env_var_config.storage_config.temp_file_storage_location,
+        copy,
+    )
+    .await?;
+    info!(
+        "Imported {} videos from {}, skipped {} which were archived or scheduled already, failed to import {}",
+        summary.imported,
+        directory.display(),
+        summary.skipped,
+        summary.failed
+    );
+    if summary.failed > 0 {
+        return Err(AdminError::Rejected(format!(
+            "failed to import {} videos",
+            summary.failed
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use diesel::{insert_into, update, ExpressionMethods, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::archival_stage::ArchivalStage;
+use immortalis_backend_common::database_models::lease::Lease;
+use immortalis_backend_common::database_models::scheduled_archival::{
+    ScheduledArchival, MANUAL_PRIORITY,
+};
+use immortalis_backend_common::database_models::video_status::VideoStatus;
+use immortalis_backend_common::schema::{leases, scheduled_archivals, videos};
+use immortalis_backend_common::utilities::canonicalize;
+use serde::Serialize;
+
+use crate::AdminError;
+
+/// a schedule along with the lease of the worker archiving it
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleEntry {
+    #[serde(flatten)]
+    pub scheduled_archival: ScheduledArchival,
+    pub lease: Option<Lease>,
+}
+
+/// Lists schedules in the order they are dequeued. If stuck_before is given, only schedules scheduled before it which no worker holds a lease on are listed,
+/// these are waiting for a retry after failed attempts or are starved by schedules of a higher priority
+pub async fn list_schedules(
+    db_connection: &mut AsyncPgConnection,
+    stuck_before: Option<DateTime<Utc>>,
+    limit: i64,
+) -> QueryResult<Vec<ScheduleEntry>> {
+    let mut query = scheduled_archivals::table
+        .order((
+            scheduled_archivals::priority.desc(),
+            scheduled_archivals::scheduled_at,
+            scheduled_archivals::id,
+        ))
+        .limit(limit)
+        .into_boxed();
+    if let Some(stuck_before) = stuck_before {
+        query = query
+            .filter(scheduled_archivals::scheduled_at.lt(stuck_before))
+            .filter(diesel::dsl::not(
+                scheduled_archivals::id.eq_any(leases::table.select(leases::scheduled_archival_id)),
+            ));
+    }
+    let schedules = query.load::<ScheduledArchival>(db_connection).await?;
+
+    let ids: Vec<i32> = schedules.iter().map(|s| s.id).collect();
+    let mut leases: HashMap<i32, Lease> = leases::table
+        .filter(leases::scheduled_archival_id.eq_any(&ids))
+        .load::<Lease>(db_connection)
+        .await?
+        .into_iter()
+        .map(|lease| (lease.scheduled_archival_id, lease))
+        .collect();
+
+    Ok(schedules
+        .into_iter()
+        .map(|scheduled_archival| ScheduleEntry {
+            lease: leases.remove(&scheduled_archival.id),
+            scheduled_archival,
+        })
+        .collect())
+}
+
+/// Schedules the video at url, like POST /schedule does. Returns the url it has been scheduled under and whether a new schedule has been inserted.
+/// An existing schedule with a lower priority is moved up the queue
+pub async fn add_schedule(
+    db_connection: &mut AsyncPgConnection,
+    url: &str,
+    priority: i16,
+) -> Result<(String, bool), AdminError> {
+    let Some(url) = canonicalize(url) else {
+        return Err(AdminError::Rejected(format!("{} is not a video url", url)));
+    };
+
+    let inserted = insert_into(scheduled_archivals::table)
+        .values((
+            scheduled_archivals::url.eq(&url),
+            scheduled_archivals::priority.eq(priority),
+        ))
+        .on_conflict_do_nothing()
+        .execute(db_connection)
+        .await?;
+    if inserted == 0 {
+        update(scheduled_archivals::table)
+            .set(scheduled_archivals::priority.eq(priority))
+            .filter(scheduled_archivals::url.eq(&url))
+            .filter(scheduled_archivals::priority.lt(priority))
+            .execute(db_connection)
+            .await?;
+    }
+    Ok((url, inserted > 0))
+}
+
+/// makes the schedules due right away, with their failed attempts reset. Schedules leased by a worker are skipped, returns the number of requeued schedules
+pub async fn requeue(db_connection: &mut AsyncPgConnection, ids: &[i32]) -> QueryResult<usize> {
+    update(scheduled_archivals::table)
+        .set((
+            scheduled_archivals::not_before.eq(Utc::now()),
+            scheduled_archivals::failed_attempts.eq(0),
+        ))
+        .filter(scheduled_archivals::id.eq_any(ids))
+        .filter(diesel::dsl::not(
+            scheduled_archivals::id.eq_any(leases::table.select(leases::scheduled_archival_id)),
+        ))
+        .execute(db_connection)
+        .await
+}
+
+/// Requeues the archival of every video whose archival failed. Their schedules are made due right away, videos without a schedule are scheduled again,
+/// resuming after their registration. Returns the number of requeued videos
+pub async fn requeue_failed(db_connection: &mut AsyncPgConnection) -> QueryResult<usize> {
+    let failed_videos = videos::table
+        .filter(videos::status.eq(VideoStatus::ArchivationFailed))
+        .select((
+            videos::id,
+            videos::original_url,
+            videos::tracked_collection_id,
+        ))
+        .load::<(i32, String, Option<i32>)>(db_connection)
+        .await?;
+    let urls: Vec<&str> = failed_videos
+        .iter()
+        .map(|(_, url, _)| url.as_str())
+        .collect();
+
+    let scheduled_ids: Vec<i32> = scheduled_archivals::table
+        .filter(scheduled_archivals::url.eq_any(&urls))
+        .select(scheduled_archivals::id)
+        .load::<i32>(db_connection)
+        .await?;
+    let mut requeued = requeue(db_connection, &scheduled_ids).await?;
+
+    let scheduled_urls: HashSet<String> = scheduled_archivals::table
+        .filter(scheduled_archivals::url.eq_any(&urls))
+        .select(scheduled_archivals::url)
+        .load::<String>(db_connection)
+        .await?
+        .into_iter()
+        .collect();
+    for (video_id, url, tracked_collection_id) in &failed_videos {
+        if scheduled_urls.contains(url) {
+            continue;
+        }
+        // the video has been registered already, so the archiver continues with the download
+        requeued += insert_into(scheduled_archivals::table)
+            .values((
+                scheduled_archivals::url.eq(url),
+                scheduled_archivals::video_id.eq(video_id),
+                scheduled_archivals::stage.eq(ArchivalStage::Registered),
+                scheduled_archivals::priority.eq(MANUAL_PRIORITY),
+                scheduled_archivals::tracked_collection_id.eq(tracked_collection_id),
+            ))
+            .on_conflict_do_nothing()
+            .execute(db_connection)
+            .await?;
+    }
+    Ok(requeued)
+}
+
+/// changes the priority of the schedule, higher priorities are archived first
+pub async fn set_priority(
+    db_connection: &mut AsyncPgConnection,
+    id: i32,
+    priority: i16,
+) -> Result<(), AdminError> {
+    let updated = update(scheduled_archivals::table.find(id))
+        .set(scheduled_archivals::priority.eq(priority))
+        .execute(db_connection)
+        .await?;
+    if updated == 0 {
+        return Err(AdminError::NotFound(format!("schedule {}", id)));
+    }
+    Ok(())
+}
+
+/// deletes the schedule, unless a worker holds a lease on it
+pub async fn delete_schedule(
+    db_connection: &mut AsyncPgConnection,
+    id: i32,
+) -> Result<ScheduledArchival, AdminError> {
+    let deleted = diesel::delete(scheduled_archivals::table)
+        .filter(scheduled_archivals::id.eq(id))
+        .filter(diesel::dsl::not(
+            scheduled_archivals::id.eq_any(leases::table.select(leases::scheduled_archival_id)),
+        ))
+        .get_result::<ScheduledArchival>(db_connection)
+        .await;
+    match deleted {
+        Ok(scheduled_archival) => Ok(scheduled_archival),
+        Err(diesel::result::Error::NotFound) => {
+            let exists = scheduled_archivals::table
+                .find(id)
+                .count()
+                .get_result::<i64>(db_connection)
+                .await?
+                > 0;
+            Err(if exists {
+                AdminError::Rejected(format!("schedule {} is being archived", id))
+            } else {
+                AdminError::NotFound(format!("schedule {}", id))
+            })
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,142 @@
+use diesel::{
+    insert_into, BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult,
+};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::file::File;
+use immortalis_backend_common::database_models::scheduled_archival::MANUAL_PRIORITY;
+use immortalis_backend_common::database_models::video::Video;
+use immortalis_backend_common::database_models::video_status::VideoStatus;
+use immortalis_backend_common::schema::{files, leases, scheduled_archivals, videos};
+use immortalis_backend_common::storage::content_addressed;
+use immortalis_backend_common::storage::Storage;
+use tracing::info;
+
+use crate::AdminError;
+
+/// lists the most recently archived videos matching the filters
+pub async fn list_videos(
+    db_connection: &mut AsyncPgConnection,
+    status: Option<VideoStatus>,
+    channel: Option<&str>,
+    limit: i64,
+) -> QueryResult<Vec<Video>> {
+    let mut query = videos::table
+        .order(videos::archived_date.desc())
+        .limit(limit)
+        .into_boxed();
+    if let Some(status) = status {
+        query = query.filter(videos::status.eq(status));
+    }
+    if let Some(channel) = channel {
+        query = query.filter(videos::channel.eq(channel.to_string()));
+    }
+    query.load::<Video>(db_connection).await
+}
+
+pub async fn get_video(
+    db_connection: &mut AsyncPgConnection,
+    id: i32,
+) -> Result<Video, AdminError> {
+    videos::table
+        .find(id)
+        .first::<Video>(db_connection)
+        .await
+        .optional()?
+        .ok_or_else(|| AdminError::NotFound(format!("video {}", id)))
+}
+
+/// Deletes the video, its schedules and its files, releasing the stored objects no other file references.
+/// If reschedule is set, the video is scheduled again afterwards so it is archived from scratch.
+/// Videos which are being archived at the moment are rejected
+pub async fn delete_video(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    id: i32,
+    reschedule: bool,
+) -> Result<Video, AdminError> {
+    let (video, deleted_files) = db_connection
+        .transaction::<(Video, Vec<File>), AdminError, _>(|db_connection| {
+            async move {
+                let video = get_video(db_connection, id).await?;
+
+                // the schedules stay locked until the transaction ends, so no worker can lease them after the check
+                let schedule_ids = scheduled_archivals::table
+                    .filter(
+                        scheduled_archivals::video_id
+                            .eq(video.id)
+                            .or(scheduled_archivals::url.eq(&video.original_url)),
+                    )
+                    .select(scheduled_archivals::id)
+                    .for_update()
+                    .load::<i32>(db_connection)
+                    .await?;
+                let leased = leases::table
+                    .filter(leases::scheduled_archival_id.eq_any(&schedule_ids))
+                    .count()
+                    .get_result::<i64>(db_connection)
+                    .await?;
+                if leased > 0 {
+                    return Err(AdminError::Rejected(format!(
+                        "video {} is being archived",
+                        video.id
+                    )));
+                }
+                diesel::delete(
+                    scheduled_archivals::table
+                        .filter(scheduled_archivals::id.eq_any(&schedule_ids)),
+                )
+                .execute(db_connection)
+                .await?;
+                diesel::delete(videos::table.find(video.id))
+                    .execute(db_connection)
+                    .await?;
+
+                // the thumbnail may share the file of the video
+                let mut file_ids = vec![video.file_id, video.thumbnail_id];
+                file_ids.dedup();
+                let deleted_files =
+                    diesel::delete(files::table.filter(files::id.eq_any(&file_ids)))
+                        .get_results::<File>(db_connection)
+                        .await?;
+
+                if reschedule {
+                    insert_into(scheduled_archivals::table)
+                        .values((
+                            scheduled_archivals::url.eq(&video.original_url),
+                            scheduled_archivals::priority.eq(MANUAL_PRIORITY),
+                            scheduled_archivals::tracked_collection_id
+                                .eq(video.tracked_collection_id),
+                        ))
+                        .on_conflict_do_nothing()
+                        .execute(db_connection)
+                        .await?;
+                }
+                Ok((video, deleted_files))
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    // the blobs are only released once the rows referencing them are gone for good
+    for file in deleted_files {
+        match &file.checksum {
+            Some(checksum) => {
+                content_addressed::release_blob(db_connection, storage, checksum).await?
+            }
+            None => storage.delete(&file.storage_key()).await?,
+        }
+    }
+
+    info!(
+        "Deleted video {} {}{}",
+        video.id,
+        video.original_url,
+        if reschedule {
+            " and scheduled it again"
+        } else {
+            ""
+        }
+    );
+    Ok(video)
+}
@@ -0,0 +1,83 @@
+use chrono::{Duration, Utc};
+use diesel::{insert_into, ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use immortalis_admin::{schedules, videos};
+use immortalis_backend_common::database_models::archival_stage::ArchivalStage;
+use immortalis_backend_common::database_models::scheduled_archival::ScheduledArchival;
+use immortalis_backend_common::database_models::video_status::VideoStatus;
+use immortalis_backend_common::schema::{files, scheduled_archivals};
+use immortalis_backend_test_support::database::{insert_video, TestDatabase};
+use immortalis_backend_test_support::memory_storage::InMemoryStorage;
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_requeue_failed() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let retried_url = "https://www.youtube.com/watch?v=retried";
+    let unscheduled_url = "https://www.youtube.com/watch?v=unscheduled";
+    insert_video(db_connection, retried_url, VideoStatus::ArchivationFailed).await;
+    let unscheduled_id = insert_video(
+        db_connection,
+        unscheduled_url,
+        VideoStatus::ArchivationFailed,
+    )
+    .await;
+    insert_video(
+        db_connection,
+        "https://www.youtube.com/watch?v=archived",
+        VideoStatus::Archived,
+    )
+    .await;
+    // waiting for the processing timeout of the failed attempt
+    insert_into(scheduled_archivals::table)
+        .values((
+            scheduled_archivals::url.eq(retried_url),
+            scheduled_archivals::not_before.eq(Utc::now() + Duration::hours(1)),
+        ))
+        .execute(db_connection)
+        .await
+        .unwrap();
+
+    assert_eq!(schedules::requeue_failed(db_connection).await.unwrap(), 2);
+
+    let schedules = scheduled_archivals::table
+        .order(scheduled_archivals::url)
+        .load::<ScheduledArchival>(db_connection)
+        .await
+        .unwrap();
+    assert_eq!(schedules.len(), 2);
+    assert!(schedules.iter().all(|s| s.not_before <= Utc::now()));
+    assert_eq!(schedules[1].url, unscheduled_url);
+    assert_eq!(schedules[1].video_id, Some(unscheduled_id));
+    assert_eq!(schedules[1].stage, ArchivalStage::Registered);
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_delete_video() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let storage = InMemoryStorage::new();
+    let url = "https://www.youtube.com/watch?v=deleted";
+    let video_id = insert_video(db_connection, url, VideoStatus::Archived).await;
+
+    let video = videos::delete_video(db_connection, &storage, video_id, true)
+        .await
+        .unwrap();
+
+    assert!(videos::get_video(db_connection, video_id).await.is_err());
+    let file_count: i64 = files::table
+        .find(video.file_id)
+        .count()
+        .get_result(db_connection)
+        .await
+        .unwrap();
+    assert_eq!(file_count, 0);
+    let rescheduled: Vec<String> = scheduled_archivals::table
+        .select(scheduled_archivals::url)
+        .load(db_connection)
+        .await
+        .unwrap();
+    assert_eq!(rescheduled, vec![url]);
+}
@@ -0,0 +1,460 @@
+use std::collections::HashSet;
+
+use immortalis_backend_common::utilities::{canonicalize, get_url_type, UrlType};
+use serde::Serialize;
+
+/// bodies of bulk imports may be large exports, like the watch history of Google Takeout
+pub const BULK_IMPORT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// an entry read from the body of a bulk import
+#[derive(Debug, PartialEq, Eq)]
+pub struct BulkEntry {
+    /// 1-based line of the body, or position within a JSON array or among the video links of an HTML export
+    pub line: usize,
+    pub input: String,
+    /// the url the entry refers to, before validation
+    pub url: String,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkScheduleResult {
+    Accepted,
+    /// already archived, scheduled, or contained in the import more than once
+    Duplicate,
+    Invalid,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkScheduleLine {
+    pub line: usize,
+    pub input: String,
+    /// the canonicalized url, unless the entry is invalid
+    pub url: Option<String>,
+    pub result: BulkScheduleResult,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkScheduleResponse {
+    pub accepted: usize,
+    pub duplicate: usize,
+    pub invalid: usize,
+    pub lines: Vec<BulkScheduleLine>,
+}
+
+impl BulkScheduleResponse {
+    /// urls of the accepted entries, which are to be scheduled
+    pub fn accepted_urls(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| line.result == BulkScheduleResult::Accepted)
+            .filter_map(|line| line.url.as_deref())
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum BulkImportError {
+    /// the content type, or that of the uploaded file, can't be imported
+    UnsupportedContentType(String),
+    InvalidUtf8,
+    InvalidJson(serde_json::Error),
+    InvalidMultipart,
+}
+
+impl std::fmt::Display for BulkImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BulkImportError::UnsupportedContentType(content_type) => {
+                write!(f, "unsupported content type: {}", content_type)
+            }
+            BulkImportError::InvalidUtf8 => write!(f, "the body is not valid UTF-8"),
+            BulkImportError::InvalidJson(e) => write!(f, "invalid JSON: {}", e),
+            BulkImportError::InvalidMultipart => {
+                write!(f, "the multipart body doesn't contain a file")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BulkImportError {}
+
+impl From<serde_json::Error> for BulkImportError {
+    fn from(e: serde_json::Error) -> Self {
+        BulkImportError::InvalidJson(e)
+    }
+}
+
+/// Reads the entries of a bulk import body according to its content type. A multipart/form-data body is read from the uploaded file,
+/// an HTML body, like the watch history of Google Takeout exported as HTML, from its links to videos. Other text bodies are read by parse_entries
+pub fn parse_body(
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<Vec<BulkEntry>, BulkImportError> {
+    let body = std::str::from_utf8(body).map_err(|_| BulkImportError::InvalidUtf8)?;
+    let (essence, boundary) = parse_content_type(content_type.unwrap_or("text/plain"));
+
+    if essence == "multipart/form-data" {
+        let boundary = boundary.ok_or(BulkImportError::InvalidMultipart)?;
+        let (file_content_type, file) =
+            multipart_file(body, &boundary).ok_or(BulkImportError::InvalidMultipart)?;
+        let (file_essence, _) = parse_content_type(file_content_type.unwrap_or("text/plain"));
+        // browsers send files of unknown type as application/octet-stream, so the content decides
+        if file_essence == "application/octet-stream" && file.trim_start().starts_with('<') {
+            return Ok(parse_html(file));
+        }
+        return parse_text(&file_essence, file);
+    }
+
+    parse_text(&essence, body)
+}
+
+fn parse_text(essence: &str, body: &str) -> Result<Vec<BulkEntry>, BulkImportError> {
+    match essence {
+        "text/html" => Ok(parse_html(body)),
+        "text/plain" | "text/csv" | "application/json" | "application/octet-stream" => {
+            Ok(parse_entries(body)?)
+        }
+        _ => Err(BulkImportError::UnsupportedContentType(essence.to_string())),
+    }
+}
+
+/// returns the lowercase media type and the boundary parameter of a content type
+fn parse_content_type(content_type: &str) -> (String, Option<String>) {
+    let mut parameters = content_type.split(';');
+    let essence = parameters.next().unwrap_or_default().trim().to_lowercase();
+    let boundary = parameters
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string());
+    (essence, boundary)
+}
+
+/// returns the content type and content of the first uploaded file of a multipart body, or of its first part if it contains no file
+fn multipart_file<'a>(body: &'a str, boundary: &str) -> Option<(Option<&'a str>, &'a str)> {
+    let delimiter = format!("--{}", boundary);
+    let parts: Vec<(&str, &str)> = body
+        .split(delimiter.as_str())
+        .skip(1)
+        .take_while(|part| !part.starts_with("--"))
+        .filter_map(|part| {
+            let (headers, content) = part.strip_prefix("\r\n")?.split_once("\r\n\r\n")?;
+            Some((headers, content.strip_suffix("\r\n").unwrap_or(content)))
+        })
+        .collect();
+
+    let header = |headers: &'a str, name: &str| {
+        headers.split("\r\n").find_map(|line| {
+            let (header_name, value) = line.split_once(':')?;
+            header_name
+                .trim()
+                .eq_ignore_ascii_case(name)
+                .then_some(value.trim())
+        })
+    };
+    let &(headers, content) = parts
+        .iter()
+        .find(|&&(headers, _)| {
+            header(headers, "content-disposition")
+                .map_or(false, |disposition| disposition.contains("filename="))
+        })
+        .or_else(|| parts.first())?;
+    Some((header(headers, "content-type"), content))
+}
+
+/// reads the links to videos of an HTML body, other links, like those to the channels in the Takeout watch history, are skipped
+fn parse_html(body: &str) -> Vec<BulkEntry> {
+    body.split("href=\"")
+        .skip(1)
+        .filter_map(|rest| rest.split_once('"'))
+        .map(|(href, _)| href.replace("&amp;", "&"))
+        .filter(|url| {
+            matches!(
+                get_url_type(url),
+                UrlType::Video | UrlType::VideoOrCollection
+            )
+        })
+        .enumerate()
+        .map(|(index, url)| BulkEntry {
+            line: index + 1,
+            input: url.clone(),
+            url,
+        })
+        .collect()
+}
+
+/// Reads the entries of a bulk import. A body starting with [ is read as a JSON array of urls or of objects with a url or titleUrl (Google Takeout watch history).
+/// Anything else is read line by line, where each line is a url, an entry of a yt-dlp --download-archive file or a row of a Google Takeout playlist export.
+/// Empty lines, lines starting with # and the header rows of Takeout exports are skipped
+pub fn parse_entries(body: &str) -> Result<Vec<BulkEntry>, serde_json::Error> {
+    let body = body.trim_start_matches('\u{feff}');
+
+    if body.trim_start().starts_with('[') {
+        let values: Vec<serde_json::Value> = serde_json::from_str(body)?;
+        return Ok(values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let url = match &value {
+                    serde_json::Value::String(url) => url.clone(),
+                    serde_json::Value::Object(entry) => entry
+                        .get("url")
+                        .or_else(|| entry.get("titleUrl"))
+                        .and_then(|url| url.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                    _ => String::new(),
+                };
+                BulkEntry {
+                    line: index + 1,
+                    input: match value {
+                        serde_json::Value::String(input) => input,
+                        value => value.to_string(),
+                    },
+                    url,
+                }
+            })
+            .collect());
+    }
+
+    Ok(body
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            parse_line(line).map(|url| BulkEntry {
+                line: index + 1,
+                input: line.trim().to_string(),
+                url,
+            })
+        })
+        .collect())
+}
+
+fn parse_line(line: &str) -> Option<String> {
+    let line = line.trim();
+    let lowercase = line.to_lowercase();
+    if line.is_empty()
+        || line.starts_with('#')
+        || lowercase.starts_with("video id")
+        || lowercase.starts_with("playlist id")
+    {
+        return None;
+    }
+
+    // yt-dlp writes "<extractor> <id>" to its download archive
+    if let Some(video_id) = line.strip_prefix("youtube ") {
+        return Some(watch_url(video_id.trim()));
+    }
+
+    // rows of Takeout playlist exports start with the video id, followed by when it was added
+    if let Some((video_id, _)) = line.split_once(',') {
+        if is_video_id(video_id.trim()) {
+            return Some(watch_url(video_id.trim()));
+        }
+    }
+
+    Some(line.to_string())
+}
+
+fn watch_url(video_id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={}", video_id)
+}
+
+fn is_video_id(value: &str) -> bool {
+    value.len() == 11
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Validates and canonicalizes the entries. Entries whose url is in known_urls, or which occured earlier in the import, are duplicates
+pub fn classify(entries: Vec<BulkEntry>, known_urls: &HashSet<String>) -> BulkScheduleResponse {
+    let mut seen = HashSet::new();
+    let lines: Vec<BulkScheduleLine> = entries
+        .into_iter()
+        .map(|entry| {
+            let url = canonicalize(&entry.url);
+            let result = match &url {
+                None => BulkScheduleResult::Invalid,
+                Some(url) if known_urls.contains(url) || !seen.insert(url.clone()) => {
+                    BulkScheduleResult::Duplicate
+                }
+                Some(_) => BulkScheduleResult::Accepted,
+            };
+            BulkScheduleLine {
+                line: entry.line,
+                input: entry.input,
+                url,
+                result,
+            }
+        })
+        .collect();
+
+    let count = |result| lines.iter().filter(|line| line.result == result).count();
+    BulkScheduleResponse {
+        accepted: count(BulkScheduleResult::Accepted),
+        duplicate: count(BulkScheduleResult::Duplicate),
+        invalid: count(BulkScheduleResult::Invalid),
+        lines,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{classify, parse_body, parse_entries, BulkImportError, BulkScheduleResult};
+
+    fn urls(body: &str) -> Vec<String> {
+        parse_entries(body)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.url)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_text() {
+        assert_eq!(
+            urls("https://www.youtube.com/watch?v=first\n\n# comment\n  https://www.youtube.com/watch?v=second  \n"),
+            vec![
+                "https://www.youtube.com/watch?v=first",
+                "https://www.youtube.com/watch?v=second"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_download_archive() {
+        assert_eq!(
+            urls("youtube dQw4w9WgXcQ\nvimeo 12345\n"),
+            vec!["https://www.youtube.com/watch?v=dQw4w9WgXcQ", "vimeo 12345"]
+        );
+    }
+
+    #[test]
+    fn test_parse_takeout_playlist() {
+        assert_eq!(
+            urls("Video ID,Playlist Video Creation Timestamp\r\ndQw4w9WgXcQ,2023-01-01T00:00:00+00:00\r\n"),
+            vec!["https://www.youtube.com/watch?v=dQw4w9WgXcQ"]
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let entries = parse_entries(
+            r#"[
+                "https://www.youtube.com/watch?v=first",
+                {"url": "https://www.youtube.com/watch?v=second"},
+                {"header": "YouTube", "titleUrl": "https://www.youtube.com/watch?v=third"},
+                {"header": "YouTube", "title": "Watched a video that has been removed"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.url.as_str()).collect::<Vec<_>>(),
+            vec![
+                "https://www.youtube.com/watch?v=first",
+                "https://www.youtube.com/watch?v=second",
+                "https://www.youtube.com/watch?v=third",
+                ""
+            ]
+        );
+        assert_eq!(entries[3].line, 4);
+        assert!(parse_entries("[").is_err());
+    }
+
+    #[test]
+    fn test_parse_takeout_html() {
+        let body = r#"<html><body><div class="content-cell">Watched <a href="https://www.youtube.com/watch?v=dQw4w9WgXcQ">Title</a><br><a href="https://www.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw">Channel</a><br></div>
+            <div class="content-cell">Watched <a href="https://www.youtube.com/watch?v=first&amp;list=PL1">Title</a><br></div></body></html>"#;
+        let entries = parse_body(Some("text/html; charset=utf-8"), body.as_bytes()).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.url.as_str()).collect::<Vec<_>>(),
+            vec![
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+                "https://www.youtube.com/watch?v=first&list=PL1"
+            ]
+        );
+        assert_eq!(entries[1].line, 2);
+    }
+
+    #[test]
+    fn test_parse_multipart() {
+        let body = "--boundary\r\nContent-Disposition: form-data; name=\"priority\"\r\n\r\n10\r\n--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"watch-history.html\"\r\nContent-Type: application/octet-stream\r\n\r\n<a href=\"https://www.youtube.com/watch?v=dQw4w9WgXcQ\">Title</a>\r\n--boundary--\r\n";
+        assert_eq!(
+            parse_body(
+                Some("multipart/form-data; boundary=boundary"),
+                body.as_bytes()
+            )
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.url)
+            .collect::<Vec<_>>(),
+            vec!["https://www.youtube.com/watch?v=dQw4w9WgXcQ"]
+        );
+
+        let body = "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"archive.txt\"\r\nContent-Type: text/plain\r\n\r\nyoutube dQw4w9WgXcQ\nyoutube first\r\n--boundary--\r\n";
+        assert_eq!(
+            parse_body(
+                Some("multipart/form-data; boundary=\"boundary\""),
+                body.as_bytes()
+            )
+            .unwrap()
+            .len(),
+            2
+        );
+        assert!(matches!(
+            parse_body(Some("multipart/form-data"), body.as_bytes()),
+            Err(BulkImportError::InvalidMultipart)
+        ));
+    }
+
+    #[test]
+    fn test_parse_unsupported_content_type() {
+        assert!(matches!(
+            parse_body(Some("application/zip"), b"PK"),
+            Err(BulkImportError::UnsupportedContentType(content_type)) if content_type == "application/zip"
+        ));
+        assert_eq!(
+            parse_body(None, b"https://www.youtube.com/watch?v=first")
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        let entries = parse_entries(
+            "https://www.youtube.com/watch?v=first&t=10\nhttps://www.youtube.com/watch?v=first\nhttps://www.youtube.com/watch?v=archived\nhttps://example.com\n",
+        )
+        .unwrap();
+        let known_urls = HashSet::from(["https://www.youtube.com/watch?v=archived".to_string()]);
+
+        let response = classify(entries, &known_urls);
+        assert_eq!(
+            response
+                .lines
+                .iter()
+                .map(|line| line.result)
+                .collect::<Vec<_>>(),
+            vec![
+                BulkScheduleResult::Accepted,
+                BulkScheduleResult::Duplicate,
+                BulkScheduleResult::Duplicate,
+                BulkScheduleResult::Invalid
+            ]
+        );
+        assert_eq!(
+            (response.accepted, response.duplicate, response.invalid),
+            (1, 2, 1)
+        );
+        assert_eq!(
+            response.accepted_urls(),
+            vec!["https://www.youtube.com/watch?v=first"]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use futures::Stream;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::subscriptions::PublishedEvent;
+
+/// Distributes the events of postgres to the websocket and server-sent event connections. Publishing never waits for a connection,
+/// every connection has a queue of its own which holds at most queue_size events
+#[derive(Clone)]
+pub struct EventHub {
+    sender: broadcast::Sender<Arc<PublishedEvent>>,
+}
+
+impl EventHub {
+    pub fn new(queue_size: usize) -> EventHub {
+        // a broadcast channel can't be created without capacity
+        let (sender, _) = broadcast::channel(queue_size.max(1));
+        EventHub { sender }
+    }
+
+    /// returns the number of connections the event has been queued for
+    pub fn publish(&self, event: PublishedEvent) -> usize {
+        // sending only fails if no connection is open
+        self.sender.send(Arc::new(event)).unwrap_or(0)
+    }
+
+    /// The events published from now on. Yields RecvError::Lagged with the number of missed events
+    /// if the consumer fell more than queue_size events behind, ends when the hub is dropped
+    pub fn subscribe(&self) -> impl Stream<Item = Result<Arc<PublishedEvent>, RecvError>> {
+        futures::stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            match receiver.recv().await {
+                Err(RecvError::Closed) => None,
+                result => Some((result, receiver)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::StreamExt;
+    use immortalis_backend_common::database_models::worker::Worker;
+    use tokio::sync::broadcast::error::RecvError;
+
+    use super::EventHub;
+    use crate::subscriptions::{ChannelEvent, PostgresEvent, PublishedEvent};
+
+    fn event(id: &str) -> PublishedEvent {
+        let event = ChannelEvent::Worker(PostgresEvent {
+            action: "update".to_string(),
+            record: serde_json::from_value::<Worker>(serde_json::json!({
+                "id": id,
+                "host": "host",
+                "registered_at": "2023-08-13T10:00:00Z",
+                "last_heartbeat": "2023-08-13T10:00:00Z",
+                "kind": "archiver",
+                "version": "0.1.0",
+                "current_job": null,
+                "job_started_at": null
+            }))
+            .unwrap(),
+            old_record: None,
+        });
+        PublishedEvent { id: 1, event }
+    }
+
+    fn worker_id(event: &Arc<PublishedEvent>) -> String {
+        match &event.event {
+            ChannelEvent::Worker(event) => event.record.id.to_string(),
+            _ => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_slow_consumer_lags() {
+        let hub = EventHub::new(2);
+        assert_eq!(
+            hub.publish(event("00000000-0000-0000-0000-000000000000")),
+            0
+        );
+
+        let mut fast = Box::pin(hub.subscribe());
+        let mut slow = Box::pin(hub.subscribe());
+        let ids = [
+            "00000000-0000-0000-0000-000000000001",
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+        ];
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(hub.publish(event(id)), 2);
+            assert_eq!(&worker_id(&fast.next().await.unwrap().unwrap()), ids[i]);
+        }
+
+        // the queue of the slow consumer holds the two latest events only
+        assert!(matches!(slow.next().await, Some(Err(RecvError::Lagged(1)))));
+        assert_eq!(&worker_id(&slow.next().await.unwrap().unwrap()), ids[1]);
+        assert_eq!(&worker_id(&slow.next().await.unwrap().unwrap()), ids[2]);
+
+        drop(hub);
+        assert!(fast.next().await.is_none());
+    }
+}
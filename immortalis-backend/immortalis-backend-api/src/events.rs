@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use diesel::{ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::data_transfer_models::video_dto::VideoDto;
+use immortalis_backend_common::database_models::event::{
+    load_events_after, oldest_event_id, Event,
+};
+use immortalis_backend_common::database_models::video::Video;
+use immortalis_backend_common::schema::{events, files, videos};
+use tracing::{error, warn};
+
+use crate::subscriptions::{
+    Channel, ChannelEvent, FileNotification, PublishedEvent, VideoEvent, VideoNotification,
+};
+
+/// events replayed at most, a client which missed more has to reload
+pub const REPLAY_LIMIT: i64 = 1000;
+
+/// the stored event with the id
+pub async fn load_event(conn: &mut AsyncPgConnection, id: i64) -> diesel::QueryResult<Event> {
+    events::table.find(id).first::<Event>(conn).await
+}
+
+/// Builds the events sent to clients from a stored event. Returns no events if the stored event can't be handled
+pub async fn load_channel_events(conn: &mut AsyncPgConnection, event: &Event) -> Vec<ChannelEvent> {
+    match event.channel.as_str() {
+        "videos" | "files" => load_video_events(conn, &event.channel, &event.payload).await,
+        channel => {
+            let Some(channel) = Channel::from_postgres_channel(channel) else {
+                warn!("received event on channel {} without handler", channel);
+                return vec![];
+            };
+            match ChannelEvent::parse(channel, &event.payload) {
+                Ok(channel_event) => vec![channel_event],
+                Err(e) => {
+                    error!(
+                        "Failed to parse event {} on channel {}, encountered error {}",
+                        event.id,
+                        channel.name(),
+                        e
+                    );
+                    vec![]
+                }
+            }
+        }
+    }
+}
+
+/// the events to send to a client resuming after an event
+pub struct Replay {
+    pub events: Vec<Arc<PublishedEvent>>,
+    /// false if events have been pruned since or there are more than REPLAY_LIMIT, the client has to reload then
+    pub complete: bool,
+}
+
+/// Loads the events of the channels stored after after_id. Videos are sent as they are now, not as they were at the time of the event
+pub async fn replay(
+    conn: &mut AsyncPgConnection,
+    after_id: i64,
+    channels: &[Channel],
+) -> diesel::QueryResult<Replay> {
+    let postgres_channels: Vec<&str> = channels
+        .iter()
+        .flat_map(|channel| channel.postgres_channels())
+        .copied()
+        .collect();
+    let stored = load_events_after(conn, after_id, &postgres_channels, REPLAY_LIMIT).await?;
+    let pruned = matches!(oldest_event_id(conn).await?, Some(oldest) if oldest > after_id + 1);
+
+    let mut events = vec![];
+    for event in &stored {
+        for channel_event in load_channel_events(conn, event).await {
+            events.push(Arc::new(PublishedEvent {
+                id: event.id,
+                event: channel_event,
+            }));
+        }
+    }
+    Ok(Replay {
+        events,
+        complete: !pruned && (stored.len() as i64) < REPLAY_LIMIT,
+    })
+}
+
+/// Loads the videos an event of the videos or files channel is about, as these events only carry ids.
+/// A changed file size is sent as an update of every video stored in the file
+async fn load_video_events(
+    conn: &mut AsyncPgConnection,
+    channel: &str,
+    payload: &str,
+) -> Vec<ChannelEvent> {
+    let notifications = if channel == "files" {
+        let file = match serde_json::from_str::<FileNotification>(payload) {
+            Ok(file) => file,
+            Err(e) => {
+                error!(
+                    "Failed to parse event on channel {}, encountered error {}",
+                    channel, e
+                );
+                return vec![];
+            }
+        };
+        match videos::table
+            .filter(videos::file_id.eq(file.id))
+            .select((videos::id, videos::tracked_collection_id))
+            .load::<(i32, Option<i32>)>(conn)
+            .await
+        {
+            Ok(videos) => videos
+                .into_iter()
+                .map(|(id, tracked_collection_id)| VideoNotification {
+                    action: "update".to_string(),
+                    id,
+                    tracked_collection_id,
+                    status_changed: false,
+                })
+                .collect(),
+            Err(e) => {
+                error!(
+                    "Failed to load videos of file {}, encountered error {}",
+                    file.id, e
+                );
+                return vec![];
+            }
+        }
+    } else {
+        match serde_json::from_str::<VideoNotification>(payload) {
+            Ok(video) => vec![video],
+            Err(e) => {
+                error!(
+                    "Failed to parse event on channel {}, encountered error {}",
+                    channel, e
+                );
+                return vec![];
+            }
+        }
+    };
+
+    let mut events = vec![];
+    for notification in notifications {
+        let record = if notification.action == "delete" {
+            None
+        } else {
+            match load_video_dto(conn, notification.id).await {
+                Ok(Some(video)) => Some(video),
+                // deleted in the meantime, its deletion is sent on its own
+                Ok(None) => continue,
+                Err(e) => {
+                    error!(
+                        "Failed to load video {}, encountered error {}",
+                        notification.id, e
+                    );
+                    continue;
+                }
+            }
+        };
+        events.push(ChannelEvent::Video(VideoEvent {
+            action: notification.action,
+            id: notification.id,
+            record,
+            tracked_collection_id: notification.tracked_collection_id,
+            status_changed: notification.status_changed,
+        }));
+    }
+    events
+}
+
+/// the video along with its size, like /search returns it
+async fn load_video_dto(
+    conn: &mut AsyncPgConnection,
+    video_id: i32,
+) -> diesel::QueryResult<Option<VideoDto>> {
+    videos::table
+        .inner_join(files::table.on(files::id.eq(videos::file_id)))
+        .filter(videos::id.eq(video_id))
+        .select((Video::as_select(), files::size))
+        .first::<(Video, i64)>(conn)
+        .await
+        .optional()
+        .map(|video| video.map(|(video, video_size)| VideoDto { video, video_size }))
+}
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use actix_http::header::CACHE_CONTROL;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::file::File;
+use immortalis_backend_common::database_models::video::Video;
+use immortalis_backend_common::database_models::video_status::VideoStatus;
+use immortalis_backend_common::env_var_config::EnvVarConfigApi;
+use immortalis_backend_common::export::ExportFilter;
+use immortalis_backend_common::schema::{files, tracked_collections, videos};
+use serde_json::json;
+
+use crate::request_models::FeedQuery;
+use crate::AppState;
+
+const FEED_LIMIT_DEFAULT: i64 = 50;
+const FEED_LIMIT_MAX: i64 = 500;
+/// feed readers poll frequently, new archivals show up after at most this long
+const FEED_CACHE_SECONDS: u32 = 300;
+/// extensions of the files podcast feeds list, podcatchers can't play videos
+const AUDIO_EXTENSIONS: [&str; 8] = ["m4a", "mp3", "opus", "ogg", "oga", "aac", "flac", "wav"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Atom,
+    /// JSON Feed 1.1
+    Json,
+    /// RSS 2.0 with the iTunes extensions podcatchers expect, only listing audio files
+    Podcast,
+}
+
+impl FromStr for FeedFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "atom" => Ok(FeedFormat::Atom),
+            "json" => Ok(FeedFormat::Json),
+            "podcast" => Ok(FeedFormat::Podcast),
+            _ => Err(format!(
+                "unknown format {}, expected atom, json or podcast",
+                s
+            )),
+        }
+    }
+}
+
+impl FeedFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+            FeedFormat::Json => "application/feed+json; charset=utf-8",
+            FeedFormat::Podcast => "application/rss+xml; charset=utf-8",
+        }
+    }
+}
+
+/// an archived video along with its files, as listed in feeds
+pub struct FeedEntry {
+    pub video: Video,
+    pub file: File,
+    pub thumbnail: Option<File>,
+}
+
+/// the feed along with the absolute urls its links are built from
+pub struct Feed {
+    pub title: String,
+    /// the url of the feed itself
+    pub feed_url: String,
+    /// the url the api is reachable at, file links point to its /file endpoint
+    pub base_url: String,
+    pub entries: Vec<FeedEntry>,
+}
+
+/// the newest archived videos of all channels and collections
+#[get("/feed")]
+pub async fn feed(
+    req: HttpRequest,
+    query: web::Query<FeedQuery>,
+    app_state: web::Data<AppState>,
+) -> HttpResponse {
+    respond(
+        &req,
+        &query,
+        &app_state,
+        "immortalis".to_string(),
+        ExportFilter::default(),
+    )
+    .await
+}
+
+/// the newest archived videos of a channel, by its name
+#[get("/feed/channel/{channel}")]
+pub async fn channel_feed(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<FeedQuery>,
+    app_state: web::Data<AppState>,
+) -> HttpResponse {
+    let channel = path.into_inner();
+    respond(
+        &req,
+        &query,
+        &app_state,
+        format!("immortalis: {}", channel),
+        ExportFilter {
+            channel: Some(channel),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// the newest archived videos the tracker discovered in a collection
+#[get("/feed/tracked_collection/{id}")]
+pub async fn tracked_collection_feed(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<FeedQuery>,
+    app_state: web::Data<AppState>,
+) -> HttpResponse {
+    let tracked_collection_id = path.into_inner();
+    let url = tracked_collections::table
+        .find(tracked_collection_id)
+        .select(tracked_collections::url)
+        .first::<String>(&mut app_state.db_connection_pool.get().await.unwrap())
+        .await
+        .optional()
+        .unwrap();
+    let Some(url) = url else {
+        return HttpResponse::NotFound().finish();
+    };
+    respond(
+        &req,
+        &query,
+        &app_state,
+        format!("immortalis: {}", url),
+        ExportFilter {
+            tracked_collection_id: Some(tracked_collection_id),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+async fn respond(
+    req: &HttpRequest,
+    query: &FeedQuery,
+    app_state: &AppState,
+    title: String,
+    filter: ExportFilter,
+) -> HttpResponse {
+    let format = match query
+        .format
+        .as_deref()
+        .unwrap_or("atom")
+        .parse::<FeedFormat>()
+    {
+        Ok(format) => format,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let limit = query
+        .limit
+        .unwrap_or(FEED_LIMIT_DEFAULT)
+        .clamp(1, FEED_LIMIT_MAX);
+
+    let entries = load_feed_entries(
+        &mut app_state.db_connection_pool.get().await.unwrap(),
+        &filter,
+        format == FeedFormat::Podcast,
+        limit,
+    )
+    .await
+    .unwrap();
+    let base_url = public_base_url(req, &app_state.env_var_config);
+    let feed = Feed {
+        title,
+        feed_url: format!("{}{}", base_url, req.uri()),
+        base_url,
+        entries,
+    };
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            CACHE_CONTROL,
+            format!("public, max-age={}", FEED_CACHE_SECONDS),
+        ))
+        .body(match format {
+            FeedFormat::Atom => atom(&feed),
+            FeedFormat::Json => json_feed(&feed),
+            FeedFormat::Podcast => podcast(&feed),
+        })
+}
+
+/// the url the api is reachable at from outside, as links in feeds have to be absolute.
+/// Without public_base_url, the api is assumed to be served at /api of the host the request has been sent to
+fn public_base_url(req: &HttpRequest, config: &EnvVarConfigApi) -> String {
+    match &config.public_base_url {
+        Some(public_base_url) => public_base_url.trim_end_matches('/').to_string(),
+        None => {
+            let connection_info = req.connection_info();
+            format!(
+                "{}://{}/api",
+                connection_info.scheme(),
+                connection_info.host()
+            )
+        }
+    }
+}
+
+/// loads the newest archived videos matching filter along with their files, newest first
+pub async fn load_feed_entries(
+    db_connection: &mut AsyncPgConnection,
+    filter: &ExportFilter,
+    audio_only: bool,
+    limit: i64,
+) -> diesel::QueryResult<Vec<FeedEntry>> {
+    let mut query = videos::table
+        .inner_join(files::table.on(files::id.eq(videos::file_id)))
+        .filter(videos::status.eq(VideoStatus::Archived))
+        .order(videos::archived_date.desc())
+        .limit(limit)
+        .select((Video::as_select(), File::as_select()))
+        .into_boxed();
+    if let Some(tracked_collection_id) = filter.tracked_collection_id {
+        query = query.filter(videos::tracked_collection_id.eq(tracked_collection_id));
+    }
+    if let Some(channel) = &filter.channel {
+        query = query.filter(videos::channel.eq(channel.clone()));
+    }
+    if audio_only {
+        query = query.filter(files::file_extension.eq_any(AUDIO_EXTENSIONS.to_vec()));
+    }
+    let videos = query.load::<(Video, File)>(db_connection).await?;
+
+    let thumbnail_ids: Vec<uuid::Uuid> =
+        videos.iter().map(|(video, _)| video.thumbnail_id).collect();
+    let mut thumbnails: HashMap<uuid::Uuid, File> = files::table
+        .filter(files::id.eq_any(&thumbnail_ids))
+        .load::<File>(db_connection)
+        .await?
+        .into_iter()
+        .map(|file| (file.id, file))
+        .collect();
+
+    Ok(videos
+        .into_iter()
+        .map(|(video, file)| FeedEntry {
+            thumbnail: thumbnails.remove(&video.thumbnail_id),
+            video,
+            file,
+        })
+        .collect())
+}
+
+fn file_url(base_url: &str, file: &File, is_thumbnail: bool) -> String {
+    format!(
+        "{}/file?file_id={}&is_thumbnail={}",
+        base_url, file.id, is_thumbnail
+    )
+}
+
+fn mime_type(file: &File) -> String {
+    actix_files::file_extension_to_mime(&file.file_extension).to_string()
+}
+
+/// the time the feed has changed last, which is when its newest video has been archived
+fn updated(feed: &Feed) -> DateTime<Utc> {
+    feed.entries
+        .first()
+        .map(|entry| entry.video.archived_date)
+        .unwrap_or_else(Utc::now)
+}
+
+/// escapes text and attribute values
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn atom(feed: &Feed) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n<title>{}</title>\n<id>{}</id>\n<link rel=\"self\" href=\"{}\"/>\n<updated>{}</updated>\n",
+        xml_escape(&feed.title),
+        xml_escape(&feed.feed_url),
+        xml_escape(&feed.feed_url),
+        updated(feed).to_rfc3339()
+    );
+    for entry in &feed.entries {
+        let video = &entry.video;
+        xml += &format!(
+            "<entry>\n<title>{}</title>\n<id>{}</id>\n<link rel=\"alternate\" href=\"{}\"/>\n<link rel=\"enclosure\" href=\"{}\" type=\"{}\" length=\"{}\"/>\n<author><name>{}</name></author>\n<published>{}</published>\n<updated>{}</updated>\n",
+            xml_escape(&video.title),
+            xml_escape(&video.original_url),
+            xml_escape(&video.original_url),
+            xml_escape(&file_url(&feed.base_url, &entry.file, false)),
+            xml_escape(&mime_type(&entry.file)),
+            entry.file.size,
+            xml_escape(&video.channel),
+            video.upload_date.to_rfc3339(),
+            video.archived_date.to_rfc3339()
+        );
+        if let Some(thumbnail) = &entry.thumbnail {
+            xml += &format!(
+                "<link rel=\"related\" href=\"{}\" type=\"{}\"/>\n",
+                xml_escape(&file_url(&feed.base_url, thumbnail, true)),
+                xml_escape(&mime_type(thumbnail))
+            );
+        }
+        xml += "</entry>\n";
+    }
+    xml + "</feed>\n"
+}
+
+pub fn json_feed(feed: &Feed) -> String {
+    let items: Vec<serde_json::Value> = feed
+        .entries
+        .iter()
+        .map(|entry| {
+            let video = &entry.video;
+            json!({
+                "id": video.original_url,
+                "url": video.original_url,
+                "title": video.title,
+                "image": entry.thumbnail.as_ref().map(|thumbnail| file_url(&feed.base_url, thumbnail, true)),
+                "date_published": video.upload_date.to_rfc3339(),
+                "date_modified": video.archived_date.to_rfc3339(),
+                "authors": [{ "name": video.channel }],
+                "attachments": [{
+                    "url": file_url(&feed.base_url, &entry.file, false),
+                    "mime_type": mime_type(&entry.file),
+                    "size_in_bytes": entry.file.size,
+                    "duration_in_seconds": video.duration,
+                }],
+            })
+        })
+        .collect();
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed.title,
+        "feed_url": feed.feed_url,
+        "items": items,
+    })
+    .to_string()
+}
+
+pub fn podcast(feed: &Feed) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n<title>{}</title>\n<link>{}</link>\n<description>{}</description>\n<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n<lastBuildDate>{}</lastBuildDate>\n",
+        xml_escape(&feed.title),
+        xml_escape(&feed.feed_url),
+        xml_escape(&feed.title),
+        xml_escape(&feed.feed_url),
+        updated(feed).to_rfc2822()
+    );
+    for entry in &feed.entries {
+        let video = &entry.video;
+        xml += &format!(
+            "<item>\n<title>{}</title>\n<link>{}</link>\n<guid isPermaLink=\"false\">{}</guid>\n<pubDate>{}</pubDate>\n<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n<itunes:author>{}</itunes:author>\n<itunes:duration>{}</itunes:duration>\n",
+            xml_escape(&video.title),
+            xml_escape(&video.original_url),
+            xml_escape(&video.original_url),
+            video.upload_date.to_rfc2822(),
+            xml_escape(&file_url(&feed.base_url, &entry.file, false)),
+            entry.file.size,
+            xml_escape(&mime_type(&entry.file)),
+            xml_escape(&video.channel),
+            video.duration
+        );
+        if let Some(thumbnail) = &entry.thumbnail {
+            xml += &format!(
+                "<itunes:image href=\"{}\"/>\n",
+                xml_escape(&file_url(&feed.base_url, thumbnail, true))
+            );
+        }
+        xml += "</item>\n";
+    }
+    xml + "</channel>\n</rss>\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use immortalis_backend_common::database_models::file::File;
+    use immortalis_backend_common::database_models::video::Video;
+    use immortalis_backend_common::database_models::video_status::VideoStatus;
+
+    use super::{atom, json_feed, podcast, xml_escape, Feed, FeedEntry};
+
+    fn feed() -> Feed {
+        let file_id = uuid::Uuid::new_v4();
+        Feed {
+            title: "immortalis".to_string(),
+            feed_url: "https://example.com/api/feed".to_string(),
+            base_url: "https://example.com/api".to_string(),
+            entries: vec![FeedEntry {
+                video: Video {
+                    id: 1,
+                    title: "Rock & Roll <live>".to_string(),
+                    channel: "channel".to_string(),
+                    views: 0,
+                    upload_date: Utc::now(),
+                    archived_date: Utc::now(),
+                    duration: 212,
+                    original_url: "https://www.youtube.com/watch?v=video".to_string(),
+                    status: VideoStatus::Archived,
+                    file_id,
+                    thumbnail_id: uuid::Uuid::new_v4(),
+                    release_timestamp: None,
+                    live_status: None,
+                    is_live_recording: false,
+                    tracked_collection_id: None,
+                },
+                file: File {
+                    id: file_id,
+                    file_name: "video".to_string(),
+                    file_extension: "m4a".to_string(),
+                    size: 42,
+                    checksum: None,
+                },
+                thumbnail: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(
+            xml_escape("a & b <c> \"d\" 'e'"),
+            "a &amp; b &lt;c&gt; &quot;d&quot; &apos;e&apos;"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let feed = feed();
+        let enclosure = format!(
+            "https://example.com/api/file?file_id={}&amp;is_thumbnail=false",
+            feed.entries[0].file.id
+        );
+
+        let atom = atom(&feed);
+        assert!(atom.contains("<title>Rock &amp; Roll &lt;live&gt;</title>"));
+        assert!(atom.contains(&format!("<link rel=\"enclosure\" href=\"{}\"", enclosure)));
+
+        let podcast = podcast(&feed);
+        assert!(podcast.contains(&format!("<enclosure url=\"{}\" length=\"42\"", enclosure)));
+        assert!(podcast.contains("<itunes:duration>212</itunes:duration>"));
+
+        let json: serde_json::Value = serde_json::from_str(&json_feed(&feed)).unwrap();
+        assert_eq!(json["items"][0]["title"], "Rock & Roll <live>");
+        assert_eq!(json["items"][0]["attachments"][0]["size_in_bytes"], 42);
+    }
+}
@@ -0,0 +1,651 @@
+use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::http::header::{Charset, ContentDisposition, DispositionParam, ExtendedValue};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use futures::StreamExt;
+use actix_web_actors::ws::{self};
+use immortalis_backend_common::data_transfer_models::video_dto::VideoDto;
+use immortalis_backend_common::database_models::tracked_collection::TrackedCollection;
+use immortalis_backend_common::database_models::worker::Worker;
+use immortalis_backend_common::database_models::{
+    scheduled_archival::{ScheduledArchival, MANUAL_PRIORITY, TRACKED_PRIORITY}, video::Video,
+};
+use immortalis_backend_common::env_var_config::EnvVarConfigApi;
+use immortalis_backend_common::export::{load_catalogue_page, CatalogueFormat, EXPORT_PAGE_SIZE};
+use immortalis_backend_common::schema::{
+    files, scheduled_archivals, tracked_collections, videos, workers,
+};
+use immortalis_backend_common::telemetry;
+
+use diesel::{insert_into, update, ExpressionMethods, SelectableHelper};
+use diesel::{JoinOnDsl, PgTextExpressionMethods, QueryDsl};
+use diesel_async::pooled_connection::deadpool::Pool;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::utilities::{canonicalize, filter_query_pairs, get_url_type, UrlType};
+use events::{load_channel_events, load_event, REPLAY_LIMIT};
+use immortalis_backend_common::database_models::event::{latest_event_id, load_events_after, prune_events, Event};
+use subscriptions::{Channel, PublishedEvent};
+use websocket_actor::WebSocketActor;
+
+use tracing::{error, info, warn};
+
+use crate::event_hub::EventHub;
+pub mod bulk_import;
+pub mod event_hub;
+pub mod events;
+pub mod feeds;
+pub mod metrics;
+pub mod request_models;
+pub mod server_sent_events;
+pub mod subscriptions;
+pub mod webhooks;
+pub mod websocket_actor;
+use request_models::{
+    BulkScheduleQuery, ExportQuery, GetFileRequestData, SchedulePriorityRequest, ScheduleRequest, SearchQuery,
+};
+
+#[get("/health")]
+async fn health() -> impl Responder {
+    HttpResponse::Ok().body("true")
+}
+
+#[get("/schedule")]
+async fn get_schedules(app_state: web::Data<AppState>) -> impl Responder {
+    // in the order they will be archived, apart from ones that aren't due yet
+    let results = scheduled_archivals::table
+        .order((
+            scheduled_archivals::priority.desc(),
+            scheduled_archivals::scheduled_at,
+            scheduled_archivals::id,
+        ))
+        .into_boxed();
+
+    let results = results
+        .load::<ScheduledArchival>(&mut app_state.db_connection_pool.get().await.unwrap())
+        .await
+        .expect("Error loading posts");
+
+    HttpResponse::Ok().json(results)
+}
+
+#[get("tracked_collection")]
+async fn get_tracked_collection(app_state: web::Data<AppState>) -> impl Responder {
+    let results = tracked_collections::table
+        .load::<TrackedCollection>(&mut app_state.db_connection_pool.get().await.unwrap())
+        .await
+        .unwrap();
+    HttpResponse::Ok().json(results)
+}
+
+/// lists all workers of the archivers and trackers along with what they are currently processing
+#[get("workers")]
+async fn get_workers(app_state: web::Data<AppState>) -> impl Responder {
+    let results = workers::table
+        .order_by((workers::kind, workers::host, workers::registered_at))
+        .load::<Worker>(&mut app_state.db_connection_pool.get().await.unwrap())
+        .await
+        .unwrap();
+    HttpResponse::Ok().json(results)
+}
+
+#[post("tracked_collection")]
+async fn tracked_collection(
+    schedule_request: web::Json<ScheduleRequest>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+
+    match get_url_type(&schedule_request.url) {
+        UrlType::VideoOrCollection | UrlType::Collection => (),
+        _=> return HttpResponse::BadRequest()
+    };
+
+    let response = insert_into(tracked_collections::table)
+        .values(tracked_collections::url.eq(&schedule_request.url))
+        .on_conflict_do_nothing()
+        .execute(&mut app_state.db_connection_pool.get().await.unwrap())
+        .await
+        .unwrap();
+    if response > 0 {
+        HttpResponse::Created()
+    } else {
+        HttpResponse::Ok()
+    }
+}
+
+#[post("schedule")]
+async fn schedule(
+    schedule_request: web::Json<ScheduleRequest>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+
+    match get_url_type(&schedule_request.url) {
+        UrlType::VideoOrCollection | UrlType::Video => (),
+        _=> return HttpResponse::BadRequest()
+    };
+
+    // v is youtubes query param for the video, so its the only thing that we want to keep here
+    if let Ok(video_url) = filter_query_pairs(&schedule_request.url, vec!["v"]) {
+        let db_connection = &mut app_state.db_connection_pool.get().await.unwrap();
+
+        let already_exists = match videos::table
+            .filter(videos::original_url.eq(&video_url))
+            .first::<Video>(db_connection)
+            .await
+        {
+            Ok(_x) => true,
+            Err(_x) => false,
+        };
+
+        if already_exists {
+            return HttpResponse::Ok();
+        }
+
+        let priority = schedule_request.priority.unwrap_or(MANUAL_PRIORITY);
+        // the archiver continues the trace of this request
+        let inserted = insert_into(scheduled_archivals::table)
+            .values((
+                scheduled_archivals::url.eq(&video_url),
+                scheduled_archivals::priority.eq(priority),
+                scheduled_archivals::trace_context.eq(telemetry::current_trace_context()),
+            ))
+            .on_conflict_do_nothing()
+            .execute(db_connection)
+            .await
+            .unwrap();
+        info!("Scheduled {} entries for url {}", inserted, video_url);
+
+        // requesting a video the tracker has scheduled already moves it up the queue
+        if inserted == 0 {
+            update(scheduled_archivals::table)
+                .set(scheduled_archivals::priority.eq(priority))
+                .filter(scheduled_archivals::url.eq(&video_url))
+                .filter(scheduled_archivals::priority.lt(priority))
+                .execute(db_connection)
+                .await
+                .unwrap();
+        }
+
+        HttpResponse::Created()
+    } else {
+        HttpResponse::BadRequest()
+    }
+}
+
+/// Schedules the videos of a JSON array, a text body with one url per line, a yt-dlp download archive or a Google Takeout export (JSON, HTML or CSV).
+/// The export may also be uploaded as a file of a multipart/form-data body. Responds with the result of each entry
+async fn bulk_schedule(
+    request: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<BulkScheduleQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let content_type = request
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok());
+    let entries = match bulk_import::parse_body(content_type, &body) {
+        Ok(entries) => entries,
+        Err(e @ bulk_import::BulkImportError::UnsupportedContentType(_)) => {
+            return HttpResponse::UnsupportedMediaType().body(e.to_string())
+        }
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let candidate_urls: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| canonicalize(&entry.url))
+        .collect();
+    let db_connection = &mut app_state.db_connection_pool.get().await.unwrap();
+    let mut known_urls: HashSet<String> = videos::table
+        .filter(videos::original_url.eq_any(&candidate_urls))
+        .select(videos::original_url)
+        .load::<String>(db_connection)
+        .await
+        .unwrap()
+        .into_iter()
+        .collect();
+    known_urls.extend(
+        scheduled_archivals::table
+            .filter(scheduled_archivals::url.eq_any(&candidate_urls))
+            .select(scheduled_archivals::url)
+            .load::<String>(db_connection)
+            .await
+            .unwrap(),
+    );
+
+    let response = bulk_import::classify(entries, &known_urls);
+    let priority = query.priority.unwrap_or(TRACKED_PRIORITY);
+    let trace_context = telemetry::current_trace_context();
+    // chunked, so large imports stay below the limit of bind parameters
+    for chunk in response.accepted_urls().chunks(1000) {
+        insert_into(scheduled_archivals::table)
+            .values(
+                chunk
+                    .iter()
+                    .map(|url| {
+                        (
+                            scheduled_archivals::url.eq(*url),
+                            scheduled_archivals::priority.eq(priority),
+                            scheduled_archivals::trace_context.eq(&trace_context),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .on_conflict_do_nothing()
+            .execute(db_connection)
+            .await
+            .unwrap();
+    }
+    info!(
+        "Bulk import scheduled {} urls, {} duplicates, {} invalid",
+        response.accepted, response.duplicate, response.invalid
+    );
+
+    HttpResponse::Ok().json(response)
+}
+
+/// changes the priority of a ScheduledArchival, higher priorities are archived first
+#[post("schedule/priority")]
+async fn schedule_priority(
+    priority_request: web::Json<SchedulePriorityRequest>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let updated = update(scheduled_archivals::table)
+        .set(scheduled_archivals::priority.eq(priority_request.priority))
+        .filter(scheduled_archivals::id.eq(priority_request.id))
+        .execute(&mut app_state.db_connection_pool.get().await.unwrap())
+        .await
+        .unwrap();
+
+    if updated > 0 {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::NotFound()
+    }
+}
+
+/// streams the catalogue of videos and their files as JSON Lines or CSV
+#[get("export/catalogue")]
+async fn export_catalogue(
+    query: web::Query<ExportQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let format = match query.format.as_deref().unwrap_or("jsonl").parse::<CatalogueFormat>() {
+        Ok(format) => format,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(e) => return HttpResponse::BadRequest().body(format!("invalid video_ids: {}", e)),
+    };
+
+    let header = futures::stream::iter(
+        format
+            .header()
+            .map(|header| Ok::<_, actix_web::Error>(web::Bytes::from(header))),
+    );
+    // pages are loaded as the client reads them, so the catalogue is never held in memory as a whole
+    let pool = app_state.db_connection_pool.clone();
+    let pages = futures::stream::unfold(Some(0), move |after_id| {
+        let pool = pool.clone();
+        let filter = filter.clone();
+        async move {
+            let after_id = after_id?;
+            let page = match pool.get().await {
+                Ok(mut db_connection) => {
+                    load_catalogue_page(&mut db_connection, &filter, after_id, EXPORT_PAGE_SIZE)
+                        .await
+                        .map_err(ErrorInternalServerError)
+                }
+                Err(e) => Err(ErrorInternalServerError(e)),
+            };
+            let lines = page.and_then(|page| {
+                let last_id = page.last().map(|entry| entry.video.id);
+                page.iter()
+                    .map(|entry| format.format(entry))
+                    .collect::<Result<String, _>>()
+                    .map(|lines| (lines, last_id))
+                    .map_err(ErrorInternalServerError)
+            });
+            match lines {
+                Ok((_, None)) => None,
+                Ok((lines, Some(last_id))) => Some((Ok(web::Bytes::from(lines)), Some(last_id))),
+                Err(e) => {
+                    error!("Export of the catalogue failed, encountered error {}", e);
+                    Some((Err(e), None))
+                }
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: actix_web::http::header::DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "catalogue.{}",
+                format.extension()
+            ))],
+        })
+        .streaming(header.chain(pages))
+}
+
+#[get("/search")]
+async fn search(query: web::Query<SearchQuery>, app_state: web::Data<AppState>) -> impl Responder {
+    let mut conn = app_state.db_connection_pool.get().await.unwrap();
+    let mut results = videos::table
+        .order(videos::archived_date.desc())
+        .into_boxed();
+
+    if let Some(x) = &query.term {
+        results =
+            results.filter(videos::title.ilike("%".to_string() + (x.to_owned() + "%").as_str()))
+    }
+
+    let results: Vec<(Video, i64)> = results
+        .inner_join(
+            immortalis_backend_common::schema::files::dsl::files.on(files::id.eq(videos::file_id)),
+        )
+        .select((Video::as_select(), files::size))
+        .load::<(Video, i64)>(&mut conn)
+        .await
+        .expect("Error loading posts");
+
+    HttpResponse::Ok().json(
+        results
+            .into_iter()
+            .map(move |f| VideoDto {
+                video: f.0,
+                video_size: f.1,
+            })
+            .collect::<Vec<VideoDto>>(),
+    )
+}
+
+#[get("/file")]
+async fn get_file(
+    req: HttpRequest,
+    query: web::Query<GetFileRequestData>,
+    app_state: web::Data<AppState>,
+) -> Result<impl Responder, actix_web::error::Error> {
+    let mut conn = app_state.db_connection_pool.get().await.unwrap();
+
+    let f: immortalis_backend_common::database_models::file::File = files::table
+        .find(query.file_id)
+        .first(&mut conn)
+        .await
+        .unwrap();
+
+    // if s3 is used, redirect to a presigned link to the s3, otherwise return the file from disk
+    if app_state.env_var_config.use_s3 {
+        let mut custom_queries = HashMap::new();
+        custom_queries.insert(
+            "response-content-disposition".into(),
+            format!(
+                "attachment; filename=\"{}.{}\"",
+                f.file_name, &f.file_extension
+            ),
+        );
+        // content addressed objects have no extension, so the content type is derived from the file
+        custom_queries.insert(
+            "response-content-type".into(),
+            actix_files::file_extension_to_mime(&f.file_extension).to_string(),
+        );
+
+        custom_queries.insert("cache-control".into(), format!("public, max-age={}", app_state.env_var_config.s3_file_cache_duration_seconds)); // the file downloaded from minio is cached for 7 days
+        let presign = app_state
+            .bucket
+            .presign_get(
+                f.storage_key(),
+                app_state.env_var_config.s3_file_cache_duration_seconds,
+                Some(custom_queries),
+            )
+            .unwrap();
+
+        let mut response = actix_web::web::Redirect::to(presign).respond_to(&req);
+        response.headers_mut().append(CACHE_CONTROL, HeaderValue::from_str(format!("public, max-age={}", app_state.env_var_config.s3_file_cache_duration_seconds).as_str()).unwrap()); // cache the presigned link for as long as its valid (7 days, which is the maximum for s3)
+        Ok(response.map_into_boxed_body())
+    } else {
+        let mut response = actix_files::NamedFile::open_async(
+            app_state.file_storage_location.join(f.storage_key()),
+        )
+        .await?;
+        response = response
+        .set_content_type(actix_files::file_extension_to_mime(&f.file_extension))
+        .set_content_disposition(ContentDisposition {
+            disposition: actix_web::http::header::DispositionType::Attachment,
+            parameters: vec![DispositionParam::FilenameExt(ExtendedValue {
+                value: format!("{}.{}", f.file_name, &f.file_extension)
+                    .as_bytes()
+                    .to_vec(),
+                charset: Charset::Ext("UTF-8".to_string()),
+                language_tag: None,
+            })],
+        });
+
+        let response = response.customize().append_header(("cache-control", format!("public, max-age={}", app_state.env_var_config.disk_file_cache_duration_seconds))); // the file directly returned is cached for one year
+        Ok(response.respond_to(&req).map_into_boxed_body())
+    }
+}
+
+pub struct AppState {
+    pub db_connection_pool: Pool<AsyncPgConnection>,
+    pub file_storage_location: PathBuf,
+    pub event_hub: EventHub,
+    pub env_var_config: Arc<EnvVarConfigApi>,
+    pub bucket: Arc<s3::Bucket>,
+}
+
+/// Publishes the events stored by postgres to the event hub. Notifications are handled as soon as they arrive,
+/// if the connection is lost the listener reconnects with a growing delay and publishes the events stored in the meantime.
+/// Event ids follow the commit order, so the events after the last published id are exactly the ones which have been missed
+pub async fn distribute_postgres_events(app_state: web::Data<AppState>) {
+    let database_url = &app_state.env_var_config.general_config.database_url;
+    let mut reconnect_delay = LISTENER_RECONNECT_DELAY_MIN;
+    let mut last_published_id: Option<i64> = None;
+    loop {
+        let mut listener = match listen_to_postgres_events(database_url).await {
+            Ok(listener) => {
+                info!("Listening to postgres events");
+                reconnect_delay = LISTENER_RECONNECT_DELAY_MIN;
+                listener
+            }
+            Err(e) => {
+                error!(
+                    "Failed to listen to postgres events, retrying in {:?}. Encountered error {}",
+                    reconnect_delay, e
+                );
+                tokio::time::sleep(reconnect_delay).await;
+                reconnect_delay = (reconnect_delay * 2).min(LISTENER_RECONNECT_DELAY_MAX);
+                continue;
+            }
+        };
+
+        // the events committed from now on are notified, the ones before are replayed. Without a replay, later notifications would skip the missed events
+        let caught_up = match last_published_id {
+            Some(after_id) => {
+                let mut last_id = after_id;
+                let replayed = publish_events_stored_after(&app_state, &mut last_id).await;
+                last_published_id = Some(last_id);
+                replayed
+            }
+            None => load_latest_event_id(&app_state).await.map(|latest_id| last_published_id = Some(latest_id)),
+        };
+        if let Err(e) = caught_up {
+            error!("Failed to load missed events, retrying in {:?}. Encountered error {}", reconnect_delay, e);
+            tokio::time::sleep(reconnect_delay).await;
+            reconnect_delay = (reconnect_delay * 2).min(LISTENER_RECONNECT_DELAY_MAX);
+            continue;
+        }
+
+        loop {
+            let notification = match listener.recv().await {
+                Ok(notification) => notification,
+                Err(e) => {
+                    warn!("Lost the connection to postgres events, reconnecting. Encountered error {}", e);
+                    break;
+                }
+            };
+
+            // notifications only carry the id of the stored event
+            let Ok(event_id) = notification.payload().parse::<i64>() else {
+                warn!(
+                    "received notification on channel {} without an event id",
+                    notification.channel()
+                );
+                continue;
+            };
+            // published by the replay after reconnecting already
+            if last_published_id.map_or(false, |id| event_id <= id) {
+                continue;
+            }
+            let mut conn = match app_state.db_connection_pool.get().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    // later notifications would move past the event, it is replayed after reconnecting instead
+                    error!("Failed to load event {}, reconnecting. Encountered error {}", event_id, e);
+                    break;
+                }
+            };
+            match load_event(&mut conn, event_id).await {
+                Ok(event) => publish_stored_event(&app_state.event_hub, &mut conn, &event).await,
+                Err(e) => {
+                    // later notifications would move past the event, it is replayed after reconnecting instead
+                    error!("Failed to load event {}, reconnecting. Encountered error {}", event_id, e);
+                    break;
+                }
+            }
+            last_published_id = Some(event_id);
+        }
+    }
+}
+
+const LISTENER_RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const LISTENER_RECONNECT_DELAY_MAX: Duration = Duration::from_secs(60);
+
+fn postgres_channels() -> Vec<&'static str> {
+    Channel::all()
+        .iter()
+        .flat_map(|channel| channel.postgres_channels())
+        .copied()
+        .collect()
+}
+
+async fn listen_to_postgres_events(
+    database_url: &str,
+) -> Result<sqlx::postgres::PgListener, sqlx::Error> {
+    let mut listener = sqlx::postgres::PgListener::connect(database_url).await?;
+    listener.listen_all(postgres_channels()).await?;
+    Ok(listener)
+}
+
+/// every connection applies the filters of its subscriptions
+async fn publish_stored_event(event_hub: &EventHub, conn: &mut AsyncPgConnection, event: &Event) {
+    for channel_event in load_channel_events(conn, event).await {
+        event_hub.publish(PublishedEvent {
+            id: event.id,
+            event: channel_event,
+        });
+    }
+}
+
+/// the id of the latest committed event, 0 if there are none
+async fn load_latest_event_id(app_state: &AppState) -> Result<i64, String> {
+    let mut conn = app_state.db_connection_pool.get().await.map_err(|e| e.to_string())?;
+    latest_event_id(&mut conn)
+        .await
+        .map(|id| id.unwrap_or(0))
+        .map_err(|e| e.to_string())
+}
+
+/// publishes the events stored after last_id, which is advanced to the id of every published event
+async fn publish_events_stored_after(app_state: &AppState, last_id: &mut i64) -> Result<(), String> {
+    let after_id = *last_id;
+    let mut conn = app_state.db_connection_pool.get().await.map_err(|e| e.to_string())?;
+    let channels = postgres_channels();
+    loop {
+        let stored = load_events_after(&mut conn, *last_id, &channels, REPLAY_LIMIT)
+            .await
+            .map_err(|e| e.to_string())?;
+        for event in &stored {
+            publish_stored_event(&app_state.event_hub, &mut conn, event).await;
+            *last_id = event.id;
+        }
+        if (stored.len() as i64) < REPLAY_LIMIT {
+            info!(
+                "Published the events stored after {} up to {}",
+                after_id, last_id
+            );
+            return Ok(());
+        }
+    }
+}
+
+/// deletes the events older than the retention, they can't be replayed anymore
+pub async fn prune_events_periodically(app_state: web::Data<AppState>) {
+    let mut interval = actix_web::rt::time::interval(EVENT_PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let older_than = chrono::Utc::now()
+            - chrono::Duration::seconds(app_state.env_var_config.event_retention_seconds);
+        let result = match app_state.db_connection_pool.get().await {
+            Ok(mut conn) => prune_events(&mut conn, older_than)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(pruned) => info!("Pruned {} events", pruned),
+            Err(e) => error!("Failed to prune events, encountered error {}", e),
+        }
+    }
+}
+
+const EVENT_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+async fn websocket(
+    req: HttpRequest,
+    stream: web::Payload,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::error::Error> {
+    ws::start(
+        WebSocketActor::new(app_state.clone()),
+        &req,
+        stream,
+    )
+}
+
+/// registers all routes of the api
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/ws/", web::get().to(websocket))
+        .service(health)
+        .service(metrics::get_metrics)
+        .service(search)
+        .service(schedule)
+        .service(
+            web::resource("/schedule/bulk")
+                .app_data(web::PayloadConfig::new(bulk_import::BULK_IMPORT_MAX_BYTES))
+                .route(web::post().to(bulk_schedule)),
+        )
+        .service(schedule_priority)
+        .service(get_schedules)
+        .service(get_tracked_collection)
+        .service(tracked_collection)
+        .service(get_workers)
+        .service(export_catalogue)
+        .service(feeds::feed)
+        .service(feeds::channel_feed)
+        .service(feeds::tracked_collection_feed)
+        .service(get_file)
+        .service(server_sent_events::event_stream)
+        .service(webhooks::get_webhooks)
+        .service(webhooks::create_webhook)
+        .service(webhooks::delete_webhook)
+        .service(webhooks::get_webhook_deliveries);
+}
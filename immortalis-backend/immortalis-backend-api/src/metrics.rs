@@ -0,0 +1,58 @@
+use std::future::Future;
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{get, web, HttpResponse, Responder};
+use immortalis_backend_common::metrics::{self, HTTP_REQUEST_DURATION};
+use tracing::error;
+
+use crate::AppState;
+
+/// The metrics of the api in the prometheus text format, along with the queue depth and storage size of all replicas
+#[get("/metrics")]
+pub async fn get_metrics(app_state: web::Data<AppState>) -> impl Responder {
+    let refreshed = match app_state.db_connection_pool.get().await {
+        Ok(mut conn) => metrics::refresh_database_gauges(
+            &mut conn,
+            metrics::storage_backend(app_state.env_var_config.use_s3),
+        )
+        .await
+        .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    // the remaining metrics are still useful, the gauges keep their last value
+    if let Err(e) = refreshed {
+        error!(
+            "Failed to refresh database metrics, encountered error {}",
+            e
+        );
+    }
+    HttpResponse::Ok()
+        .content_type(metrics::content_type())
+        .body(metrics::encode())
+}
+
+/// Middleware recording the duration of every request by the pattern of its route, so paths with ids don't create a series each.
+/// Used with App::wrap_fn
+pub fn record_request<S, B>(
+    req: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let response = service.call(req);
+    async move {
+        let response = response.await?;
+        let route = response
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[&method, &route, response.status().as_str()])
+            .observe(start.elapsed().as_secs_f64());
+        Ok(response)
+    }
+}
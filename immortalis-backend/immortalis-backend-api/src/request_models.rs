@@ -1,3 +1,4 @@
+use immortalis_backend_common::export::ExportFilter;
 use serde::Deserialize;
 use uuid::Uuid;
 
@@ -10,9 +11,74 @@ pub struct GetFileRequestData {
 #[derive(Deserialize)]
 pub struct ScheduleRequest {
     pub url: String,
+    /// defaults to MANUAL_PRIORITY
+    pub priority: Option<i16>,
+}
+
+#[derive(Deserialize)]
+pub struct SchedulePriorityRequest {
+    pub id: i32,
+    pub priority: i16,
+}
+
+#[derive(Deserialize)]
+pub struct BulkScheduleQuery {
+    /// bulk imports are usually back catalogues, so they default to TRACKED_PRIORITY
+    pub priority: Option<i16>,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// jsonl or csv, defaults to jsonl
+    pub format: Option<String>,
+    /// comma separated ids of the videos to export
+    pub video_ids: Option<String>,
+    pub tracked_collection_id: Option<i32>,
+    pub channel: Option<String>,
+}
+
+impl ExportQuery {
+    pub fn filter(&self) -> Result<ExportFilter, std::num::ParseIntError> {
+        Ok(ExportFilter {
+            video_ids: match &self.video_ids {
+                Some(video_ids) => video_ids
+                    .split(',')
+                    .map(|id| id.trim().parse())
+                    .collect::<Result<_, _>>()?,
+                None => Vec::new(),
+            },
+            tracked_collection_id: self.tracked_collection_id,
+            channel: self.channel.clone(),
+        })
+    }
 }
 
 #[derive(Deserialize)]
 pub struct SearchQuery {
     pub term: Option<String>,
 }
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    /// the lifecycle events to send, all of them if empty
+    #[serde(default)]
+    pub event_types: Vec<String>,
+    /// generated if missing
+    pub secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookDeliveriesQuery {
+    /// defaults to 50
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    /// atom, json or podcast, defaults to atom
+    pub format: Option<String>,
+    /// defaults to 50
+    pub limit: Option<i64>,
+}
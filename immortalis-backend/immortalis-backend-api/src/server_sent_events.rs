@@ -0,0 +1,167 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_http::header::CACHE_CONTROL;
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+use immortalis_backend_common::env_var_config::SlowConsumerPolicy;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::error;
+
+use crate::events::replay;
+use crate::subscriptions::{Channel, PublishedEvent, SubscriptionFilter};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct EventStreamQuery {
+    /// comma separated channels, e.g. scheduled_archivals,videos
+    channels: String,
+    video_id: Option<i32>,
+    tracked_collection_id: Option<i32>,
+    #[serde(default)]
+    status_changes_only: bool,
+    /// replays the events stored after this one, like the Last-Event-ID header browsers send when reconnecting
+    last_event_id: Option<i64>,
+}
+
+/// The events of the channels as server-sent events, for consumers which don't need to change their subscriptions.
+/// The filter applies to every channel
+#[get("/events")]
+pub async fn event_stream(
+    req: HttpRequest,
+    query: web::Query<EventStreamQuery>,
+    app_state: web::Data<AppState>,
+) -> HttpResponse {
+    let filter = SubscriptionFilter {
+        video_id: query.video_id,
+        tracked_collection_id: query.tracked_collection_id,
+        status_changes_only: query.status_changes_only,
+    };
+    let mut channels = vec![];
+    for name in query.channels.split(',').map(str::trim) {
+        let Some(channel) = Channel::from_postgres_channel(name) else {
+            return HttpResponse::BadRequest().body(format!("unknown channel {}", name));
+        };
+        if let Err(message) = filter.validate(channel) {
+            return HttpResponse::BadRequest().body(message);
+        }
+        channels.push(channel);
+    }
+    let last_event_id = query.last_event_id.or_else(|| {
+        req.headers()
+            .get("Last-Event-ID")
+            .and_then(|id| id.to_str().ok())
+            .and_then(|id| id.parse().ok())
+    });
+
+    // subscribed before the replay is loaded, so no event is missed in between
+    let events = Box::pin(app_state.event_hub.subscribe());
+    // the queue of the connection is the one of the hub, this only decouples writing the response
+    let (sender, receiver) = mpsc::channel::<Result<Bytes, actix_web::Error>>(16);
+    actix_web::rt::spawn(stream_events(
+        app_state,
+        channels,
+        filter,
+        last_event_id,
+        events,
+        sender,
+    ));
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        // keeps nginx from buffering the events
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(receiver)
+}
+
+/// writes the replay and then the new events to the sender, until the client disconnects
+async fn stream_events(
+    app_state: web::Data<AppState>,
+    channels: Vec<Channel>,
+    filter: SubscriptionFilter,
+    last_event_id: Option<i64>,
+    mut events: impl Stream<Item = Result<Arc<PublishedEvent>, RecvError>> + Unpin,
+    mut sender: mpsc::Sender<Result<Bytes, actix_web::Error>>,
+) {
+    let matches = |event: &PublishedEvent| {
+        channels.contains(&event.event.channel()) && event.event.matches(&filter)
+    };
+
+    let mut last_sent_id = None;
+    if let Some(last_event_id) = last_event_id {
+        let replayed = match app_state.db_connection_pool.get().await {
+            Ok(mut conn) => replay(&mut conn, last_event_id, &channels)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let mut messages = vec![];
+        match replayed {
+            Ok(replayed) => {
+                for event in replayed.events.iter().filter(|event| matches(event)) {
+                    messages.push(event_message(event));
+                }
+                last_sent_id = replayed.events.last().map(|event| event.id);
+                if !replayed.complete {
+                    messages.push(control_message("reset", "{}"));
+                }
+            }
+            Err(e) => {
+                error!("Failed to replay events, encountered error {}", e);
+                messages.push(control_message("reset", "{}"));
+            }
+        }
+        for message in messages {
+            if sender.send(Ok(Bytes::from(message))).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    let config = &app_state.env_var_config;
+    let heartbeat_interval = Duration::from_secs(config.websocket_heartbeat_interval_seconds);
+    let mut heartbeat = actix_web::rt::time::interval(heartbeat_interval);
+    loop {
+        let message = tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(event)) => {
+                    if last_sent_id.map_or(false, |id| event.id <= id) || !matches(&event) {
+                        continue;
+                    }
+                    event_message(&event)
+                }
+                Some(Err(RecvError::Lagged(missed))) => {
+                    match config.websocket_slow_consumer_policy {
+                        SlowConsumerPolicy::Drop => {
+                            control_message("lagged", &format!("{{\"missed\":{}}}", missed))
+                        }
+                        SlowConsumerPolicy::Disconnect => return,
+                    }
+                }
+                Some(Err(RecvError::Closed)) | None => return,
+            },
+            // comments keep proxies from closing the connection and detect disconnected clients
+            _ = heartbeat.tick() => ": ping\n\n".to_string(),
+        };
+        if sender.send(Ok(Bytes::from(message))).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn event_message(event: &PublishedEvent) -> String {
+    format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.id,
+        event.event.channel().name(),
+        serde_json::to_string(&event.event).expect("could not serialize event")
+    )
+}
+
+fn control_message(event: &str, data: &str) -> String {
+    format!("event: {}\ndata: {}\n\n", event, data)
+}
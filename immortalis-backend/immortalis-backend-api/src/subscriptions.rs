@@ -0,0 +1,478 @@
+use immortalis_backend_common::data_transfer_models::video_dto::VideoDto;
+use immortalis_backend_common::database_models::scheduled_archival::ScheduledArchival;
+use immortalis_backend_common::database_models::tracked_collection::TrackedCollection;
+use immortalis_backend_common::database_models::worker::Worker;
+use immortalis_backend_common::lifecycle_event::{LifecycleEvent, LIFECYCLE_CHANNEL};
+use serde::{Deserialize, Serialize};
+
+/// the channels clients can subscribe to, named like the tables postgres notifies about
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    ScheduledArchivals,
+    TrackedCollections,
+    Workers,
+    Videos,
+    LifecycleEvents,
+}
+
+impl Channel {
+    pub fn from_postgres_channel(channel: &str) -> Option<Channel> {
+        match channel {
+            "scheduled_archivals" => Some(Channel::ScheduledArchivals),
+            "tracked_collections" => Some(Channel::TrackedCollections),
+            "workers" => Some(Channel::Workers),
+            "videos" => Some(Channel::Videos),
+            LIFECYCLE_CHANNEL => Some(Channel::LifecycleEvents),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::ScheduledArchivals => "scheduled_archivals",
+            Channel::TrackedCollections => "tracked_collections",
+            Channel::Workers => "workers",
+            Channel::Videos => "videos",
+            Channel::LifecycleEvents => LIFECYCLE_CHANNEL,
+        }
+    }
+
+    /// the channels of the notifications the events of the channel are built from
+    pub fn postgres_channels(&self) -> &'static [&'static str] {
+        match self {
+            Channel::ScheduledArchivals => &["scheduled_archivals"],
+            Channel::TrackedCollections => &["tracked_collections"],
+            Channel::Workers => &["workers"],
+            Channel::Videos => &["videos", "files"],
+            Channel::LifecycleEvents => &[LIFECYCLE_CHANNEL],
+        }
+    }
+
+    pub fn all() -> [Channel; 5] {
+        [
+            Channel::ScheduledArchivals,
+            Channel::TrackedCollections,
+            Channel::Workers,
+            Channel::Videos,
+            Channel::LifecycleEvents,
+        ]
+    }
+}
+
+/// Narrows down the events of a subscription, an empty filter matches every event of the channel
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SubscriptionFilter {
+    /// only events of the archival of this video
+    pub video_id: Option<i32>,
+    /// only events of this collection or of the archivals of videos discovered in it
+    pub tracked_collection_id: Option<i32>,
+    /// skips updates which leave the status unchanged, like the heartbeats of workers
+    #[serde(default)]
+    pub status_changes_only: bool,
+}
+
+impl SubscriptionFilter {
+    /// returns why the filter can't be applied to the channel, if it can't
+    pub fn validate(&self, channel: Channel) -> Result<(), String> {
+        match channel {
+            Channel::ScheduledArchivals | Channel::Videos | Channel::LifecycleEvents => Ok(()),
+            Channel::TrackedCollections if self.video_id.is_some() => {
+                Err("tracked_collections can't be filtered by videoId".to_string())
+            }
+            Channel::TrackedCollections => Ok(()),
+            Channel::Workers if self.video_id.is_some() || self.tracked_collection_id.is_some() => {
+                Err("workers can only be filtered by statusChangesOnly".to_string())
+            }
+            Channel::Workers => Ok(()),
+        }
+    }
+
+    pub fn matches<E: FilterableEvent>(&self, event: &E) -> bool {
+        if self.video_id.is_some() && event.video_id() != self.video_id {
+            return false;
+        }
+        if self.tracked_collection_id.is_some()
+            && event.tracked_collection_id() != self.tracked_collection_id
+        {
+            return false;
+        }
+        !self.status_changes_only || event.status_changed()
+    }
+}
+
+/// an event a subscription filter can be applied to
+pub trait FilterableEvent {
+    fn video_id(&self) -> Option<i32>;
+    fn tracked_collection_id(&self) -> Option<i32>;
+    /// inserts and deletes always change the status
+    fn status_changed(&self) -> bool;
+}
+
+/// a row postgres notifies about, see notify_delete_insert()
+pub trait EventRecord {
+    fn video_id(&self) -> Option<i32> {
+        None
+    }
+
+    fn tracked_collection_id(&self) -> Option<i32> {
+        None
+    }
+
+    /// whether the update from old changed the status of the record
+    fn status_changed(&self, old: &Self) -> bool;
+}
+
+impl EventRecord for ScheduledArchival {
+    fn video_id(&self) -> Option<i32> {
+        self.video_id
+    }
+
+    fn tracked_collection_id(&self) -> Option<i32> {
+        self.tracked_collection_id
+    }
+
+    fn status_changed(&self, old: &Self) -> bool {
+        self.stage != old.stage || self.video_id != old.video_id
+    }
+}
+
+impl EventRecord for TrackedCollection {
+    fn tracked_collection_id(&self) -> Option<i32> {
+        Some(self.id)
+    }
+
+    fn status_changed(&self, old: &Self) -> bool {
+        self.last_checked != old.last_checked
+    }
+}
+
+impl EventRecord for Worker {
+    fn status_changed(&self, old: &Self) -> bool {
+        self.current_job != old.current_job
+    }
+}
+
+/// the payload of a postgres notification
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct PostgresEvent<T> {
+    pub action: String,
+    pub record: T,
+    /// the row before an update
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_record: Option<T>,
+}
+
+impl<T: EventRecord> FilterableEvent for PostgresEvent<T> {
+    fn video_id(&self) -> Option<i32> {
+        self.record.video_id()
+    }
+
+    fn tracked_collection_id(&self) -> Option<i32> {
+        self.record.tracked_collection_id()
+    }
+
+    fn status_changed(&self) -> bool {
+        match &self.old_record {
+            Some(old_record) if self.action == "update" => self.record.status_changed(old_record),
+            _ => true,
+        }
+    }
+}
+
+/// the payload of a notification about a video, which only carries its id since videos may exceed the size limit of notifications
+#[derive(Deserialize, Debug)]
+pub struct VideoNotification {
+    pub action: String,
+    pub id: i32,
+    pub tracked_collection_id: Option<i32>,
+    pub status_changed: bool,
+}
+
+/// the payload of a notification about a changed file size
+#[derive(Deserialize, Debug)]
+pub struct FileNotification {
+    pub id: uuid::Uuid,
+}
+
+/// A change of a video, in the shape /search returns videos in. Deleted videos can't be loaded anymore, so only their id is sent
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoEvent {
+    pub action: String,
+    pub id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<VideoDto>,
+    #[serde(skip)]
+    pub tracked_collection_id: Option<i32>,
+    #[serde(skip)]
+    pub status_changed: bool,
+}
+
+impl FilterableEvent for VideoEvent {
+    fn video_id(&self) -> Option<i32> {
+        Some(self.id)
+    }
+
+    fn tracked_collection_id(&self) -> Option<i32> {
+        self.tracked_collection_id
+    }
+
+    fn status_changed(&self) -> bool {
+        self.status_changed
+    }
+}
+
+impl FilterableEvent for LifecycleEvent {
+    fn video_id(&self) -> Option<i32> {
+        LifecycleEvent::video_id(self)
+    }
+
+    fn tracked_collection_id(&self) -> Option<i32> {
+        LifecycleEvent::tracked_collection_id(self)
+    }
+
+    fn status_changed(&self) -> bool {
+        true
+    }
+}
+
+/// an event of any channel, as distributed to the connections
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum ChannelEvent {
+    ScheduledArchival(PostgresEvent<ScheduledArchival>),
+    TrackedCollection(PostgresEvent<TrackedCollection>),
+    Worker(PostgresEvent<Worker>),
+    Video(VideoEvent),
+    Lifecycle(LifecycleEvent),
+}
+
+impl ChannelEvent {
+    /// parses notifications of the channels carrying the whole row, events of videos are loaded by the api
+    pub fn parse(channel: Channel, payload: &str) -> Result<ChannelEvent, serde_json::Error> {
+        Ok(match channel {
+            Channel::ScheduledArchivals => {
+                ChannelEvent::ScheduledArchival(serde_json::from_str(payload)?)
+            }
+            Channel::TrackedCollections => {
+                ChannelEvent::TrackedCollection(serde_json::from_str(payload)?)
+            }
+            Channel::Workers => ChannelEvent::Worker(serde_json::from_str(payload)?),
+            Channel::LifecycleEvents => ChannelEvent::Lifecycle(serde_json::from_str(payload)?),
+            Channel::Videos => {
+                return Err(serde::de::Error::custom(
+                    "video notifications only carry ids",
+                ))
+            }
+        })
+    }
+
+    pub fn channel(&self) -> Channel {
+        match self {
+            ChannelEvent::ScheduledArchival(_) => Channel::ScheduledArchivals,
+            ChannelEvent::TrackedCollection(_) => Channel::TrackedCollections,
+            ChannelEvent::Worker(_) => Channel::Workers,
+            ChannelEvent::Video(_) => Channel::Videos,
+            ChannelEvent::Lifecycle(_) => Channel::LifecycleEvents,
+        }
+    }
+
+    pub fn matches(&self, filter: &SubscriptionFilter) -> bool {
+        match self {
+            ChannelEvent::ScheduledArchival(event) => filter.matches(event),
+            ChannelEvent::TrackedCollection(event) => filter.matches(event),
+            ChannelEvent::Worker(event) => filter.matches(event),
+            ChannelEvent::Video(event) => filter.matches(event),
+            ChannelEvent::Lifecycle(event) => filter.matches(event),
+        }
+    }
+}
+
+/// an event along with the id it has been stored under, clients pass the id of the last event they received to resume after reconnecting.
+/// A changed file results in an event for every video stored in it, these share the id
+#[derive(Debug)]
+pub struct PublishedEvent {
+    pub id: i64,
+    pub event: ChannelEvent,
+}
+
+/// messages sent by clients
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ClientMessage {
+    /// subscribes to the channel, replacing the filter of an existing subscription
+    Subscribe {
+        channel: Channel,
+        #[serde(default)]
+        filter: SubscriptionFilter,
+        /// replays the events stored after this one before sending new ones
+        #[serde(default, rename = "lastEventId")]
+        last_event_id: Option<i64>,
+    },
+    Unsubscribe {
+        channel: Channel,
+    },
+}
+
+/// messages sent to clients
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage<'a> {
+    Subscribed {
+        channel: Channel,
+        filter: &'a SubscriptionFilter,
+    },
+    Unsubscribed {
+        channel: Channel,
+    },
+    /// the connection fell behind and the events in between have been dropped, the client should reload what it displays
+    Lagged {
+        missed: u64,
+    },
+    /// the events since lastEventId can't be replayed completely, as they have been pruned or are too many. The client should reload what it displays
+    Reset {
+        channel: Channel,
+    },
+    /// the message of the client was invalid, nothing has changed
+    Error {
+        message: String,
+    },
+    Event {
+        id: i64,
+        channel: Channel,
+        data: &'a ChannelEvent,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use immortalis_backend_common::database_models::archival_stage::ArchivalStage;
+    use immortalis_backend_common::database_models::scheduled_archival::ScheduledArchival;
+
+    use super::{Channel, ClientMessage, PostgresEvent, SubscriptionFilter};
+
+    fn scheduled_archival(stage: ArchivalStage) -> ScheduledArchival {
+        ScheduledArchival {
+            id: 1,
+            url: "https://www.youtube.com/watch?v=video".to_string(),
+            scheduled_at: Utc::now(),
+            not_before: Utc::now(),
+            stage,
+            video_id: Some(2),
+            domain: Some("youtube.com".to_string()),
+            priority: 0,
+            tracked_collection_id: Some(3),
+            failed_attempts: 0,
+            trace_context: None,
+        }
+    }
+
+    fn update(old: ArchivalStage, new: ArchivalStage) -> PostgresEvent<ScheduledArchival> {
+        PostgresEvent {
+            action: "update".to_string(),
+            record: scheduled_archival(new),
+            old_record: Some(scheduled_archival(old)),
+        }
+    }
+
+    #[test]
+    fn test_parse_client_message() {
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(
+                r#"{"type": "subscribe", "channel": "scheduled_archivals", "filter": {"videoId": 2, "statusChangesOnly": true}}"#
+            )
+            .unwrap(),
+            ClientMessage::Subscribe {
+                channel: Channel::ScheduledArchivals,
+                filter: SubscriptionFilter {
+                    video_id: Some(2),
+                    tracked_collection_id: None,
+                    status_changes_only: true
+                },
+                last_event_id: None
+            }
+        );
+        assert!(matches!(
+            serde_json::from_str::<ClientMessage>(
+                r#"{"type": "subscribe", "channel": "videos", "lastEventId": 42}"#
+            )
+            .unwrap(),
+            ClientMessage::Subscribe {
+                last_event_id: Some(42),
+                ..
+            }
+        ));
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(
+                r#"{"type": "unsubscribe", "channel": "workers"}"#
+            )
+            .unwrap(),
+            ClientMessage::Unsubscribe {
+                channel: Channel::Workers
+            }
+        );
+        assert!(serde_json::from_str::<ClientMessage>(
+            r#"{"type": "subscribe", "channel": "videos"}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<ClientMessage>(
+            r#"{"type": "subscribe", "channel": "workers", "filter": {"unknown": 1}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let by_video = SubscriptionFilter {
+            video_id: Some(2),
+            ..Default::default()
+        };
+        assert!(by_video.validate(Channel::ScheduledArchivals).is_ok());
+        assert!(by_video.validate(Channel::Videos).is_ok());
+        assert!(by_video.validate(Channel::TrackedCollections).is_err());
+        assert!(by_video.validate(Channel::Workers).is_err());
+        assert!(SubscriptionFilter::default()
+            .validate(Channel::Workers)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_matches() {
+        let event = update(ArchivalStage::Pending, ArchivalStage::Registered);
+        assert!(SubscriptionFilter::default().matches(&event));
+        assert!(SubscriptionFilter {
+            video_id: Some(2),
+            tracked_collection_id: Some(3),
+            status_changes_only: true
+        }
+        .matches(&event));
+        assert!(!SubscriptionFilter {
+            video_id: Some(4),
+            ..Default::default()
+        }
+        .matches(&event));
+        assert!(!SubscriptionFilter {
+            tracked_collection_id: Some(4),
+            ..Default::default()
+        }
+        .matches(&event));
+
+        let status_changes_only = SubscriptionFilter {
+            status_changes_only: true,
+            ..Default::default()
+        };
+        assert!(!status_changes_only.matches(&update(
+            ArchivalStage::Registered,
+            ArchivalStage::Registered
+        )));
+        assert!(status_changes_only.matches(&PostgresEvent {
+            action: "delete".to_string(),
+            record: scheduled_archival(ArchivalStage::Downloaded),
+            old_record: None,
+        }));
+    }
+}
@@ -0,0 +1,309 @@
+use std::time::Duration;
+
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use immortalis_backend_common::database_models::webhook::{
+    self, DeliveryOutcome, Webhook, WebhookDelivery,
+};
+use immortalis_backend_common::env_var_config::EnvVarConfigApi;
+use immortalis_backend_common::lifecycle_event::{LifecycleEvent, LIFECYCLE_EVENT_TYPES};
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use sha2::Sha256;
+use tracing::{error, info, warn};
+
+use crate::request_models::{CreateWebhookRequest, WebhookDeliveriesQuery};
+use crate::subscriptions::Channel;
+use crate::AppState;
+
+/// due retries are picked up this often, new events are delivered right away
+const WEBHOOK_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const DELIVERIES_LIMIT_DEFAULT: i64 = 50;
+const DELIVERIES_LIMIT_MAX: i64 = 1000;
+
+/// a new webhook along with its secret, which isn't shown afterwards
+#[derive(Serialize)]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String,
+}
+
+/// The body posted to webhooks. Slack and Matrix display its text, Discord does so at the /slack variant of its webhook urls
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload<'a> {
+    id: i64,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    created_at: DateTime<Utc>,
+    text: String,
+    data: &'a LifecycleEvent,
+}
+
+#[get("/webhooks")]
+pub async fn get_webhooks(app_state: web::Data<AppState>) -> impl Responder {
+    let results = webhook::list_webhooks(&mut app_state.db_connection_pool.get().await.unwrap())
+        .await
+        .unwrap();
+    HttpResponse::Ok().json(results)
+}
+
+/// registers a webhook, a secret is generated unless one is passed
+#[post("/webhooks")]
+pub async fn create_webhook(
+    webhook_request: web::Json<CreateWebhookRequest>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    match url::Url::parse(&webhook_request.url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => (),
+        _ => {
+            return HttpResponse::BadRequest()
+                .body(format!("{} is not a http(s) url", webhook_request.url))
+        }
+    }
+    if let Some(event_type) = webhook_request
+        .event_types
+        .iter()
+        .find(|event_type| !LIFECYCLE_EVENT_TYPES.contains(&event_type.as_str()))
+    {
+        return HttpResponse::BadRequest().body(format!("unknown event type {}", event_type));
+    }
+    let secret = match &webhook_request.secret {
+        Some(secret) if secret.is_empty() => {
+            return HttpResponse::BadRequest().body("the secret must not be empty")
+        }
+        Some(secret) => secret.clone(),
+        None => uuid::Uuid::new_v4().simple().to_string(),
+    };
+
+    let webhook = webhook::insert_webhook(
+        &mut app_state.db_connection_pool.get().await.unwrap(),
+        &webhook_request.url,
+        &secret,
+        &webhook_request.event_types,
+    )
+    .await
+    .unwrap();
+    info!("Registered webhook {} for {}", webhook.id, webhook.url);
+    HttpResponse::Created().json(CreatedWebhook { webhook, secret })
+}
+
+/// deletes the webhook along with its delivery log
+#[delete("/webhooks/{id}")]
+pub async fn delete_webhook(
+    path: web::Path<i32>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let deleted = webhook::delete_webhook(
+        &mut app_state.db_connection_pool.get().await.unwrap(),
+        path.into_inner(),
+    )
+    .await
+    .unwrap();
+    if deleted {
+        HttpResponse::NoContent()
+    } else {
+        HttpResponse::NotFound()
+    }
+}
+
+/// the delivery log of the webhook, the newest delivery first
+#[get("/webhooks/{id}/deliveries")]
+pub async fn get_webhook_deliveries(
+    path: web::Path<i32>,
+    query: web::Query<WebhookDeliveriesQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let limit = query
+        .limit
+        .unwrap_or(DELIVERIES_LIMIT_DEFAULT)
+        .clamp(1, DELIVERIES_LIMIT_MAX);
+    let results: Vec<WebhookDelivery> = webhook::list_deliveries(
+        &mut app_state.db_connection_pool.get().await.unwrap(),
+        path.into_inner(),
+        limit,
+    )
+    .await
+    .unwrap();
+    HttpResponse::Ok().json(results)
+}
+
+/// Delivers the queued lifecycle events to the webhooks. Deliveries are attempted as soon as the event is published,
+/// failed ones are retried with a growing delay. Replicas of the api share the queue, every delivery is sent by one of them
+pub async fn deliver_webhooks(app_state: web::Data<AppState>) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(
+            app_state.env_var_config.webhook_timeout_seconds,
+        ))
+        .build()
+        .expect("could not build http client");
+    let mut events = Box::pin(app_state.event_hub.subscribe());
+    let mut poll = actix_web::rt::time::interval(WEBHOOK_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = poll.tick() => (),
+            event = events.next() => match event {
+                Some(Ok(event)) if event.event.channel() != Channel::LifecycleEvents => continue,
+                // the missed events may have been lifecycle events
+                Some(_) => (),
+                None => return,
+            },
+        }
+        deliver_due(&app_state, &client).await;
+    }
+}
+
+/// sends the due deliveries until none are left
+async fn deliver_due(app_state: &AppState, client: &reqwest::Client) {
+    let config = &app_state.env_var_config;
+    let mut conn = match app_state.db_connection_pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!(
+                "Failed to dequeue webhook deliveries, encountered error {}",
+                e
+            );
+            return;
+        }
+    };
+    // an attempt interrupted by a restart is retried once it would have timed out twice
+    let processing_timeout_seconds = 2 * config.webhook_timeout_seconds as i64;
+    loop {
+        let (delivery, webhook) =
+            match webhook::dequeue_delivery(&mut conn, processing_timeout_seconds).await {
+                Ok(Some(dequeued)) => dequeued,
+                Ok(None) => return,
+                Err(e) => {
+                    error!(
+                        "Failed to dequeue webhook deliveries, encountered error {}",
+                        e
+                    );
+                    return;
+                }
+            };
+
+        let outcome = deliver(client, config, &delivery, &webhook).await;
+        match &outcome {
+            DeliveryOutcome::Delivered { .. } => info!(
+                "Delivered {} {} to webhook {}",
+                delivery.event_type, delivery.id, webhook.id
+            ),
+            DeliveryOutcome::Failed {
+                error, retry_at, ..
+            } => warn!(
+                "Failed to deliver {} {} to webhook {}, retrying at {:?}. Encountered error {}",
+                delivery.event_type, delivery.id, webhook.id, retry_at, error
+            ),
+        }
+        if let Err(e) = webhook::record_attempt(&mut conn, delivery.id, &outcome).await {
+            error!(
+                "Failed to record the attempt of delivery {}, encountered error {}",
+                delivery.id, e
+            );
+        }
+    }
+}
+
+/// posts the delivery to the webhook, signed with its secret
+async fn deliver(
+    client: &reqwest::Client,
+    config: &EnvVarConfigApi,
+    delivery: &WebhookDelivery,
+    webhook: &Webhook,
+) -> DeliveryOutcome {
+    let attempts = delivery.attempts + 1;
+    let event = match serde_json::from_str::<LifecycleEvent>(&delivery.payload) {
+        Ok(event) => event,
+        Err(e) => {
+            return DeliveryOutcome::Failed {
+                status_code: None,
+                error: format!("invalid payload: {}", e),
+                retry_at: None,
+            }
+        }
+    };
+    let body = serde_json::to_string(&WebhookPayload {
+        id: delivery.id,
+        event_type: &delivery.event_type,
+        created_at: delivery.created_at,
+        text: event.text(),
+        data: &event,
+    })
+    .expect("could not serialize webhook payload");
+    let timestamp = Utc::now().timestamp();
+
+    let response = client
+        .post(&webhook.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("X-Immortalis-Event", &delivery.event_type)
+        .header("X-Immortalis-Delivery", delivery.id.to_string())
+        .header("X-Immortalis-Timestamp", timestamp.to_string())
+        .header(
+            "X-Immortalis-Signature",
+            format!("sha256={}", sign(&webhook.secret, timestamp, &body)),
+        )
+        .body(body)
+        .send()
+        .await;
+    let (status_code, error) = match response {
+        Ok(response) if response.status().is_success() => {
+            return DeliveryOutcome::Delivered {
+                status_code: response.status().as_u16() as i32,
+            }
+        }
+        Ok(response) => (
+            Some(response.status().as_u16() as i32),
+            format!("responded with {}", response.status()),
+        ),
+        Err(e) => (None, e.to_string()),
+    };
+    DeliveryOutcome::Failed {
+        status_code,
+        error,
+        retry_at: (attempts < config.webhook_max_attempts).then(|| {
+            Utc::now()
+                + chrono::Duration::seconds(retry_delay_seconds(
+                    config.webhook_retry_delay_seconds,
+                    attempts,
+                ))
+        }),
+    }
+}
+
+/// the delay after the attempt, doubling with every attempt
+fn retry_delay_seconds(retry_delay_seconds: i64, attempts: i32) -> i64 {
+    retry_delay_seconds.saturating_mul(1 << (attempts - 1).clamp(0, 16))
+}
+
+/// Signs the timestamp and body with HMAC-SHA256, hex encoded. Receivers compute it over `{timestamp}.{body}` to verify a delivery
+/// and should reject old timestamps, so deliveries can't be replayed
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay_seconds, sign};
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("secret", 1_700_000_000, r#"{"id":1}"#),
+            "3dd1b9aef568d75f6790a84bd2e5dfa1f44409eef3cbdbd3f10b837376100c11"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay_seconds() {
+        assert_eq!(retry_delay_seconds(30, 1), 30);
+        assert_eq!(retry_delay_seconds(30, 2), 60);
+        assert_eq!(retry_delay_seconds(30, 4), 240);
+        assert_eq!(retry_delay_seconds(30, 100), 30 << 16);
+    }
+}
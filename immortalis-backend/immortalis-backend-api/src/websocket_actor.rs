@@ -1,26 +1,212 @@
-use actix::prelude::*;
-use actix::{Actor, Addr, Handler, StreamHandler};
+use actix::{Actor, ActorContext, ActorFutureExt, AsyncContext, StreamHandler, WrapFuture};
+use actix_web::web;
 use actix_web_actors::ws::{self};
+use immortalis_backend_common::env_var_config::SlowConsumerPolicy;
+use immortalis_backend_common::metrics::WEBSOCKET_CONNECTIONS;
 use std::collections::hash_map::HashMap;
-use std::sync::{Arc, RwLock};
-use tracing::info;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
-#[derive(Clone)]
+use crate::events::{replay, Replay};
+use crate::subscriptions::{
+    Channel, ClientMessage, PublishedEvent, ServerMessage, SubscriptionFilter,
+};
+use crate::AppState;
+
 pub struct WebSocketActor {
-    pub web_socket_connections: Arc<RwLock<HashMap<String, Addr<WebSocketActor>>>>,
+    app_state: web::Data<AppState>,
     id: Uuid,
+    /// the channels the client subscribed to, events of other channels are not sent
+    subscriptions: HashMap<Channel, SubscriptionFilter>,
+    /// channels whose replay is loading, their new events are held back until the replay has been sent
+    pending_replays: HashMap<Channel, PendingReplay>,
+    replay_count: u64,
+    /// the last time the client sent a message or answered a ping
+    last_heartbeat: Instant,
+}
+
+struct PendingReplay {
+    /// tells the replay apart from previous ones of the channel, if the client subscribed again meanwhile
+    replay_id: u64,
+    buffered_events: Vec<Arc<PublishedEvent>>,
 }
 
 impl WebSocketActor {
-    pub fn new(
-        web_socket_connections: Arc<RwLock<HashMap<String, Addr<WebSocketActor>>>>,
-    ) -> WebSocketActor {
+    pub fn new(app_state: web::Data<AppState>) -> WebSocketActor {
         WebSocketActor {
-            web_socket_connections,
+            app_state,
             id: Uuid::new_v4(),
+            subscriptions: HashMap::new(),
+            pending_replays: HashMap::new(),
+            replay_count: 0,
+            last_heartbeat: Instant::now(),
         }
     }
+
+    /// applies a message of the client and returns the acknowledgement, or why the message has been rejected
+    fn handle_client_message(&mut self, text: &str, ctx: &mut <Self as Actor>::Context) -> String {
+        let response = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(ClientMessage::Subscribe {
+                channel,
+                filter,
+                last_event_id,
+            }) => match filter.validate(channel) {
+                Ok(()) => {
+                    info!(
+                        "Connection {} subscribed to {} with {:?}",
+                        self.id,
+                        channel.name(),
+                        filter
+                    );
+                    self.subscriptions.insert(channel, filter);
+                    self.pending_replays.remove(&channel);
+                    if let Some(last_event_id) = last_event_id {
+                        self.start_replay(channel, last_event_id, ctx);
+                    }
+                    serde_json::to_string(&ServerMessage::Subscribed {
+                        channel,
+                        filter: &self.subscriptions[&channel],
+                    })
+                }
+                Err(message) => serde_json::to_string(&ServerMessage::Error { message }),
+            },
+            Ok(ClientMessage::Unsubscribe { channel }) => {
+                self.pending_replays.remove(&channel);
+                if self.subscriptions.remove(&channel).is_some() {
+                    serde_json::to_string(&ServerMessage::Unsubscribed { channel })
+                } else {
+                    serde_json::to_string(&ServerMessage::Error {
+                        message: format!("not subscribed to {}", channel.name()),
+                    })
+                }
+            }
+            Err(e) => serde_json::to_string(&ServerMessage::Error {
+                message: format!("invalid message: {}", e),
+            }),
+        };
+        response.expect("could not serialize acknowledgement")
+    }
+
+    /// loads the events of the channel stored after last_event_id and sends them after the acknowledgement of the subscription
+    fn start_replay(
+        &mut self,
+        channel: Channel,
+        last_event_id: i64,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        self.replay_count += 1;
+        let replay_id = self.replay_count;
+        self.pending_replays.insert(
+            channel,
+            PendingReplay {
+                replay_id,
+                buffered_events: vec![],
+            },
+        );
+
+        let app_state = self.app_state.clone();
+        ctx.spawn(
+            async move {
+                let mut conn = app_state
+                    .db_connection_pool
+                    .get()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                replay(&mut conn, last_event_id, &[channel])
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            .into_actor(self)
+            .map(move |result, actor, ctx| actor.finish_replay(channel, replay_id, result, ctx)),
+        );
+    }
+
+    fn finish_replay(
+        &mut self,
+        channel: Channel,
+        replay_id: u64,
+        result: Result<Replay, String>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        // the client unsubscribed or subscribed again in the meantime
+        let pending = match self.pending_replays.remove(&channel) {
+            Some(pending) if pending.replay_id == replay_id => pending,
+            Some(pending) => {
+                self.pending_replays.insert(channel, pending);
+                return;
+            }
+            None => return,
+        };
+
+        let mut last_sent_id = None;
+        match result {
+            Ok(replay) => {
+                for event in &replay.events {
+                    self.send_event(event, ctx);
+                    last_sent_id = Some(event.id);
+                }
+                if !replay.complete {
+                    self.send_reset(channel, ctx);
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Failed to replay {} for connection {}, encountered error {}",
+                    channel.name(),
+                    self.id,
+                    e
+                );
+                self.send_reset(channel, ctx);
+            }
+        }
+        // events published while the replay was loading may have been replayed already
+        for event in &pending.buffered_events {
+            if last_sent_id.map_or(true, |id| event.id > id) {
+                self.send_event(event, ctx);
+            }
+        }
+    }
+
+    /// sends the event if it matches the subscription of its channel
+    fn send_event(&self, event: &PublishedEvent, ctx: &mut <Self as Actor>::Context) {
+        let channel = event.event.channel();
+        let Some(filter) = self.subscriptions.get(&channel) else {
+            return;
+        };
+        if event.event.matches(filter) {
+            ctx.text(
+                serde_json::to_string(&ServerMessage::Event {
+                    id: event.id,
+                    channel,
+                    data: &event.event,
+                })
+                .expect("could not serialize event"),
+            );
+        }
+    }
+
+    fn send_reset(&self, channel: Channel, ctx: &mut <Self as Actor>::Context) {
+        ctx.text(
+            serde_json::to_string(&ServerMessage::Reset { channel })
+                .expect("could not serialize acknowledgement"),
+        );
+    }
+
+    /// pings the client, or disconnects it if it didn't answer the previous pings
+    fn heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
+        let config = &self.app_state.env_var_config;
+        if self.last_heartbeat.elapsed()
+            > Duration::from_secs(config.websocket_client_timeout_seconds)
+        {
+            info!("Connection {} timed out", self.id);
+            ctx.stop();
+            return;
+        }
+        ctx.ping(b"");
+    }
 }
 
 impl Actor for WebSocketActor {
@@ -28,26 +214,53 @@ impl Actor for WebSocketActor {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         info!("Actor started for id {}", self.id);
-        self.web_socket_connections
-            .write()
-            .unwrap()
-            .insert(self.id.to_string(), ctx.address());
+        WEBSOCKET_CONNECTIONS.inc();
+        ctx.add_stream(self.app_state.event_hub.subscribe());
+        let heartbeat_interval = self
+            .app_state
+            .env_var_config
+            .websocket_heartbeat_interval_seconds;
+        ctx.run_interval(Duration::from_secs(heartbeat_interval), |actor, ctx| {
+            actor.heartbeat(ctx)
+        });
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
-        self.web_socket_connections
-            .write()
-            .unwrap()
-            .remove(&self.id.to_string());
         info!("Actor stopped for id {}", self.id);
+        WEBSOCKET_CONNECTIONS.dec();
     }
 }
 
-impl Handler<Message> for WebSocketActor {
-    type Result = ();
+/// Handler for the events of the hub, which are forwarded if they match one of the subscriptions
+impl StreamHandler<Result<Arc<PublishedEvent>, RecvError>> for WebSocketActor {
+    fn handle(&mut self, event: Result<Arc<PublishedEvent>, RecvError>, ctx: &mut Self::Context) {
+        let event = match event {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Connection {} missed {} events", self.id, missed);
+                match self.app_state.env_var_config.websocket_slow_consumer_policy {
+                    SlowConsumerPolicy::Drop => ctx.text(
+                        serde_json::to_string(&ServerMessage::Lagged { missed })
+                            .expect("could not serialize acknowledgement"),
+                    ),
+                    SlowConsumerPolicy::Disconnect => {
+                        ctx.close(Some(ws::CloseReason {
+                            code: ws::CloseCode::Policy,
+                            description: Some("too slow to receive events".to_string()),
+                        }));
+                        ctx.stop();
+                    }
+                }
+                return;
+            }
+            // the stream ends instead
+            Err(RecvError::Closed) => return,
+        };
 
-    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(msg.0);
+        match self.pending_replays.get_mut(&event.event.channel()) {
+            Some(pending) => pending.buffered_events.push(event),
+            None => self.send_event(&event, ctx),
+        }
     }
 }
 
@@ -55,15 +268,27 @@ impl Handler<Message> for WebSocketActor {
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketActor {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
-            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
-            Ok(ws::Message::Text(text)) => ctx.text(text),
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
+            Ok(ws::Message::Ping(msg)) => {
+                self.last_heartbeat = Instant::now();
+                ctx.pong(&msg);
+            }
+            Ok(ws::Message::Pong(_)) => self.last_heartbeat = Instant::now(),
+            Ok(ws::Message::Text(text)) => {
+                self.last_heartbeat = Instant::now();
+                let response = self.handle_client_message(&text, ctx);
+                ctx.text(response);
+            }
+            Ok(ws::Message::Binary(_)) => ctx.text(
+                serde_json::to_string(&ServerMessage::Error {
+                    message: "messages have to be sent as text".to_string(),
+                })
+                .expect("could not serialize acknowledgement"),
+            ),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
             _ => (),
         }
     }
 }
-
-/// Chat server sends this messages to session
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct Message(pub String);
@@ -0,0 +1,1064 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::QueryDsl;
+use diesel::{delete, insert_into, update, ExpressionMethods, OptionalExtension};
+use diesel_async::pooled_connection::deadpool::Pool;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::archival_stage::ArchivalStage;
+use immortalis_backend_common::database_models::blob::Blob;
+use immortalis_backend_common::database_models::file::File;
+use immortalis_backend_common::database_models::lease::{self, LeaseLimits};
+use immortalis_backend_common::database_models::live_status::LiveStatus;
+use immortalis_backend_common::database_models::scheduled_archival::ScheduledArchival;
+use immortalis_backend_common::database_models::video::{InsertableVideo, Video};
+use immortalis_backend_common::database_models::video_status::{
+    self, TransitionError, VideoStatus,
+};
+use immortalis_backend_common::database_models::worker;
+use immortalis_backend_common::database_models::worker_kind::WorkerKind;
+use immortalis_backend_common::downloader::{Downloader, DownloaderError};
+use immortalis_backend_common::env_var_config::EnvVarConfigArchiver;
+use immortalis_backend_common::lifecycle_event::{self, LifecycleEvent};
+use immortalis_backend_common::metrics::{
+    ArchivalOutcome, ARCHIVAL_DURATION, DEQUEUE_DURATION, DOWNLOADED_BYTES, QUEUE_WAIT,
+};
+use immortalis_backend_common::schema::{files, scheduled_archivals, videos};
+use immortalis_backend_common::storage::content_addressed::{self, BlobError};
+use immortalis_backend_common::storage::{reconciliation, Storage};
+use immortalis_backend_common::telemetry;
+
+use tracing::{error, info, info_span, warn, Instrument};
+
+/// reconciles the files with the storage. Anything younger than archiver_archiving_timeout_seconds may still be in progress and is left alone
+pub async fn reconcile_storage(
+    pool: Pool<AsyncPgConnection>,
+    env_var_config: Arc<EnvVarConfigArchiver>,
+    storage: Arc<dyn Storage>,
+) {
+    let db_connection = &mut match pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Encountered Database error: {}", e);
+            return;
+        }
+    };
+
+    match reconciliation::reconcile(
+        db_connection,
+        storage.as_ref(),
+        &env_var_config.storage_config.temp_file_storage_location,
+        Duration::seconds(env_var_config.archiver_archiving_timeout_seconds),
+        env_var_config.archiver_reconciliation_repair,
+    )
+    .await
+    {
+        Ok(Some(_)) => (),
+        Ok(None) => info!("Skipped reconciliation, another process is reconciling"),
+        Err(e) => error!("Reconciliation failed, encountered error {}", e),
+    }
+}
+
+/// Registers the workers or updates their heartbeat. Dead workers of all replicas are removed, so their archivals are requeued.
+/// Returns false if the heartbeat couldn't be sent
+pub async fn heartbeat(
+    pool: Pool<AsyncPgConnection>,
+    env_var_config: Arc<EnvVarConfigArchiver>,
+    worker_ids: &[uuid::Uuid],
+    host: &str,
+) -> bool {
+    let db_connection = &mut match pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Encountered Database error: {}", e);
+            return false;
+        }
+    };
+
+    if let Err(e) = worker::heartbeat(
+        db_connection,
+        worker_ids,
+        host,
+        WorkerKind::Archiver,
+        env!("CARGO_PKG_VERSION"),
+    )
+    .await
+    {
+        error!("Failed to send heartbeat, encountered error {}", e);
+        return false;
+    }
+
+    match worker::remove_dead_workers(
+        db_connection,
+        Duration::seconds(env_var_config.archiver_worker_timeout_seconds),
+    )
+    .await
+    {
+        Ok(0) => (),
+        Ok(removed) => warn!(
+            "Removed {} dead workers, their archivals have been requeued",
+            removed
+        ),
+        Err(e) => error!("Failed to remove dead workers, encountered error {}", e),
+    }
+    true
+}
+
+/// returns true if a video has been archived, returns false if there were no schedules, the limits didn't allow another archival or an error occured
+pub async fn archive(
+    pool: Pool<AsyncPgConnection>,
+    env_var_config: Arc<EnvVarConfigArchiver>,
+    storage: Arc<dyn Storage>,
+    downloader: Arc<dyn Downloader>,
+    worker_id: uuid::Uuid,
+) -> bool {
+    // try getting db connection, retry if it fails
+    let db_connection = &mut loop {
+        match pool.get().await {
+            Ok(c) => break c,
+            Err(e) => {
+                error!("Encountered Database error: {}", e);
+                tokio::time::sleep(tokio::time::Duration::from_secs(
+                    env_var_config.archiver_idle_sleep_seconds,
+                ))
+                .await;
+                continue;
+            }
+        }
+    };
+
+    let limits = LeaseLimits {
+        max_concurrent_archivals: env_var_config.archiver_max_concurrent_archivals,
+        max_concurrent_archivals_per_domain: env_var_config
+            .archiver_max_concurrent_archivals_per_domain,
+    };
+    let dequeue_start = Instant::now();
+    let dequeued = lease::dequeue(
+        db_connection,
+        worker_id,
+        limits,
+        env_var_config.archiver_archiving_timeout_seconds,
+    )
+    .await;
+    DEQUEUE_DURATION.observe(dequeue_start.elapsed().as_secs_f64());
+    let scheduled_archival = match dequeued {
+        Ok(x) => match x {
+            Some(scheduled_archival) => {
+                // retries are due once not_before has passed
+                let due_at = scheduled_archival
+                    .scheduled_at
+                    .max(scheduled_archival.not_before);
+                let wait = (Utc::now() - due_at).to_std().unwrap_or_default();
+                QUEUE_WAIT.observe(wait.as_secs_f64());
+                info!(
+                    scheduled_archival.id = scheduled_archival.id,
+                    scheduled_archival.url = scheduled_archival.url,
+                    "Dequeued entry: {} with url: {}",
+                    scheduled_archival.id,
+                    scheduled_archival.url
+                );
+                scheduled_archival
+            }
+            None => {
+                info!("No ScheduledArchivals found or the concurrency limits are reached");
+                return false;
+            }
+        },
+        Err(x) => {
+            error!(
+                "Failed to Dequeue ScheduledArchival, encountered error {}",
+                x
+            );
+            return false;
+        }
+    };
+
+    if let Err(e) =
+        worker::set_current_job(db_connection, worker_id, Some(&scheduled_archival.url)).await
+    {
+        warn!("Failed to set current job, encountered error {}", e);
+    }
+
+    // continues the trace of the request which scheduled the archival
+    let span = info_span!(
+        "archive",
+        scheduled_archival.id = scheduled_archival.id,
+        scheduled_archival.url = scheduled_archival.url,
+        archived = tracing::field::Empty,
+    );
+    if let Some(trace_context) = &scheduled_archival.trace_context {
+        telemetry::set_parent(&span, trace_context);
+    }
+    let archival_start = Instant::now();
+    let archived = archive_scheduled(
+        db_connection,
+        &env_var_config,
+        storage.as_ref(),
+        downloader.as_ref(),
+        &scheduled_archival,
+    )
+    .instrument(span.clone())
+    .await;
+    span.record("archived", archived);
+    ARCHIVAL_DURATION
+        .with_label_values(&[if archived { "true" } else { "false" }])
+        .observe(archival_start.elapsed().as_secs_f64());
+
+    // a completed schedule has been deleted along with its lease already
+    if let Err(e) = lease::release(db_connection, worker_id, scheduled_archival.id).await {
+        error!("Failed to release lease, encountered error {}", e);
+    }
+    if let Err(e) = worker::set_current_job(db_connection, worker_id, None).await {
+        warn!("Failed to clear current job, encountered error {}", e);
+    }
+    archived
+}
+
+/// archives the dequeued schedule, resuming at the stage the last attempt reached
+async fn archive_scheduled(
+    db_connection: &mut AsyncPgConnection,
+    env_var_config: &EnvVarConfigArchiver,
+    storage: &dyn Storage,
+    downloader: &dyn Downloader,
+    scheduled_archival: &ScheduledArchival,
+) -> bool {
+    // the stream has been recorded already, only its VOD is missing
+    if let (ArchivalStage::AwaitingVod, Some(video_id)) =
+        (scheduled_archival.stage, scheduled_archival.video_id)
+    {
+        return refresh_vod(
+            db_connection,
+            env_var_config,
+            storage,
+            downloader,
+            scheduled_archival,
+            video_id,
+        )
+        .await;
+    }
+
+    // resume at the stage the last attempt reached
+    let video_id = match (scheduled_archival.stage, scheduled_archival.video_id) {
+        (ArchivalStage::Registered | ArchivalStage::Downloaded, Some(video_id)) => video_id,
+        _ => match register_video(
+            db_connection,
+            env_var_config,
+            storage,
+            downloader,
+            scheduled_archival,
+        )
+        .await
+        {
+            Some(video_id) => video_id,
+            None => return false,
+        },
+    };
+
+    let video = videos::table
+        .find(video_id)
+        .first::<Video>(db_connection)
+        .await
+        .unwrap();
+
+    // an earlier attempt may have failed, the video is being archived again now
+    if video.status == VideoStatus::ArchivationFailed {
+        if let Err(e) =
+            video_status::transition(db_connection, video.id, VideoStatus::BeingArchived).await
+        {
+            error!("Failed to resume archival, encountered error {}", e);
+            return false;
+        }
+    }
+
+    if scheduled_archival.stage != ArchivalStage::Downloaded
+        && !store_video(
+            db_connection,
+            env_var_config,
+            storage,
+            downloader,
+            scheduled_archival,
+            &video,
+            video.is_live_recording,
+        )
+        .await
+    {
+        // the schedule is kept, so the archival is attempted again once archiver_archiving_timeout_seconds have passed
+        if let Err(e) =
+            video_status::transition(db_connection, video.id, VideoStatus::ArchivationFailed).await
+        {
+            error!("Failed to mark archival as failed, encountered error {}", e);
+        }
+        record_failed_attempt(
+            db_connection,
+            env_var_config,
+            scheduled_archival,
+            Some(video.id),
+            None,
+        )
+        .await;
+        return false;
+    }
+
+    complete_archival(
+        db_connection,
+        env_var_config,
+        downloader,
+        scheduled_archival,
+        &video,
+    )
+    .await;
+    true
+}
+
+/// Fetches the metadata and registers the video along with its thumbnail and video file, then advances the schedule to Registered. All database writes happen in a single transaction.
+/// If an earlier attempt registered the video already, it is resumed instead. Returns None if the metadata couldn't be fetched, the video has been archived already or registering failed
+#[tracing::instrument(skip_all)]
+async fn register_video(
+    db_connection: &mut AsyncPgConnection,
+    env_var_config: &EnvVarConfigArchiver,
+    storage: &dyn Storage,
+    downloader: &dyn Downloader,
+    scheduled_archival: &ScheduledArchival,
+) -> Option<i32> {
+    let yt_video_result = downloader.fetch_metadata(&scheduled_archival.url).await;
+
+    // on error, schedule retry and return early;
+    if let Err(e) = &yt_video_result {
+        let unavailable_reason = match e {
+            DownloaderError::Unavailable(reason) => Some(reason.clone()),
+            _ => None,
+        };
+        warn!(
+            "Received error {:#?}. Video {} will be retried in {} seconds",
+            yt_video_result, scheduled_archival.url, env_var_config.archiver_error_backoff_seconds
+        );
+        retry_registration(
+            db_connection,
+            env_var_config,
+            scheduled_archival,
+            unavailable_reason,
+        )
+        .await;
+        return None;
+    }
+
+    let metadata = yt_video_result.unwrap();
+    let (Some(webpage_url), Some(thumbnail_url)) = (
+        metadata.video.webpage_url.clone(),
+        metadata.video.thumbnail.clone(),
+    ) else {
+        error!(
+            "The metadata of {} has no webpage_url or thumbnail, it will be retried in {} seconds",
+            scheduled_archival.url, env_var_config.archiver_error_backoff_seconds
+        );
+        retry_registration(db_connection, env_var_config, scheduled_archival, None).await;
+        return None;
+    };
+
+    // an earlier attempt may have registered the video before it was interrupted
+    match videos::table
+        .filter(videos::original_url.eq(&webpage_url))
+        .first::<Video>(db_connection)
+        .await
+        .optional()
+    {
+        Ok(Some(video)) => {
+            return resume_registered(db_connection, scheduled_archival, video).await
+        }
+        Ok(None) => (),
+        Err(e) => {
+            error!(
+                "Failed to look up video {}, encountered error {}",
+                webpage_url, e
+            );
+            return None;
+        }
+    }
+
+    // upcoming streams and premieres are archived once they start, so no worker has to wait for them
+    match metadata.live_status {
+        Some(LiveStatus::IsUpcoming) => {
+            let not_before = metadata
+                .scheduled_start()
+                .filter(|start| *start > Utc::now())
+                .unwrap_or_else(|| {
+                    Utc::now() + Duration::seconds(env_var_config.archiver_upcoming_poll_seconds)
+                });
+            info!(
+                "{} has not started yet and will be archived at {}",
+                scheduled_archival.url, not_before
+            );
+            postpone(db_connection, scheduled_archival, not_before).await;
+            return None;
+        }
+        Some(LiveStatus::PostLive) => {
+            info!(
+                "{} is over, but its VOD has not been processed yet",
+                scheduled_archival.url
+            );
+            postpone(
+                db_connection,
+                scheduled_archival,
+                Utc::now() + Duration::seconds(env_var_config.archiver_vod_refresh_delay_seconds),
+            )
+            .await;
+            return None;
+        }
+        _ => (),
+    }
+
+    let (thumbnail, thumbnail_extension) = match downloader.fetch_thumbnail(&thumbnail_url).await {
+        Ok(thumbnail) => thumbnail,
+        Err(e) => {
+            error!(
+                "Failed to fetch thumbnail of {}, encountered error {}",
+                scheduled_archival.url, e
+            );
+            return None;
+        }
+    };
+
+    // get file_size from youtube (exact or if its unknown then aprox). This value will be replaced by the actual size of the file after the download
+    let file_size = metadata
+        .video
+        .filesize
+        .unwrap_or(metadata.video.filesize_approx.unwrap_or(0.0) as i64);
+
+    let thumbnail_id = uuid::Uuid::new_v4();
+    let file_id = uuid::Uuid::new_v4();
+    let video = InsertableVideo {
+        tracked_collection_id: scheduled_archival.tracked_collection_id,
+        ..InsertableVideo::new(metadata, VideoStatus::BeingArchived, file_id, thumbnail_id)
+    };
+
+    // None if the video has been registered concurrently
+    let result = db_connection
+        .transaction::<Option<i32>, BlobError, _>(|db_connection| {
+            async move {
+                let thumbnail_blob =
+                    content_addressed::store_bytes(db_connection, storage, &thumbnail).await?;
+                let thumbnail_checksum = thumbnail_blob.checksum;
+
+                // insert file for thumbnail
+                insert_into(files::table)
+                    .values(File {
+                        id: thumbnail_id,
+                        file_name: video.title.to_string(),
+                        file_extension: thumbnail_extension,
+                        size: thumbnail_blob.size,
+                        checksum: Some(thumbnail_checksum.clone()),
+                    })
+                    .execute(db_connection)
+                    .await?;
+
+                // insert file for video
+                insert_into(files::table)
+                    .values(File {
+                        id: file_id,
+                        file_name: video.title.to_string(),
+                        file_extension: "mkv".to_string(),
+                        size: file_size,
+                        checksum: None,
+                    })
+                    .execute(db_connection)
+                    .await?;
+
+                // on a conflict the files are removed again instead of rolling back, which would orphan the stored thumbnail
+                let Some(video_id) = insert_into(videos::table)
+                    .values(&video)
+                    .on_conflict_do_nothing()
+                    .returning(videos::id)
+                    .get_result::<i32>(db_connection)
+                    .await
+                    .optional()?
+                else {
+                    delete(files::table)
+                        .filter(files::id.eq_any([thumbnail_id, file_id]))
+                        .execute(db_connection)
+                        .await?;
+                    content_addressed::release_blob(db_connection, storage, &thumbnail_checksum)
+                        .await?;
+                    return Ok(None);
+                };
+
+                update(scheduled_archivals::table)
+                    .set((
+                        scheduled_archivals::stage.eq(ArchivalStage::Registered),
+                        scheduled_archivals::video_id.eq(video_id),
+                    ))
+                    .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+                    .execute(db_connection)
+                    .await?;
+                Ok(Some(video_id))
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    match result {
+        Ok(Some(video_id)) => Some(video_id),
+        Ok(None) => {
+            info!("{} has been registered concurrently", webpage_url);
+            match videos::table
+                .filter(videos::original_url.eq(&webpage_url))
+                .first::<Video>(db_connection)
+                .await
+            {
+                Ok(video) => resume_registered(db_connection, scheduled_archival, video).await,
+                Err(e) => {
+                    error!(
+                        "Failed to look up video {}, encountered error {}",
+                        webpage_url, e
+                    );
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            error!(
+                "Failed to register video {}, encountered error {}",
+                scheduled_archival.url, e
+            );
+            None
+        }
+    }
+}
+
+/// Downloads the video and stores it. Updating its file and advancing the schedule to Downloaded happens in a single transaction.
+/// live_recording marks whether the stored file is a recording of the live stream or the processed VOD. Returns false if the download couldn't be stored
+#[tracing::instrument(skip_all)]
+async fn store_video(
+    db_connection: &mut AsyncPgConnection,
+    env_var_config: &EnvVarConfigArchiver,
+    storage: &dyn Storage,
+    downloader: &dyn Downloader,
+    scheduled_archival: &ScheduledArchival,
+    video: &Video,
+    live_recording: bool,
+) -> bool {
+    // if simulate_download is false, we perform the actual download, otherwise we wait for simulated_download_duration_seconds
+    if env_var_config.simulate_download {
+        tokio::time::sleep(tokio::time::Duration::from_secs(
+            env_var_config.simulated_download_duration_seconds,
+        ))
+        .await;
+        update(scheduled_archivals::table)
+            .set(scheduled_archivals::stage.eq(ArchivalStage::Downloaded))
+            .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+            .execute(db_connection)
+            .await
+            .unwrap();
+        return true;
+    }
+
+    // a previous attempt may have stored a download of this video already, which is replaced
+    let previous_checksum = files::table
+        .find(video.file_id)
+        .select(files::checksum)
+        .first::<Option<String>>(db_connection)
+        .await
+        .unwrap();
+
+    // the path only depends on the file_id, so an interrupted download is continued by the next attempt
+    let file_name = env_var_config
+        .storage_config
+        .temp_file_storage_location
+        .join(format!("{}.mkv", video.file_id));
+
+    // the bandwidth is split between the downloads running when this one starts
+    let limit_rate = match lease::bandwidth_share(
+        db_connection,
+        env_var_config.archiver_total_bandwidth_bytes_per_second,
+    )
+    .await
+    {
+        Ok(limit_rate) => limit_rate,
+        Err(e) => {
+            error!(
+                "Failed to determine the bandwidth of {}, encountered error {}",
+                scheduled_archival.url, e
+            );
+            return false;
+        }
+    };
+    let partial_download = PartialDownload::new(&file_name);
+    let downloaded = downloader
+        .download(&scheduled_archival.url, &file_name, limit_rate)
+        .await;
+    partial_download.keep();
+    if let Err(e) = downloaded {
+        error!(
+            "Failed to download video {}, encountered error {}",
+            scheduled_archival.url, e
+        );
+        return false;
+    }
+
+    let file_id = video.file_id;
+    let video_id = video.id;
+    let downloaded_file = file_name.as_path();
+    let result = db_connection
+        .transaction::<Blob, BlobError, _>(|db_connection| {
+            async move {
+                let blob =
+                    content_addressed::store_file(db_connection, storage, downloaded_file).await?;
+
+                // update video file size and checksum after download
+                update(files::table)
+                    .set((
+                        files::size.eq(blob.size),
+                        files::checksum.eq(&blob.checksum),
+                    ))
+                    .filter(files::id.eq(file_id))
+                    .execute(db_connection)
+                    .await?;
+
+                update(videos::table)
+                    .set(videos::is_live_recording.eq(live_recording))
+                    .filter(videos::id.eq(video_id))
+                    .execute(db_connection)
+                    .await?;
+
+                update(scheduled_archivals::table)
+                    .set(scheduled_archivals::stage.eq(ArchivalStage::Downloaded))
+                    .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+                    .execute(db_connection)
+                    .await?;
+                Ok(blob)
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    let blob = match result {
+        Ok(blob) => blob,
+        Err(e) => {
+            error!(
+                "Failed to store video {}, encountered error {}",
+                scheduled_archival.url, e
+            );
+            return false;
+        }
+    };
+
+    DOWNLOADED_BYTES.inc_by(blob.size as u64);
+    // the download is kept until it has been stored, a rolled back attempt continues from it
+    if let Err(e) = tokio::fs::remove_file(&file_name).await {
+        warn!(
+            "Failed to remove downloaded file {}, encountered error {}",
+            file_name.display(),
+            e
+        );
+    }
+
+    // the previous blob is only released once the new one is committed
+    if let Some(previous_checksum) = previous_checksum.filter(|c| *c != blob.checksum) {
+        if let Err(e) =
+            content_addressed::release_blob(db_connection, storage, &previous_checksum).await
+        {
+            error!(
+                "Failed to release replaced blob {}, encountered error {}",
+                previous_checksum, e
+            );
+        }
+    }
+    true
+}
+
+/// The files of a download in progress, which are removed if the archival is cancelled by a shutdown.
+/// Another replica may continue the schedule, it couldn't resume from the files of this one
+struct PartialDownload {
+    directory: PathBuf,
+    /// yt-dlp names the files of formats and fragments after the destination
+    prefix: String,
+    keep: bool,
+}
+
+impl PartialDownload {
+    fn new(destination: &Path) -> PartialDownload {
+        PartialDownload {
+            directory: destination
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            prefix: format!(
+                "{}.",
+                destination
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+            ),
+            keep: false,
+        }
+    }
+
+    /// the download has finished or failed on its own, a later attempt continues from its files
+    fn keep(mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for PartialDownload {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(&self.directory) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(&self.prefix)
+            {
+                match std::fs::remove_file(entry.path()) {
+                    Ok(()) => info!("Removed partial download {}", entry.path().display()),
+                    Err(e) => warn!(
+                        "Failed to remove partial download {}, encountered error {}",
+                        entry.path().display(),
+                        e
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Marks the video as archived and deletes the schedule in a single transaction. Only the row of this video is updated.
+/// The duration of a livestream is 0 while it is live, so its metadata is reloaded to get the final duration.
+/// If only a recording of the live stream could be stored, the schedule is kept in AwaitingVod to replace the recording with the VOD later
+#[tracing::instrument(skip_all)]
+async fn complete_archival(
+    db_connection: &mut AsyncPgConnection,
+    env_var_config: &EnvVarConfigArchiver,
+    downloader: &dyn Downloader,
+    scheduled_archival: &ScheduledArchival,
+    video: &Video,
+) {
+    let metadata =
+        if (video.duration == 0 || video.is_live_recording) && !env_var_config.simulate_download {
+            match downloader.fetch_metadata(&scheduled_archival.url).await {
+                Ok(metadata) => Some(metadata),
+                Err(e) => {
+                    warn!(
+                        "Failed to reload metadata of {}, encountered error {}",
+                        scheduled_archival.url, e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+    let video_duration = metadata
+        .as_ref()
+        .and_then(|m| m.video.duration.as_ref())
+        .and_then(|d| d.as_f64())
+        .map(|d| d as i32);
+    let live_status = metadata.as_ref().and_then(|m| m.live_status);
+    // the recording may have been cut off while the stream is still live, refresh_vod waits until the VOD is available
+    let awaiting_vod = video.is_live_recording;
+
+    let result = db_connection
+        .transaction::<(), TransitionError, _>(|db_connection| {
+            async move {
+                // a refreshed VOD replaces the recording of a video which is archived already
+                if video.status != VideoStatus::Archived {
+                    video_status::transition(db_connection, video.id, VideoStatus::Archived)
+                        .await?;
+                    lifecycle_event::publish(
+                        db_connection,
+                        &LifecycleEvent::VideoArchived {
+                            video_id: video.id,
+                            url: video.original_url.clone(),
+                            title: video.title.clone(),
+                            tracked_collection_id: video.tracked_collection_id,
+                        },
+                    )
+                    .await?;
+                }
+
+                if let Some(video_duration) = video_duration {
+                    update(videos::table)
+                        .set(videos::duration.eq(video_duration))
+                        .filter(videos::id.eq(video.id))
+                        .execute(db_connection)
+                        .await?;
+                }
+
+                if let Some(live_status) = live_status {
+                    update(videos::table)
+                        .set(videos::live_status.eq(live_status))
+                        .filter(videos::id.eq(video.id))
+                        .execute(db_connection)
+                        .await?;
+                }
+
+                if awaiting_vod {
+                    update(scheduled_archivals::table)
+                        .set((
+                            scheduled_archivals::stage.eq(ArchivalStage::AwaitingVod),
+                            scheduled_archivals::not_before.eq(Utc::now()
+                                + Duration::seconds(
+                                    env_var_config.archiver_vod_refresh_delay_seconds,
+                                )),
+                        ))
+                        .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+                        .execute(db_connection)
+                        .await?;
+                } else {
+                    // delete the schedule once archival is completed
+                    delete(scheduled_archivals::table)
+                        .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+                        .execute(db_connection)
+                        .await?;
+                }
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    match result {
+        Ok(()) => ArchivalOutcome::Archived.record(),
+        Err(e) => error!(
+            "Failed to complete archival of {}, encountered error {}",
+            scheduled_archival.url, e
+        ),
+    }
+}
+
+/// Replaces the recording of a live stream with the processed VOD once youtube has finished processing it.
+/// While the stream is still live or its VOD is being processed, the schedule is postponed by archiver_vod_refresh_delay_seconds
+#[tracing::instrument(skip_all)]
+async fn refresh_vod(
+    db_connection: &mut AsyncPgConnection,
+    env_var_config: &EnvVarConfigArchiver,
+    storage: &dyn Storage,
+    downloader: &dyn Downloader,
+    scheduled_archival: &ScheduledArchival,
+    video_id: i32,
+) -> bool {
+    let retry_at =
+        Utc::now() + Duration::seconds(env_var_config.archiver_vod_refresh_delay_seconds);
+
+    match downloader.fetch_metadata(&scheduled_archival.url).await {
+        Ok(metadata)
+            if matches!(
+                metadata.live_status,
+                Some(LiveStatus::WasLive | LiveStatus::NotLive)
+            ) => {}
+        Ok(_) => {
+            info!(
+                "The VOD of {} is not available yet, retrying at {}",
+                scheduled_archival.url, retry_at
+            );
+            postpone(db_connection, scheduled_archival, retry_at).await;
+            return false;
+        }
+        Err(e) => {
+            warn!(
+                "Failed to fetch metadata of {}, encountered error {}. Retrying at {}",
+                scheduled_archival.url, e, retry_at
+            );
+            postpone(db_connection, scheduled_archival, retry_at).await;
+            return false;
+        }
+    }
+
+    let video = videos::table
+        .find(video_id)
+        .first::<Video>(db_connection)
+        .await
+        .unwrap();
+
+    info!(
+        "Replacing the live recording of {} with its VOD",
+        video.original_url
+    );
+    if !store_video(
+        db_connection,
+        env_var_config,
+        storage,
+        downloader,
+        scheduled_archival,
+        &video,
+        false,
+    )
+    .await
+    {
+        // the recording stays available, the VOD is tried again later
+        postpone(db_connection, scheduled_archival, retry_at).await;
+        return false;
+    }
+
+    let video = Video {
+        is_live_recording: false,
+        ..video
+    };
+    complete_archival(
+        db_connection,
+        env_var_config,
+        downloader,
+        scheduled_archival,
+        &video,
+    )
+    .await;
+    true
+}
+
+/// continues with a video registered by an earlier attempt or another worker. Returns None if it has been archived already, its schedule is deleted then
+async fn resume_registered(
+    db_connection: &mut AsyncPgConnection,
+    scheduled_archival: &ScheduledArchival,
+    video: Video,
+) -> Option<i32> {
+    if video.status == VideoStatus::Archived {
+        info!(
+            "{} has already been archived, deleting its schedule",
+            video.original_url
+        );
+        if let Err(e) = delete(scheduled_archivals::table)
+            .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+            .execute(db_connection)
+            .await
+        {
+            error!("Failed to delete schedule, encountered error {}", e);
+        }
+        return None;
+    }
+
+    info!("Resuming archival of {}", video.original_url);
+    match update(scheduled_archivals::table)
+        .set((
+            scheduled_archivals::stage.eq(ArchivalStage::Registered),
+            scheduled_archivals::video_id.eq(video.id),
+        ))
+        .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+        .execute(db_connection)
+        .await
+    {
+        Ok(_) => Some(video.id),
+        Err(e) => {
+            error!("Failed to resume archival, encountered error {}", e);
+            None
+        }
+    }
+}
+
+/// counts a failed attempt at registering the video, the schedule is retried after archiver_error_backoff_seconds unless it has been given up
+async fn retry_registration(
+    db_connection: &mut AsyncPgConnection,
+    env_var_config: &EnvVarConfigArchiver,
+    scheduled_archival: &ScheduledArchival,
+    unavailable_reason: Option<String>,
+) {
+    if record_failed_attempt(
+        db_connection,
+        env_var_config,
+        scheduled_archival,
+        scheduled_archival.video_id,
+        unavailable_reason,
+    )
+    .await
+    {
+        return;
+    }
+    if let Err(e) = update(scheduled_archivals::table)
+        .set(
+            scheduled_archivals::not_before
+                .eq(Utc::now() + Duration::seconds(env_var_config.archiver_error_backoff_seconds)),
+        )
+        .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+        .execute(db_connection)
+        .await
+    {
+        error!("Failed to postpone schedule, encountered error {}", e);
+    }
+}
+
+/// Counts a failed attempt of the schedule. Once archiver_max_failed_attempts is reached the schedule is deleted and the archival is given up,
+/// which is published as video.unavailable if the video couldn't be extracted or as video.failed otherwise. Returns true if the archival has been given up
+async fn record_failed_attempt(
+    db_connection: &mut AsyncPgConnection,
+    env_var_config: &EnvVarConfigArchiver,
+    scheduled_archival: &ScheduledArchival,
+    video_id: Option<i32>,
+    unavailable_reason: Option<String>,
+) -> bool {
+    let attempts = scheduled_archival.failed_attempts + 1;
+    let max_attempts = env_var_config.archiver_max_failed_attempts;
+    if max_attempts == 0 || attempts < max_attempts {
+        if let Err(e) = update(scheduled_archivals::table.find(scheduled_archival.id))
+            .set(scheduled_archivals::failed_attempts.eq(attempts))
+            .execute(db_connection)
+            .await
+        {
+            error!("Failed to count failed attempt, encountered error {}", e);
+        }
+        ArchivalOutcome::Failed.record();
+        return false;
+    }
+
+    let url = scheduled_archival.url.clone();
+    let tracked_collection_id = scheduled_archival.tracked_collection_id;
+    let event = match unavailable_reason {
+        Some(reason) => LifecycleEvent::VideoUnavailable {
+            video_id,
+            url,
+            tracked_collection_id,
+            reason,
+        },
+        None => LifecycleEvent::VideoFailed {
+            video_id,
+            url,
+            tracked_collection_id,
+            attempts,
+        },
+    };
+    let result = db_connection
+        .transaction::<(), diesel::result::Error, _>(|db_connection| {
+            async move {
+                delete(scheduled_archivals::table.find(scheduled_archival.id))
+                    .execute(db_connection)
+                    .await?;
+                lifecycle_event::publish(db_connection, &event).await
+            }
+            .scope_boxed()
+        })
+        .await;
+    match result {
+        Ok(()) => {
+            ArchivalOutcome::GivenUp.record();
+            warn!(
+                "Gave up archiving {} after {} failed attempts",
+                scheduled_archival.url, attempts
+            )
+        }
+        Err(e) => error!(
+            "Failed to give up archiving {}, encountered error {}",
+            scheduled_archival.url, e
+        ),
+    }
+    true
+}
+
+/// sets not_before of the schedule, so it isn't dequeued before the specified time
+async fn postpone(
+    db_connection: &mut AsyncPgConnection,
+    scheduled_archival: &ScheduledArchival,
+    not_before: DateTime<Utc>,
+) {
+    update(scheduled_archivals::table)
+        .set(scheduled_archivals::not_before.eq(not_before))
+        .filter(scheduled_archivals::id.eq(scheduled_archival.id))
+        .execute(db_connection)
+        .await
+        .unwrap();
+    ArchivalOutcome::Postponed.record();
+}
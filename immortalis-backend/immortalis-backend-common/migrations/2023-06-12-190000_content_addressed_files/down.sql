@@ -0,0 +1,3 @@
+ALTER TABLE files DROP CONSTRAINT fk_file_blob;
+ALTER TABLE files DROP COLUMN checksum;
+DROP TABLE blobs;
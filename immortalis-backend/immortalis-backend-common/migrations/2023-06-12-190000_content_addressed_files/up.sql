@@ -0,0 +1,12 @@
+-- objects are stored under their sha256 checksum, so identical files share a single object
+CREATE TABLE blobs (
+  checksum varchar NOT NULL PRIMARY KEY,
+  size bigint NOT NULL,
+  reference_count int NOT NULL DEFAULT 1
+);
+
+-- files without a checksum were stored before content addressing and keep using {id}.{file_extension} as key
+ALTER TABLE files ADD COLUMN checksum varchar;
+ALTER TABLE files ADD CONSTRAINT fk_file_blob
+    foreign key (checksum)
+        references blobs(checksum);
@@ -0,0 +1,4 @@
+ALTER TABLE scheduled_archivals DROP CONSTRAINT fk_scheduled_archival_video;
+ALTER TABLE scheduled_archivals DROP COLUMN video_id;
+ALTER TABLE scheduled_archivals DROP COLUMN stage;
+DROP TYPE archival_stage;
@@ -0,0 +1,9 @@
+CREATE TYPE archival_stage AS ENUM ('pending', 'registered', 'downloaded'); -- these may not start with Uppercase
+
+-- stage the archival has reached, so a retry can resume where the last attempt stopped
+ALTER TABLE scheduled_archivals ADD COLUMN stage archival_stage NOT NULL DEFAULT 'pending';
+ALTER TABLE scheduled_archivals ADD COLUMN video_id int;
+ALTER TABLE scheduled_archivals ADD CONSTRAINT fk_scheduled_archival_video
+    foreign key (video_id)
+        references videos(id)
+            on delete set null;
@@ -0,0 +1,12 @@
+UPDATE scheduled_archivals SET stage = 'downloaded' WHERE stage = 'awaiting_vod';
+ALTER TABLE scheduled_archivals ALTER COLUMN stage DROP DEFAULT;
+ALTER TYPE archival_stage RENAME TO archival_stage_old;
+CREATE TYPE archival_stage AS ENUM ('pending', 'registered', 'downloaded');
+ALTER TABLE scheduled_archivals ALTER COLUMN stage TYPE archival_stage USING stage::text::archival_stage;
+ALTER TABLE scheduled_archivals ALTER COLUMN stage SET DEFAULT 'pending';
+DROP TYPE archival_stage_old;
+
+ALTER TABLE videos DROP COLUMN is_live_recording;
+ALTER TABLE videos DROP COLUMN live_status;
+ALTER TABLE videos DROP COLUMN release_timestamp;
+DROP TYPE live_status;
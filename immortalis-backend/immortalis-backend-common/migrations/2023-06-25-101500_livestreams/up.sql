@@ -0,0 +1,9 @@
+CREATE TYPE live_status AS ENUM ('not_live', 'is_live', 'is_upcoming', 'was_live', 'post_live'); -- same values as yt-dlp uses
+
+ALTER TABLE videos ADD COLUMN release_timestamp timestamp with time zone;
+ALTER TABLE videos ADD COLUMN live_status live_status;
+-- true if the file has been captured while the stream was live, false once it has been replaced by the VOD
+ALTER TABLE videos ADD COLUMN is_live_recording boolean NOT NULL DEFAULT false;
+
+-- live captures are kept until youtube has processed the VOD, which then replaces the capture
+ALTER TYPE archival_stage ADD VALUE 'awaiting_vod';
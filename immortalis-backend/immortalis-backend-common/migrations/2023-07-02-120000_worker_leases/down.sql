@@ -0,0 +1,3 @@
+DROP TABLE leases;
+ALTER TABLE scheduled_archivals DROP COLUMN domain;
+DROP TABLE workers;
@@ -0,0 +1,29 @@
+-- archiver workers register themselves and send heartbeats. Workers without a recent heartbeat are considered dead
+CREATE TABLE workers (
+    id uuid PRIMARY KEY,
+    host varchar NOT NULL,
+    registered_at timestamptz NOT NULL DEFAULT now(),
+    last_heartbeat timestamptz NOT NULL DEFAULT now()
+);
+
+-- source domain of the url, used to limit concurrent archivals per domain
+ALTER TABLE scheduled_archivals ADD COLUMN domain varchar
+    GENERATED ALWAYS AS (lower(substring(url from '^[a-zA-Z]+://(?:www\.)?([^/:?#]+)'))) STORED;
+
+-- a slot held by a worker while it archives a schedule. Leases are removed along with their worker or schedule
+CREATE TABLE leases (
+    scheduled_archival_id int PRIMARY KEY,
+    worker_id uuid NOT NULL,
+    domain varchar,
+    acquired_at timestamptz NOT NULL DEFAULT now(),
+    CONSTRAINT fk_lease_scheduled_archival
+        foreign key (scheduled_archival_id)
+            references scheduled_archivals(id)
+                on delete cascade,
+    CONSTRAINT fk_lease_worker
+        foreign key (worker_id)
+            references workers(id)
+                on delete cascade
+);
+
+CREATE INDEX leases_domain_index ON leases (domain);
@@ -0,0 +1,6 @@
+DROP TRIGGER workers_after_delete_insert_update_trigger ON workers;
+ALTER TABLE workers DROP COLUMN job_started_at;
+ALTER TABLE workers DROP COLUMN current_job;
+ALTER TABLE workers DROP COLUMN version;
+ALTER TABLE workers DROP COLUMN kind;
+DROP TYPE worker_kind;
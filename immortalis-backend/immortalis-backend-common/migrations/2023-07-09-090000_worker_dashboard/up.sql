@@ -0,0 +1,15 @@
+CREATE TYPE worker_kind AS ENUM ('archiver', 'tracker');
+
+-- trackers register themselves as well. Only archivers registered before, so existing rows are archivers of an unknown version
+ALTER TABLE workers ADD COLUMN kind worker_kind NOT NULL DEFAULT 'archiver';
+ALTER TABLE workers ALTER COLUMN kind DROP DEFAULT;
+ALTER TABLE workers ADD COLUMN version varchar NOT NULL DEFAULT 'unknown';
+ALTER TABLE workers ALTER COLUMN version DROP DEFAULT;
+-- url of the archival or collection the worker is processing, null while idle
+ALTER TABLE workers ADD COLUMN current_job varchar;
+ALTER TABLE workers ADD COLUMN job_started_at timestamp with time zone;
+
+DROP TRIGGER IF EXISTS workers_after_delete_insert_update_trigger ON workers;
+CREATE TRIGGER workers_after_delete_insert_update_trigger AFTER DELETE OR INSERT OR UPDATE
+       ON workers
+       FOR EACH ROW EXECUTE PROCEDURE notify_delete_insert();
@@ -0,0 +1,2 @@
+DROP INDEX scheduled_archivals_dequeue_index;
+ALTER TABLE scheduled_archivals DROP COLUMN priority;
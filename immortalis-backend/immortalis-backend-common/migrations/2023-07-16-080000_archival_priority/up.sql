@@ -0,0 +1,4 @@
+-- higher priorities are archived first, schedules of the same priority in the order they were scheduled
+ALTER TABLE scheduled_archivals ADD COLUMN priority smallint NOT NULL DEFAULT 0;
+
+CREATE INDEX scheduled_archivals_dequeue_index ON scheduled_archivals (priority DESC, scheduled_at, id);
@@ -0,0 +1,2 @@
+ALTER TABLE videos DROP COLUMN tracked_collection_id;
+ALTER TABLE scheduled_archivals DROP COLUMN tracked_collection_id;
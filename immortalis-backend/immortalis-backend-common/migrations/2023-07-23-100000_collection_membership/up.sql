@@ -0,0 +1,11 @@
+-- the tracked collection a video has been discovered in, so a collection can be exported as a whole
+ALTER TABLE scheduled_archivals ADD COLUMN tracked_collection_id int
+    CONSTRAINT fk_scheduled_archival_tracked_collection
+        references tracked_collections(id)
+            on delete set null;
+ALTER TABLE videos ADD COLUMN tracked_collection_id int
+    CONSTRAINT fk_video_tracked_collection
+        references tracked_collections(id)
+            on delete set null;
+
+CREATE INDEX videos_tracked_collection_id_index ON videos (tracked_collection_id);
@@ -0,0 +1,18 @@
+CREATE OR REPLACE FUNCTION notify_delete_insert()
+RETURNS trigger AS
+$$
+DECLARE
+  payload TEXT;
+  rec RECORD;
+BEGIN
+	if tg_op = 'INSERT' OR TG_OP = 'UPDATE' then
+      rec := NEW;
+	elsif tg_op = 'DELETE' then
+      REC := OLD;
+	end if;
+
+  payload := json_build_object('action',LOWER(TG_OP), 'record',row_to_json(rec));
+  PERFORM pg_notify(TG_TABLE_NAME, payload);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
@@ -0,0 +1,19 @@
+-- updates carry the row before the update as well, so subscribers can tell whether the status changed
+CREATE OR REPLACE FUNCTION notify_delete_insert()
+RETURNS trigger AS
+$$
+DECLARE
+  payload TEXT;
+BEGIN
+	if tg_op = 'INSERT' then
+      payload := json_build_object('action', 'insert', 'record', row_to_json(NEW));
+	elsif tg_op = 'UPDATE' then
+      payload := json_build_object('action', 'update', 'record', row_to_json(NEW), 'old_record', row_to_json(OLD));
+	elsif tg_op = 'DELETE' then
+      payload := json_build_object('action', 'delete', 'record', row_to_json(OLD));
+	end if;
+
+  PERFORM pg_notify(TG_TABLE_NAME, payload);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
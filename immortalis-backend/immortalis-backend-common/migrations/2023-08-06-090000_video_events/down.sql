@@ -0,0 +1,6 @@
+DROP TRIGGER files_after_size_update_trigger ON files;
+DROP FUNCTION notify_file_size_change;
+DROP TRIGGER videos_after_delete_insert_update_trigger ON videos;
+DROP FUNCTION notify_video_change;
+ALTER TRIGGER tracked_collections_after_delete_insert_update_trigger ON tracked_collections
+       RENAME TO scheduled_archivals_after_delete_insert_trigger;
@@ -0,0 +1,43 @@
+-- the trigger on tracked_collections has been created under the name of the one on scheduled_archivals
+ALTER TRIGGER scheduled_archivals_after_delete_insert_trigger ON tracked_collections
+       RENAME TO tracked_collections_after_delete_insert_update_trigger;
+
+-- videos may exceed the 8000 bytes a notification can carry, so only their ids are sent and the api loads the rest
+CREATE OR REPLACE FUNCTION notify_video_change()
+RETURNS trigger AS
+$$
+DECLARE
+  payload TEXT;
+BEGIN
+	if tg_op = 'INSERT' then
+      payload := json_build_object('action', 'insert', 'id', NEW.id, 'tracked_collection_id', NEW.tracked_collection_id, 'status_changed', true);
+	elsif tg_op = 'UPDATE' then
+      payload := json_build_object('action', 'update', 'id', NEW.id, 'tracked_collection_id', NEW.tracked_collection_id, 'status_changed', NEW.status IS DISTINCT FROM OLD.status);
+	elsif tg_op = 'DELETE' then
+      payload := json_build_object('action', 'delete', 'id', OLD.id, 'tracked_collection_id', OLD.tracked_collection_id, 'status_changed', true);
+	end if;
+
+  PERFORM pg_notify(TG_TABLE_NAME, payload);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
+
+DROP TRIGGER IF EXISTS videos_after_delete_insert_update_trigger ON videos;
+CREATE TRIGGER videos_after_delete_insert_update_trigger AFTER DELETE OR INSERT OR UPDATE
+       ON videos
+       FOR EACH ROW EXECUTE PROCEDURE notify_video_change();
+
+-- the size of a video is the size of its file, the api sends an update of every video stored in the file
+CREATE OR REPLACE FUNCTION notify_file_size_change()
+RETURNS trigger AS
+$$
+BEGIN
+  PERFORM pg_notify(TG_TABLE_NAME, json_build_object('id', NEW.id)::text);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
+
+DROP TRIGGER IF EXISTS files_after_size_update_trigger ON files;
+CREATE TRIGGER files_after_size_update_trigger AFTER UPDATE OF size
+       ON files
+       FOR EACH ROW WHEN (OLD.size IS DISTINCT FROM NEW.size) EXECUTE PROCEDURE notify_file_size_change();
@@ -0,0 +1,56 @@
+DROP TRIGGER workers_after_update_trigger ON workers;
+DROP TRIGGER workers_after_delete_insert_trigger ON workers;
+CREATE TRIGGER workers_after_delete_insert_update_trigger AFTER DELETE OR INSERT OR UPDATE
+       ON workers
+       FOR EACH ROW EXECUTE PROCEDURE notify_delete_insert();
+
+CREATE OR REPLACE FUNCTION notify_delete_insert()
+RETURNS trigger AS
+$$
+DECLARE
+  payload TEXT;
+BEGIN
+	if tg_op = 'INSERT' then
+      payload := json_build_object('action', 'insert', 'record', row_to_json(NEW));
+	elsif tg_op = 'UPDATE' then
+      payload := json_build_object('action', 'update', 'record', row_to_json(NEW), 'old_record', row_to_json(OLD));
+	elsif tg_op = 'DELETE' then
+      payload := json_build_object('action', 'delete', 'record', row_to_json(OLD));
+	end if;
+
+  PERFORM pg_notify(TG_TABLE_NAME, payload);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
+
+CREATE OR REPLACE FUNCTION notify_video_change()
+RETURNS trigger AS
+$$
+DECLARE
+  payload TEXT;
+BEGIN
+	if tg_op = 'INSERT' then
+      payload := json_build_object('action', 'insert', 'id', NEW.id, 'tracked_collection_id', NEW.tracked_collection_id, 'status_changed', true);
+	elsif tg_op = 'UPDATE' then
+      payload := json_build_object('action', 'update', 'id', NEW.id, 'tracked_collection_id', NEW.tracked_collection_id, 'status_changed', NEW.status IS DISTINCT FROM OLD.status);
+	elsif tg_op = 'DELETE' then
+      payload := json_build_object('action', 'delete', 'id', OLD.id, 'tracked_collection_id', OLD.tracked_collection_id, 'status_changed', true);
+	end if;
+
+  PERFORM pg_notify(TG_TABLE_NAME, payload);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
+
+CREATE OR REPLACE FUNCTION notify_file_size_change()
+RETURNS trigger AS
+$$
+BEGIN
+  PERFORM pg_notify(TG_TABLE_NAME, json_build_object('id', NEW.id)::text);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
+
+DROP FUNCTION publish_event;
+DROP TABLE events;
+DROP FUNCTION assign_event_id;
@@ -0,0 +1,99 @@
+-- every event is stored before it is sent, so clients can replay the events they missed. Notifications only carry the id of the event.
+-- Ids are assigned in the order the transactions storing the events commit, so every event before an id that has been seen has been committed as well.
+-- Until its transaction commits, an event has a negative id, which isn't replayed
+CREATE SEQUENCE events_pending_id_seq;
+CREATE TABLE events (
+  id bigint PRIMARY KEY DEFAULT -nextval('events_pending_id_seq'),
+  channel varchar NOT NULL,
+  payload text NOT NULL,
+  created_at timestamp with time zone NOT NULL DEFAULT now()
+);
+CREATE SEQUENCE events_id_seq OWNED BY events.id;
+ALTER SEQUENCE events_pending_id_seq OWNED BY events.id;
+
+CREATE INDEX events_created_at_index ON events (created_at);
+
+CREATE OR REPLACE FUNCTION publish_event(event_channel varchar, event_payload text)
+RETURNS void AS
+$$
+BEGIN
+  INSERT INTO events (channel, payload) VALUES (event_channel, event_payload);
+END;
+$$ LANGUAGE 'plpgsql';
+
+-- runs when the transaction commits. The lock is held until the commit has finished, so the next transaction gets a higher id only after this one is visible
+CREATE OR REPLACE FUNCTION assign_event_id()
+RETURNS trigger AS
+$$
+DECLARE
+  event_id bigint;
+BEGIN
+  PERFORM pg_advisory_xact_lock(4226583);
+  UPDATE events SET id = nextval('events_id_seq') WHERE id = NEW.id RETURNING id INTO event_id;
+  PERFORM pg_notify(NEW.channel, event_id::text);
+  RETURN NULL;
+END;
+$$ LANGUAGE 'plpgsql';
+
+CREATE CONSTRAINT TRIGGER events_after_insert_trigger AFTER INSERT
+       ON events
+       DEFERRABLE INITIALLY DEFERRED
+       FOR EACH ROW EXECUTE PROCEDURE assign_event_id();
+
+CREATE OR REPLACE FUNCTION notify_delete_insert()
+RETURNS trigger AS
+$$
+DECLARE
+  payload TEXT;
+BEGIN
+	if tg_op = 'INSERT' then
+      payload := json_build_object('action', 'insert', 'record', row_to_json(NEW));
+	elsif tg_op = 'UPDATE' then
+      payload := json_build_object('action', 'update', 'record', row_to_json(NEW), 'old_record', row_to_json(OLD));
+	elsif tg_op = 'DELETE' then
+      payload := json_build_object('action', 'delete', 'record', row_to_json(OLD));
+	end if;
+
+  PERFORM publish_event(TG_TABLE_NAME, payload);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
+
+CREATE OR REPLACE FUNCTION notify_video_change()
+RETURNS trigger AS
+$$
+DECLARE
+  payload TEXT;
+BEGIN
+	if tg_op = 'INSERT' then
+      payload := json_build_object('action', 'insert', 'id', NEW.id, 'tracked_collection_id', NEW.tracked_collection_id, 'status_changed', true);
+	elsif tg_op = 'UPDATE' then
+      payload := json_build_object('action', 'update', 'id', NEW.id, 'tracked_collection_id', NEW.tracked_collection_id, 'status_changed', NEW.status IS DISTINCT FROM OLD.status);
+	elsif tg_op = 'DELETE' then
+      payload := json_build_object('action', 'delete', 'id', OLD.id, 'tracked_collection_id', OLD.tracked_collection_id, 'status_changed', true);
+	end if;
+
+  PERFORM publish_event(TG_TABLE_NAME, payload);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
+
+CREATE OR REPLACE FUNCTION notify_file_size_change()
+RETURNS trigger AS
+$$
+BEGIN
+  PERFORM publish_event(TG_TABLE_NAME, json_build_object('id', NEW.id)::text);
+  RETURN NEW;
+END;
+$$ LANGUAGE 'plpgsql';
+
+-- every event is stored now, so heartbeats, which only update last_heartbeat, don't publish one
+DROP TRIGGER workers_after_delete_insert_update_trigger ON workers;
+CREATE TRIGGER workers_after_delete_insert_trigger AFTER DELETE OR INSERT
+       ON workers
+       FOR EACH ROW EXECUTE PROCEDURE notify_delete_insert();
+CREATE TRIGGER workers_after_update_trigger AFTER UPDATE
+       ON workers
+       FOR EACH ROW
+       WHEN (OLD.current_job IS DISTINCT FROM NEW.current_job OR OLD.job_started_at IS DISTINCT FROM NEW.job_started_at)
+       EXECUTE PROCEDURE notify_delete_insert();
@@ -0,0 +1,5 @@
+DROP TABLE unavailable_videos;
+DROP FUNCTION publish_lifecycle_event;
+DROP TABLE webhook_deliveries;
+DROP TABLE webhooks;
+ALTER TABLE scheduled_archivals DROP COLUMN failed_attempts;
@@ -0,0 +1,55 @@
+-- failed attempts of a schedule, the archiver gives up once archiver_max_failed_attempts is reached
+ALTER TABLE scheduled_archivals ADD COLUMN failed_attempts int NOT NULL DEFAULT 0;
+
+CREATE TABLE webhooks (
+  id SERIAL PRIMARY KEY,
+  url varchar NOT NULL,
+  -- the key the payloads are signed with
+  secret varchar NOT NULL,
+  -- the lifecycle events sent to the webhook, empty for all of them
+  event_types text[] NOT NULL DEFAULT '{}',
+  created_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+-- every lifecycle event is delivered to each webhook asking for it, until it has been received or the delivery is given up
+CREATE TABLE webhook_deliveries (
+  id bigserial PRIMARY KEY,
+  webhook_id int NOT NULL
+    CONSTRAINT fk_webhook_delivery_webhook
+      references webhooks(id)
+        on delete cascade,
+  event_type varchar NOT NULL,
+  payload text NOT NULL,
+  attempts int NOT NULL DEFAULT 0,
+  next_attempt_at timestamp with time zone NOT NULL DEFAULT now(),
+  delivered_at timestamp with time zone,
+  failed_at timestamp with time zone,
+  last_status_code int,
+  last_error text,
+  created_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhook_deliveries_webhook_id_index ON webhook_deliveries (webhook_id, id);
+CREATE INDEX webhook_deliveries_pending_index ON webhook_deliveries (next_attempt_at) WHERE delivered_at IS NULL AND failed_at IS NULL;
+
+-- queues the deliveries of a lifecycle event and publishes it, so the api delivers them right away
+CREATE OR REPLACE FUNCTION publish_lifecycle_event(lifecycle_event_type varchar, event_payload text)
+RETURNS void AS
+$$
+BEGIN
+  INSERT INTO webhook_deliveries (webhook_id, event_type, payload)
+    SELECT id, lifecycle_event_type, event_payload FROM webhooks
+      WHERE cardinality(event_types) = 0 OR lifecycle_event_type = ANY(event_types);
+  PERFORM publish_event('lifecycle_events', event_payload);
+END;
+$$ LANGUAGE 'plpgsql';
+
+-- archived videos the tracker found removed upstream or no longer listed in their collection, so video.unavailable is only published once for each
+CREATE TABLE unavailable_videos (
+  video_id int PRIMARY KEY
+    CONSTRAINT fk_unavailable_video_video
+      references videos(id)
+        on delete cascade,
+  reason text NOT NULL,
+  detected_at timestamp with time zone NOT NULL DEFAULT now()
+);
@@ -0,0 +1 @@
+ALTER TABLE scheduled_archivals DROP COLUMN trace_context;
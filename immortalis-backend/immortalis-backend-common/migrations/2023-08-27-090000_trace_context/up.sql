@@ -0,0 +1,2 @@
+-- the W3C traceparent of the span that scheduled the archival, so the archiver continues its trace
+ALTER TABLE scheduled_archivals ADD COLUMN trace_context text;
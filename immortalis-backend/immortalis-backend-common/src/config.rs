@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// names the config file if --config isn't passed
+pub const CONFIG_FILE_VARIABLE: &str = "CONFIG_FILE";
+
+/// the configuration of a service, read from a TOML file and the environment and validated before the service starts
+pub trait ServiceConfig: DeserializeOwned + Serialize {
+    /// adds a message to problems for every value which is invalid or doesn't fit to the others
+    fn validate(&self, problems: &mut Vec<String>);
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Arguments(String),
+    File(PathBuf, std::io::Error),
+    Toml(PathBuf, toml::de::Error),
+    /// a value is missing or can't be parsed
+    Value(envy::Error),
+    Invalid(Vec<String>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Arguments(message) => write!(f, "{}", message),
+            ConfigError::File(path, e) => {
+                write!(f, "could not read config file {}: {}", path.display(), e)
+            }
+            ConfigError::Toml(path, e) => {
+                write!(f, "config file {} is not valid TOML: {}", path.display(), e)
+            }
+            ConfigError::Value(envy::Error::MissingValue(key)) => write!(
+                f,
+                "{} is not set, set the environment variable or the key {} in the config file",
+                key.to_uppercase(),
+                key
+            ),
+            ConfigError::Value(e) => write!(f, "invalid configuration: {}", e),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// the options every service takes on the command line
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConfigArguments {
+    pub config_file: Option<PathBuf>,
+    /// prints the configuration with redacted secrets and exits
+    pub print_config: bool,
+}
+
+impl ConfigArguments {
+    /// parses --config <path>, --config=<path> and --print-config. Without --config the file named by CONFIG_FILE is read, if it is set
+    pub fn parse(arguments: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut parsed = ConfigArguments::default();
+        let mut arguments = arguments.into_iter();
+        while let Some(argument) = arguments.next() {
+            match argument.as_str() {
+                "--print-config" => parsed.print_config = true,
+                "--config" => match arguments.next() {
+                    Some(path) => parsed.config_file = Some(PathBuf::from(path)),
+                    None => {
+                        return Err(ConfigError::Arguments(
+                            "--config requires the path of a config file".to_string(),
+                        ))
+                    }
+                },
+                _ => match argument.strip_prefix("--config=") {
+                    Some(path) => parsed.config_file = Some(PathBuf::from(path)),
+                    None => {
+                        return Err(ConfigError::Arguments(format!(
+                            "unknown argument {}, expected --config <path> or --print-config",
+                            argument
+                        )))
+                    }
+                },
+            }
+        }
+        if parsed.config_file.is_none() {
+            parsed.config_file = config_file_from_env();
+        }
+        Ok(parsed)
+    }
+}
+
+/// the config file named by CONFIG_FILE
+pub fn config_file_from_env() -> Option<PathBuf> {
+    std::env::var_os(CONFIG_FILE_VARIABLE)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Reads the config file if there is one, overrides its values with the environment and validates the result
+pub fn load<T: ServiceConfig>(config_file: Option<&Path>) -> Result<T, ConfigError> {
+    load_from(config_file, std::env::vars())
+}
+
+/// Like load, with the variables overriding the config file passed explicitly.
+/// Keys of the file are named like the variables, tables prefix the keys they contain, so bucket_name in [s3] is S3_BUCKET_NAME.
+/// Arrays are joined with commas, like the variables taking lists
+pub fn load_from<T: ServiceConfig>(
+    config_file: Option<&Path>,
+    variables: impl IntoIterator<Item = (String, String)>,
+) -> Result<T, ConfigError> {
+    let mut values = BTreeMap::new();
+    if let Some(path) = config_file {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::File(path.to_path_buf(), e))?;
+        let table = toml::from_str::<toml::Table>(&content)
+            .map_err(|e| ConfigError::Toml(path.to_path_buf(), e))?;
+        flatten_table("", table, &mut values);
+    }
+    // envy matches variables case-insensitively, so both sources are lowercased for the environment to take precedence
+    values.extend(
+        variables
+            .into_iter()
+            .map(|(key, value)| (key.to_lowercase(), value)),
+    );
+
+    let config = envy::from_iter::<_, T>(values).map_err(ConfigError::Value)?;
+    let mut problems = vec![];
+    config.validate(&mut problems);
+    if problems.is_empty() {
+        Ok(config)
+    } else {
+        Err(ConfigError::Invalid(problems))
+    }
+}
+
+fn flatten_table(prefix: &str, table: toml::Table, values: &mut BTreeMap<String, String>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.to_lowercase()
+        } else {
+            format!("{}_{}", prefix, key.to_lowercase())
+        };
+        match value {
+            toml::Value::Table(table) => flatten_table(&key, table, values),
+            toml::Value::Array(items) => {
+                values.insert(
+                    key,
+                    items.iter().map(scalar).collect::<Vec<String>>().join(","),
+                );
+            }
+            value => {
+                values.insert(key, scalar(&value));
+            }
+        }
+    }
+}
+
+/// the value as it would be written in a variable, strings without quotes
+fn scalar(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Loads the configuration of a service according to the arguments it has been started with.
+/// Exits listing the problems if the configuration is invalid, or printing it if --print-config is passed
+pub fn load_or_exit<T: ServiceConfig>() -> T {
+    let loaded = ConfigArguments::parse(std::env::args().skip(1)).and_then(|arguments| {
+        load::<T>(arguments.config_file.as_deref()).map(|config| (config, arguments.print_config))
+    });
+    match loaded {
+        Ok((config, false)) => config,
+        Ok((config, true)) => {
+            print!("{}", to_toml(&config));
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// the configuration in the format of the config file, secrets are redacted
+pub fn to_toml<T: Serialize>(config: &T) -> String {
+    toml::to_string(config).expect("could not serialize configuration")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{load_from, to_toml, ConfigArguments, ConfigError};
+    use crate::env_var_config::EnvVarConfigTracker;
+
+    fn variables(variables: &[(&str, &str)]) -> Vec<(String, String)> {
+        variables
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_environment_overrides_config_file() {
+        let mut config_file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            config_file,
+            "database_url = \"postgres://immortalis:secret@db/immortalis\"\ntracker_thread_count = 2\n\n[yt_dlp]\nproxies = [\"http://a:1\", \"http://b:2\"]\n"
+        )
+        .unwrap();
+
+        let config = load_from::<EnvVarConfigTracker>(
+            Some(config_file.path()),
+            variables(&[("TRACKER_THREAD_COUNT", "4")]),
+        )
+        .unwrap();
+        assert_eq!(config.tracker_thread_count, 4);
+        assert_eq!(
+            config.yt_dlp_config.yt_dlp_proxies,
+            vec!["http://a:1", "http://b:2"]
+        );
+        assert!(to_toml(&config).contains("database_url = \"postgres://<redacted>@db/immortalis\""));
+    }
+
+    #[test]
+    fn test_invalid_values_are_listed() {
+        let error = load_from::<EnvVarConfigTracker>(
+            None,
+            variables(&[
+                ("DATABASE_URL", "postgres://db/immortalis"),
+                ("TRACKER_THREAD_COUNT", "0"),
+                ("TRACKER_WORKER_TIMEOUT_SECONDS", "10"),
+            ]),
+        )
+        .unwrap_err();
+        match error {
+            ConfigError::Invalid(problems) => assert_eq!(problems.len(), 2),
+            e => panic!("unexpected error {}", e),
+        }
+
+        let error = load_from::<EnvVarConfigTracker>(
+            None,
+            variables(&[("DATABASE_URL", "postgres://db/immortalis")]),
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("TRACKER_THREAD_COUNT is not set"));
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        let arguments = ConfigArguments::parse(
+            ["--config=immortalis.toml", "--print-config"].map(String::from),
+        )
+        .unwrap();
+        assert_eq!(
+            arguments.config_file.unwrap().to_str(),
+            Some("immortalis.toml")
+        );
+        assert!(arguments.print_config);
+        assert!(ConfigArguments::parse(["--config"].map(String::from)).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::database_models::video::Video;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct VideoDto {
     #[serde(flatten)]
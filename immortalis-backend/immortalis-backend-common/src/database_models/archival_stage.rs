@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+/// the stage a ScheduledArchival has reached. Each stage is persisted once its database writes are committed
+#[derive(diesel_derive_enum::DbEnum, Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[ExistingTypePath = "crate::schema::sql_types::ArchivalStage"]
+// same names as the postgres enum, so rows sent by postgres notifications can be deserialized
+#[serde(rename_all = "snake_case")]
+pub enum ArchivalStage {
+    /// nothing has been stored yet
+    Pending,
+    /// the video, its thumbnail and the file for the video have been inserted
+    Registered,
+    /// the video has been downloaded and stored
+    Downloaded,
+    /// the live capture has been archived, the VOD will replace it once youtube has processed it
+    AwaitingVod,
+}
@@ -0,0 +1,15 @@
+use crate::schema::blobs;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A stored object, keyed by the sha256 checksum of its content. Multiple files may reference the same blob
+#[derive(
+    Deserialize, Serialize, std::fmt::Debug, Queryable, Identifiable, Selectable, Insertable,
+)]
+#[diesel(primary_key(checksum))]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Blob {
+    pub checksum: String,
+    pub size: i64,
+    pub reference_count: i32,
+}
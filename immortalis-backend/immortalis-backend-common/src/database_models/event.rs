@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use diesel::{delete, ExpressionMethods, QueryDsl, QueryResult, Queryable, Selectable};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::schema::events;
+
+/// An event stored by the notify triggers before it is sent, so it can be replayed. Its id is sent as the payload of the notification
+#[derive(std::fmt::Debug, Queryable, Selectable)]
+pub struct Event {
+    pub id: i64,
+    /// the table the event is about, which is the channel of the notification
+    pub channel: String,
+    pub payload: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// the events after after_id of the channels, in the order they have been committed
+pub async fn load_events_after(
+    db_connection: &mut AsyncPgConnection,
+    after_id: i64,
+    channels: &[&str],
+    limit: i64,
+) -> QueryResult<Vec<Event>> {
+    events::table
+        .filter(events::id.gt(after_id))
+        .filter(events::channel.eq_any(channels))
+        .order(events::id)
+        .limit(limit)
+        .load::<Event>(db_connection)
+        .await
+}
+
+/// the id of the latest committed event. Events are committed in the order of their ids, so every later event has a higher id
+pub async fn latest_event_id(db_connection: &mut AsyncPgConnection) -> QueryResult<Option<i64>> {
+    events::table
+        .select(diesel::dsl::max(events::id))
+        .first::<Option<i64>>(db_connection)
+        .await
+}
+
+/// the id of the oldest stored event, events before it have been pruned
+pub async fn oldest_event_id(db_connection: &mut AsyncPgConnection) -> QueryResult<Option<i64>> {
+    events::table
+        .select(diesel::dsl::min(events::id))
+        .first::<Option<i64>>(db_connection)
+        .await
+}
+
+/// deletes the events stored before older_than, returns the number of deleted events
+pub async fn prune_events(
+    db_connection: &mut AsyncPgConnection,
+    older_than: DateTime<Utc>,
+) -> QueryResult<usize> {
+    delete(events::table)
+        .filter(events::created_at.lt(older_than))
+        .execute(db_connection)
+        .await
+}
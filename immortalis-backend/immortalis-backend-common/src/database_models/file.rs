@@ -3,7 +3,7 @@ use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
 #[derive(
-    Deserialize, Serialize, std::fmt::Debug, Queryable, Identifiable, Selectable, Insertable,
+    Deserialize, Serialize, std::fmt::Debug, Clone, Queryable, Identifiable, Selectable, Insertable,
 )]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct File {
@@ -11,4 +11,15 @@ pub struct File {
     pub file_name: String,
     pub file_extension: String,
     pub size: i64,
+    pub checksum: Option<String>,
+}
+
+impl File {
+    /// the key of the object in the storage. Files stored before content addressing was introduced have no checksum and are stored as {id}.{file_extension}
+    pub fn storage_key(&self) -> String {
+        match &self.checksum {
+            Some(checksum) => checksum.to_owned(),
+            None => format!("{}.{}", self.id, self.file_extension),
+        }
+    }
 }
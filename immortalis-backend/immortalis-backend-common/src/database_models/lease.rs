@@ -0,0 +1,162 @@
+use chrono::{Duration, Utc};
+use diesel::dsl::count_star;
+use diesel::sql_types::BigInt;
+use diesel::upsert::excluded;
+use diesel::{
+    delete, insert_into, sql_query, update, BoolExpressionMethods, ExpressionMethods, Identifiable,
+    OptionalExtension, QueryDsl, QueryResult, Queryable, Selectable,
+};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use super::scheduled_archival::ScheduledArchival;
+use crate::schema::{leases, scheduled_archivals};
+
+// acquiring leases is serialized across all replicas with this transaction level advisory lock
+const LEASE_LOCK_KEY: i64 = 4_226_582;
+
+/// A slot held by a worker while it archives a schedule
+#[derive(Deserialize, Serialize, std::fmt::Debug, Queryable, Identifiable, Selectable)]
+#[diesel(primary_key(scheduled_archival_id))]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Lease {
+    pub scheduled_archival_id: i32,
+    pub worker_id: uuid::Uuid,
+    pub domain: Option<String>,
+    pub acquired_at: chrono::DateTime<Utc>,
+}
+
+/// limits of concurrent archivals across all replicas, 0 means unlimited
+#[derive(Debug, Clone, Copy)]
+pub struct LeaseLimits {
+    pub max_concurrent_archivals: i64,
+    pub max_concurrent_archivals_per_domain: i64,
+}
+
+/// Dequeues the due ScheduledArchival with the highest priority, the oldest one first within a priority, and leases it to the worker, if the limits allow another archival.
+/// The schedule becomes available again once processing_timeout_seconds have passed, or right away if the worker is considered dead
+pub async fn dequeue(
+    db_connection: &mut AsyncPgConnection,
+    worker_id: uuid::Uuid,
+    limits: LeaseLimits,
+    processing_timeout_seconds: i64,
+) -> QueryResult<Option<ScheduledArchival>> {
+    db_connection
+        .transaction::<Option<ScheduledArchival>, diesel::result::Error, _>(|db_connection| {
+            async move {
+                sql_query("SELECT pg_advisory_xact_lock($1)")
+                    .bind::<BigInt, _>(LEASE_LOCK_KEY)
+                    .execute(db_connection)
+                    .await?;
+
+                if limits.max_concurrent_archivals > 0
+                    && leases::table
+                        .count()
+                        .get_result::<i64>(db_connection)
+                        .await?
+                        >= limits.max_concurrent_archivals
+                {
+                    return Ok(None);
+                }
+
+                let saturated_domains: Vec<String> =
+                    if limits.max_concurrent_archivals_per_domain > 0 {
+                        leases::table
+                            .group_by(leases::domain)
+                            .having(count_star().ge(limits.max_concurrent_archivals_per_domain))
+                            .select(leases::domain)
+                            .load::<Option<String>>(db_connection)
+                            .await?
+                            .into_iter()
+                            .flatten()
+                            .collect()
+                    } else {
+                        Vec::new()
+                    };
+
+                let result = scheduled_archivals::table
+                    .limit(1)
+                    .filter(scheduled_archivals::not_before.lt(Utc::now()))
+                    .order((
+                        scheduled_archivals::priority.desc(),
+                        scheduled_archivals::scheduled_at,
+                        scheduled_archivals::id,
+                    ))
+                    .filter(
+                        scheduled_archivals::domain
+                            .is_null()
+                            .or(scheduled_archivals::domain.ne_all(&saturated_domains)),
+                    )
+                    .for_update()
+                    .skip_locked()
+                    .first::<ScheduledArchival>(db_connection)
+                    .await
+                    .optional()?;
+
+                let Some(entry) = result else {
+                    return Ok(None);
+                };
+
+                // set not_before to now + timeout. This prevents other processes from trying to preform it as well and allows retry in case this process crashes
+                update(scheduled_archivals::table)
+                    .set(
+                        scheduled_archivals::not_before
+                            .eq(Utc::now() + Duration::seconds(processing_timeout_seconds)),
+                    )
+                    .filter(scheduled_archivals::id.eq(entry.id))
+                    .execute(db_connection)
+                    .await?;
+
+                // the lease of a worker that exceeded the processing timeout is taken over
+                insert_into(leases::table)
+                    .values((
+                        leases::scheduled_archival_id.eq(entry.id),
+                        leases::worker_id.eq(worker_id),
+                        leases::domain.eq(&entry.domain),
+                    ))
+                    .on_conflict(leases::scheduled_archival_id)
+                    .do_update()
+                    .set((
+                        leases::worker_id.eq(excluded(leases::worker_id)),
+                        leases::acquired_at.eq(excluded(leases::acquired_at)),
+                    ))
+                    .execute(db_connection)
+                    .await?;
+                Ok(Some(entry))
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// releases the lease, unless it has been taken over by another worker
+pub async fn release(
+    db_connection: &mut AsyncPgConnection,
+    worker_id: uuid::Uuid,
+    scheduled_archival_id: i32,
+) -> QueryResult<()> {
+    delete(leases::table)
+        .filter(leases::scheduled_archival_id.eq(scheduled_archival_id))
+        .filter(leases::worker_id.eq(worker_id))
+        .execute(db_connection)
+        .await?;
+    Ok(())
+}
+
+/// splits total_bytes_per_second evenly between all leases. Returns None if total_bytes_per_second is 0, which means unlimited
+pub async fn bandwidth_share(
+    db_connection: &mut AsyncPgConnection,
+    total_bytes_per_second: u64,
+) -> QueryResult<Option<u64>> {
+    if total_bytes_per_second == 0 {
+        return Ok(None);
+    }
+    let lease_count = leases::table
+        .count()
+        .get_result::<i64>(db_connection)
+        .await?;
+    Ok(Some(
+        total_bytes_per_second / u64::try_from(lease_count.max(1)).unwrap(),
+    ))
+}
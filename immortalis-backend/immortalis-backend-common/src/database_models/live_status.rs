@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+/// live status of a video as reported by yt-dlp
+#[derive(diesel_derive_enum::DbEnum, Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[ExistingTypePath = "crate::schema::sql_types::LiveStatus"]
+pub enum LiveStatus {
+    NotLive,
+    IsLive,
+    /// a scheduled stream or premiere that hasn't started yet
+    IsUpcoming,
+    WasLive,
+    /// the stream is over, but youtube is still processing the VOD
+    PostLive,
+}
+
+impl LiveStatus {
+    /// parses the live_status field of yt-dlp. Returns None for values this version doesn't know
+    pub fn from_yt_dlp(live_status: &str) -> Option<LiveStatus> {
+        match live_status {
+            "not_live" => Some(LiveStatus::NotLive),
+            "is_live" => Some(LiveStatus::IsLive),
+            "is_upcoming" => Some(LiveStatus::IsUpcoming),
+            "was_live" => Some(LiveStatus::WasLive),
+            "post_live" => Some(LiveStatus::PostLive),
+            _ => None,
+        }
+    }
+}
@@ -1,5 +1,13 @@
+pub mod archival_stage;
+pub mod blob;
+pub mod event;
 pub mod file;
+pub mod lease;
+pub mod live_status;
 pub mod scheduled_archival;
 pub mod tracked_collection;
 pub mod video;
 pub mod video_status;
+pub mod webhook;
+pub mod worker;
+pub mod worker_kind;
@@ -1,3 +1,4 @@
+use super::archival_stage::ArchivalStage;
 use crate::schema::scheduled_archivals;
 use chrono::Utc;
 use diesel::prelude::*;
@@ -10,4 +11,22 @@ pub struct ScheduledArchival {
     pub url: String,
     pub scheduled_at: chrono::DateTime<Utc>,
     pub not_before: chrono::DateTime<Utc>,
+    pub stage: ArchivalStage,
+    pub video_id: Option<i32>,
+    /// source domain of the url, without www.
+    pub domain: Option<String>,
+    /// higher priorities are dequeued first
+    pub priority: i16,
+    /// the collection the tracker discovered the video in
+    pub tracked_collection_id: Option<i32>,
+    /// attempts which failed since the video has been scheduled
+    pub failed_attempts: i32,
+    /// the traceparent of the span which scheduled the archival, see telemetry::current_trace_context()
+    #[serde(default, skip_serializing)]
+    pub trace_context: Option<String>,
 }
+
+/// default priority of archivals requested through the api
+pub const MANUAL_PRIORITY: i16 = 100;
+/// priority of archivals discovered by the tracker, so back catalogues don't delay manual requests
+pub const TRACKED_PRIORITY: i16 = 0;
@@ -1,6 +1,8 @@
+use super::live_status::LiveStatus;
 use super::video_status::VideoStatus;
 use crate::database_models::file::File;
 use crate::schema::videos;
+use crate::video_metadata::VideoMetadata;
 use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -23,6 +25,11 @@ pub struct Video {
     pub status: VideoStatus,
     pub file_id: uuid::Uuid,
     pub thumbnail_id: uuid::Uuid,
+    pub release_timestamp: Option<DateTime<Utc>>,
+    pub live_status: Option<LiveStatus>,
+    pub is_live_recording: bool,
+    /// the collection the tracker discovered the video in
+    pub tracked_collection_id: Option<i32>,
 }
 
 #[derive(Deserialize, Serialize, Selectable, std::fmt::Debug, Insertable)]
@@ -38,33 +45,48 @@ pub struct InsertableVideo {
     pub status: VideoStatus,
     pub file_id: uuid::Uuid,
     pub thumbnail_id: uuid::Uuid,
+    pub release_timestamp: Option<DateTime<Utc>>,
+    pub live_status: Option<LiveStatus>,
+    pub is_live_recording: bool,
+    pub tracked_collection_id: Option<i32>,
 }
 
 impl InsertableVideo {
     pub fn new(
-        single_video: youtube_dl::SingleVideo,
+        metadata: VideoMetadata,
         status: VideoStatus,
         file_id: uuid::Uuid,
         thumbnail_id: uuid::Uuid,
     ) -> InsertableVideo {
+        let single_video = metadata.video;
         InsertableVideo {
             title: single_video.title.unwrap_or_default(),
             channel: single_video.channel.unwrap(),
-            views: single_video.view_count.unwrap(),
-            upload_date: DateTime::from_utc(
-                NaiveDateTime::new(
-                    NaiveDate::parse_from_str(&single_video.upload_date.unwrap(), "%Y%m%d")
-                        .unwrap(),
-                    NaiveTime::default(),
+            views: single_video.view_count.unwrap_or_default(), // streams which haven't started have no views
+            upload_date: match single_video.upload_date {
+                Some(upload_date) => DateTime::from_utc(
+                    NaiveDateTime::new(
+                        NaiveDate::parse_from_str(&upload_date, "%Y%m%d").unwrap(),
+                        NaiveTime::default(),
+                    ),
+                    Utc,
                 ),
-                Utc,
-            ),
+                None => metadata.release_timestamp.unwrap_or_else(Utc::now),
+            },
             archived_date: Utc::now(),
-            duration: single_video.duration.unwrap().as_i64().unwrap() as i32,
+            // livestreams have no duration until they are over
+            duration: single_video
+                .duration
+                .and_then(|d| d.as_f64())
+                .unwrap_or_default() as i32,
             original_url: single_video.webpage_url.unwrap(),
             status,
             file_id,
             thumbnail_id,
+            release_timestamp: metadata.release_timestamp,
+            live_status: metadata.live_status,
+            is_live_recording: metadata.live_status == Some(LiveStatus::IsLive),
+            tracked_collection_id: None,
         }
     }
 }
@@ -1,6 +1,11 @@
+use diesel::{update, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 
-#[derive(diesel_derive_enum::DbEnum, Debug, Deserialize, Serialize)]
+use crate::schema::videos;
+
+#[derive(diesel_derive_enum::DbEnum, Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
 #[ExistingTypePath = "crate::schema::sql_types::VideoStatus"]
 pub enum VideoStatus {
     Archived,
@@ -8,3 +13,107 @@ pub enum VideoStatus {
     BeingArchived,
     ArchivationFailed,
 }
+
+impl VideoStatus {
+    /// ScheduledForArchival -> BeingArchived -> Archived or ArchivationFailed. A failed archival may be attempted again
+    pub fn can_transition_to(self, to: VideoStatus) -> bool {
+        matches!(
+            (self, to),
+            (
+                VideoStatus::ScheduledForArchival,
+                VideoStatus::BeingArchived
+            ) | (VideoStatus::BeingArchived, VideoStatus::Archived)
+                | (VideoStatus::BeingArchived, VideoStatus::ArchivationFailed)
+                | (VideoStatus::ArchivationFailed, VideoStatus::BeingArchived)
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum TransitionError {
+    Database(diesel::result::Error),
+    NotFound {
+        video_id: i32,
+    },
+    Invalid {
+        video_id: i32,
+        from: VideoStatus,
+        to: VideoStatus,
+    },
+}
+
+impl std::fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransitionError::Database(e) => write!(f, "database error: {}", e),
+            TransitionError::NotFound { video_id } => write!(f, "video {} not found", video_id),
+            TransitionError::Invalid { video_id, from, to } => write!(
+                f,
+                "video {} can't transition from {:?} to {:?}",
+                video_id, from, to
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+impl From<diesel::result::Error> for TransitionError {
+    fn from(e: diesel::result::Error) -> Self {
+        TransitionError::Database(e)
+    }
+}
+
+/// Moves the video with video_id to the status `to` and returns its previous status.
+/// The row is locked while the transition is checked, so concurrent workers can't move it past each other and no other video is affected
+pub async fn transition(
+    db_connection: &mut AsyncPgConnection,
+    video_id: i32,
+    to: VideoStatus,
+) -> Result<VideoStatus, TransitionError> {
+    db_connection
+        .transaction::<VideoStatus, TransitionError, _>(|db_connection| {
+            async move {
+                let from = videos::table
+                    .find(video_id)
+                    .select(videos::status)
+                    .for_update()
+                    .first::<VideoStatus>(db_connection)
+                    .await
+                    .optional()?
+                    .ok_or(TransitionError::NotFound { video_id })?;
+
+                if !from.can_transition_to(to) {
+                    return Err(TransitionError::Invalid { video_id, from, to });
+                }
+
+                update(videos::table)
+                    .set(videos::status.eq(to))
+                    .filter(videos::id.eq(video_id))
+                    .execute(db_connection)
+                    .await?;
+                Ok(from)
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VideoStatus;
+
+    #[test]
+    fn test_can_transition_to() {
+        assert!(VideoStatus::ScheduledForArchival.can_transition_to(VideoStatus::BeingArchived));
+        assert!(VideoStatus::BeingArchived.can_transition_to(VideoStatus::Archived));
+        assert!(VideoStatus::BeingArchived.can_transition_to(VideoStatus::ArchivationFailed));
+        assert!(VideoStatus::ArchivationFailed.can_transition_to(VideoStatus::BeingArchived));
+
+        assert!(!VideoStatus::ScheduledForArchival.can_transition_to(VideoStatus::Archived));
+        assert!(!VideoStatus::Archived.can_transition_to(VideoStatus::BeingArchived));
+        assert!(!VideoStatus::Archived.can_transition_to(VideoStatus::ArchivationFailed));
+        assert!(!VideoStatus::BeingArchived.can_transition_to(VideoStatus::BeingArchived));
+        assert!(!VideoStatus::ArchivationFailed.can_transition_to(VideoStatus::Archived));
+    }
+}
@@ -0,0 +1,197 @@
+use chrono::{DateTime, Duration, Utc};
+use diesel::{
+    delete, insert_into, update, ExpressionMethods, Identifiable, OptionalExtension, QueryDsl,
+    QueryResult, Queryable, Selectable,
+};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::schema::{webhook_deliveries, webhooks};
+
+/// An endpoint lifecycle events are posted to
+#[derive(Serialize, std::fmt::Debug, Clone, Queryable, Identifiable, Selectable)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    /// only shown once the webhook has been created
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// the lifecycle events sent to the webhook, empty for all of them
+    pub event_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A lifecycle event queued for a webhook, along with the outcome of its last attempt
+#[derive(Serialize, std::fmt::Debug, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = webhook_deliveries)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i32,
+    pub event_type: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// set once the delivery has been given up
+    pub failed_at: Option<DateTime<Utc>>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// the outcome of an attempt to deliver a lifecycle event
+#[derive(Debug)]
+pub enum DeliveryOutcome {
+    Delivered {
+        status_code: i32,
+    },
+    /// the delivery is attempted again at retry_at, or given up if there is none
+    Failed {
+        status_code: Option<i32>,
+        error: String,
+        retry_at: Option<DateTime<Utc>>,
+    },
+}
+
+pub async fn list_webhooks(db_connection: &mut AsyncPgConnection) -> QueryResult<Vec<Webhook>> {
+    webhooks::table
+        .order(webhooks::id)
+        .load::<Webhook>(db_connection)
+        .await
+}
+
+pub async fn insert_webhook(
+    db_connection: &mut AsyncPgConnection,
+    url: &str,
+    secret: &str,
+    event_types: &[String],
+) -> QueryResult<Webhook> {
+    insert_into(webhooks::table)
+        .values((
+            webhooks::url.eq(url),
+            webhooks::secret.eq(secret),
+            webhooks::event_types.eq(event_types),
+        ))
+        .get_result::<Webhook>(db_connection)
+        .await
+}
+
+/// deletes the webhook along with its deliveries, returns false if it doesn't exist
+pub async fn delete_webhook(db_connection: &mut AsyncPgConnection, id: i32) -> QueryResult<bool> {
+    Ok(delete(webhooks::table.find(id))
+        .execute(db_connection)
+        .await?
+        > 0)
+}
+
+/// the latest deliveries of the webhook, the newest first
+pub async fn list_deliveries(
+    db_connection: &mut AsyncPgConnection,
+    webhook_id: i32,
+    limit: i64,
+) -> QueryResult<Vec<WebhookDelivery>> {
+    webhook_deliveries::table
+        .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+        .order(webhook_deliveries::id.desc())
+        .limit(limit)
+        .load::<WebhookDelivery>(db_connection)
+        .await
+}
+
+/// Dequeues the oldest due delivery along with its webhook. The delivery becomes due again once processing_timeout_seconds have passed,
+/// so it is retried if the api stops before recording the outcome
+pub async fn dequeue_delivery(
+    db_connection: &mut AsyncPgConnection,
+    processing_timeout_seconds: i64,
+) -> QueryResult<Option<(WebhookDelivery, Webhook)>> {
+    db_connection
+        .transaction::<Option<(WebhookDelivery, Webhook)>, diesel::result::Error, _>(
+            |db_connection| {
+                async move {
+                    let Some(delivery) = webhook_deliveries::table
+                        .filter(webhook_deliveries::delivered_at.is_null())
+                        .filter(webhook_deliveries::failed_at.is_null())
+                        .filter(webhook_deliveries::next_attempt_at.le(Utc::now()))
+                        .order(webhook_deliveries::next_attempt_at)
+                        .for_update()
+                        .skip_locked()
+                        .first::<WebhookDelivery>(db_connection)
+                        .await
+                        .optional()?
+                    else {
+                        return Ok(None);
+                    };
+
+                    let retry_at = Utc::now() + Duration::seconds(processing_timeout_seconds);
+                    update(webhook_deliveries::table.find(delivery.id))
+                        .set(webhook_deliveries::next_attempt_at.eq(retry_at))
+                        .execute(db_connection)
+                        .await?;
+                    let webhook = webhooks::table
+                        .find(delivery.webhook_id)
+                        .first::<Webhook>(db_connection)
+                        .await?;
+                    Ok(Some((delivery, webhook)))
+                }
+                .scope_boxed()
+            },
+        )
+        .await
+}
+
+/// records the outcome of an attempt of the delivery
+pub async fn record_attempt(
+    db_connection: &mut AsyncPgConnection,
+    delivery_id: i64,
+    outcome: &DeliveryOutcome,
+) -> QueryResult<()> {
+    let delivery = webhook_deliveries::table.find(delivery_id);
+    let attempts = webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1);
+    match outcome {
+        DeliveryOutcome::Delivered { status_code } => {
+            update(delivery)
+                .set((
+                    attempts,
+                    webhook_deliveries::delivered_at.eq(Utc::now()),
+                    webhook_deliveries::last_status_code.eq(status_code),
+                    webhook_deliveries::last_error.eq(None::<String>),
+                ))
+                .execute(db_connection)
+                .await?
+        }
+        DeliveryOutcome::Failed {
+            status_code,
+            error,
+            retry_at: Some(retry_at),
+        } => {
+            update(delivery)
+                .set((
+                    attempts,
+                    webhook_deliveries::next_attempt_at.eq(retry_at),
+                    webhook_deliveries::last_status_code.eq(status_code),
+                    webhook_deliveries::last_error.eq(error),
+                ))
+                .execute(db_connection)
+                .await?
+        }
+        DeliveryOutcome::Failed {
+            status_code,
+            error,
+            retry_at: None,
+        } => {
+            update(delivery)
+                .set((
+                    attempts,
+                    webhook_deliveries::failed_at.eq(Utc::now()),
+                    webhook_deliveries::last_status_code.eq(status_code),
+                    webhook_deliveries::last_error.eq(error),
+                ))
+                .execute(db_connection)
+                .await?
+        }
+    };
+    Ok(())
+}
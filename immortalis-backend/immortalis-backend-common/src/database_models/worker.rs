@@ -0,0 +1,143 @@
+use chrono::{Duration, Utc};
+use diesel::upsert::excluded;
+use diesel::{
+    delete, insert_into, update, ExpressionMethods, Identifiable, QueryDsl, QueryResult, Queryable,
+    Selectable,
+};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use super::worker_kind::WorkerKind;
+use crate::schema::{leases, scheduled_archivals, workers};
+
+/// A worker task of an archiver or tracker, which sends heartbeats while it is alive
+#[derive(Deserialize, Serialize, std::fmt::Debug, Queryable, Identifiable, Selectable)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Worker {
+    pub id: uuid::Uuid,
+    pub host: String,
+    pub registered_at: chrono::DateTime<Utc>,
+    pub last_heartbeat: chrono::DateTime<Utc>,
+    pub kind: WorkerKind,
+    pub version: String,
+    /// url of the archival or collection being processed
+    pub current_job: Option<String>,
+    pub job_started_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// the name of the host the worker runs on. In kubernetes this is the name of the pod
+pub fn host_name() -> String {
+    std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Registers the workers or updates their heartbeat if they are registered already.
+/// A worker that has been considered dead is registered again, its previous leases are gone though
+pub async fn heartbeat(
+    db_connection: &mut AsyncPgConnection,
+    worker_ids: &[uuid::Uuid],
+    host: &str,
+    kind: WorkerKind,
+    version: &str,
+) -> QueryResult<()> {
+    // all worker tasks of the process may have died
+    if worker_ids.is_empty() {
+        return Ok(());
+    }
+    insert_into(workers::table)
+        .values(
+            worker_ids
+                .iter()
+                .map(|id| {
+                    (
+                        workers::id.eq(id),
+                        workers::host.eq(host),
+                        workers::kind.eq(kind),
+                        workers::version.eq(version),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .on_conflict(workers::id)
+        .do_update()
+        .set(workers::last_heartbeat.eq(excluded(workers::last_heartbeat)))
+        .execute(db_connection)
+        .await?;
+    Ok(())
+}
+
+/// sets the url the worker is processing, or clears it if job is None
+pub async fn set_current_job(
+    db_connection: &mut AsyncPgConnection,
+    worker_id: uuid::Uuid,
+    job: Option<&str>,
+) -> QueryResult<()> {
+    update(workers::table)
+        .set((
+            workers::current_job.eq(job),
+            workers::job_started_at.eq(job.map(|_| Utc::now())),
+        ))
+        .filter(workers::id.eq(worker_id))
+        .execute(db_connection)
+        .await?;
+    Ok(())
+}
+
+/// Removes workers without a heartbeat within timeout along with their leases.
+/// Their schedules are requeued right away, instead of waiting for archiver_archiving_timeout_seconds. Returns the number of removed workers
+pub async fn remove_dead_workers(
+    db_connection: &mut AsyncPgConnection,
+    timeout: Duration,
+) -> QueryResult<usize> {
+    db_connection
+        .transaction::<usize, diesel::result::Error, _>(|db_connection| {
+            async move {
+                let dead_workers = workers::table
+                    .filter(workers::last_heartbeat.lt(Utc::now() - timeout))
+                    .select(workers::id)
+                    .for_update()
+                    .skip_locked()
+                    .load::<uuid::Uuid>(db_connection)
+                    .await?;
+                if dead_workers.is_empty() {
+                    return Ok(0);
+                }
+                remove_workers(db_connection, &dead_workers).await
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// Removes the workers of a process that is shutting down along with their leases.
+/// Schedules they haven't finished are requeued right away, so another replica can pick them up. Returns the number of removed workers
+pub async fn deregister(
+    db_connection: &mut AsyncPgConnection,
+    worker_ids: &[uuid::Uuid],
+) -> QueryResult<usize> {
+    db_connection
+        .transaction::<usize, diesel::result::Error, _>(|db_connection| {
+            async move { remove_workers(db_connection, worker_ids).await }.scope_boxed()
+        })
+        .await
+}
+
+async fn remove_workers(
+    db_connection: &mut AsyncPgConnection,
+    worker_ids: &[uuid::Uuid],
+) -> QueryResult<usize> {
+    let leased_schedules = leases::table
+        .filter(leases::worker_id.eq_any(worker_ids))
+        .select(leases::scheduled_archival_id);
+    update(scheduled_archivals::table)
+        .set(scheduled_archivals::not_before.eq(Utc::now()))
+        .filter(scheduled_archivals::id.eq_any(leased_schedules))
+        .execute(db_connection)
+        .await?;
+
+    // leases are deleted along with their worker
+    delete(workers::table)
+        .filter(workers::id.eq_any(worker_ids))
+        .execute(db_connection)
+        .await
+}
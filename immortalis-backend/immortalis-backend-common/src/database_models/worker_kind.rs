@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// the service a worker belongs to
+#[derive(diesel_derive_enum::DbEnum, Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[ExistingTypePath = "crate::schema::sql_types::WorkerKind"]
+// matches the labels postgres uses in notifications
+#[serde(rename_all = "snake_case")]
+pub enum WorkerKind {
+    Archiver,
+    Tracker,
+}
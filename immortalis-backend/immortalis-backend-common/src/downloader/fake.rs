@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{collection_entries, Downloader, DownloaderError};
+use crate::video_metadata::VideoMetadata;
+
+/// Downloader which serves fixtures instead of accessing the network.
+/// Fixtures are in the format printed by yt-dlp -J and are looked up by their webpage_url. Downloads and thumbnails are placeholder files whose content depends on the url
+#[derive(Default)]
+pub struct FakeDownloader {
+    fixtures: Mutex<HashMap<String, serde_json::Value>>,
+}
+
+impl FakeDownloader {
+    pub fn new() -> FakeDownloader {
+        FakeDownloader::default()
+    }
+
+    /// loads every .json file in directory as a fixture
+    pub fn from_fixture_directory(directory: &Path) -> Result<FakeDownloader, DownloaderError> {
+        let downloader = FakeDownloader::new();
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |e| e == "json") {
+                downloader.add_fixture(serde_json::from_slice(&std::fs::read(path)?)?);
+            }
+        }
+        Ok(downloader)
+    }
+
+    /// adds or replaces a fixture. The entries of a collection are added as fixtures as well, so they can be archived
+    pub fn add_fixture(&self, fixture: serde_json::Value) {
+        let mut fixtures = self.fixtures.lock().unwrap();
+        if let Some(entries) = fixture["entries"].as_array() {
+            for entry in entries.iter().filter(|e| e["webpage_url"].is_string()) {
+                fixtures.insert(entry["webpage_url"].as_str().unwrap().into(), entry.clone());
+            }
+        }
+        if let Some(url) = fixture["webpage_url"].as_str() {
+            fixtures.insert(url.into(), fixture);
+        }
+    }
+
+    pub fn remove_fixture(&self, url: &str) {
+        self.fixtures.lock().unwrap().remove(url);
+    }
+
+    fn fixture(&self, url: &str) -> Result<serde_json::Value, DownloaderError> {
+        self.fixtures
+            .lock()
+            .unwrap()
+            .get(url)
+            .cloned()
+            .ok_or_else(|| DownloaderError::Unavailable(format!("no fixture for {}", url)))
+    }
+}
+
+#[async_trait]
+impl Downloader for FakeDownloader {
+    async fn fetch_metadata(&self, url: &str) -> Result<VideoMetadata, DownloaderError> {
+        Ok(VideoMetadata::from_value(self.fixture(url)?)?)
+    }
+
+    async fn list_collection(&self, url: &str) -> Result<Vec<VideoMetadata>, DownloaderError> {
+        collection_entries(self.fixture(url)?)
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        destination: &Path,
+        _limit_rate: Option<u64>,
+    ) -> Result<(), DownloaderError> {
+        self.fixture(url)?;
+        tokio::fs::write(destination, format!("video of {}", url)).await?;
+        Ok(())
+    }
+
+    async fn fetch_thumbnail(&self, url: &str) -> Result<(Vec<u8>, String), DownloaderError> {
+        Ok((format!("thumbnail of {}", url).into_bytes(), "jpg".into()))
+    }
+}
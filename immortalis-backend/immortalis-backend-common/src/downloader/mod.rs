@@ -0,0 +1,88 @@
+pub mod fake;
+pub mod request_limiter;
+pub mod yt_dlp;
+
+use std::path::Path;
+
+use async_trait::async_trait;
+
+use crate::video_metadata::VideoMetadata;
+
+/// Source of videos and their metadata. The archiver and tracker only access videos through this, so they can run against fixtures
+#[async_trait]
+pub trait Downloader: Send + Sync {
+    async fn fetch_metadata(&self, url: &str) -> Result<VideoMetadata, DownloaderError>;
+    /// returns the entries of a playlist or channel. A single video is returned as the only entry
+    async fn list_collection(&self, url: &str) -> Result<Vec<VideoMetadata>, DownloaderError>;
+    /// downloads the video to destination. Downloading to the same destination again continues an interrupted download.
+    /// limit_rate in bytes per second takes precedence over a configured limit
+    async fn download(
+        &self,
+        url: &str,
+        destination: &Path,
+        limit_rate: Option<u64>,
+    ) -> Result<(), DownloaderError>;
+    /// downloads the image at url and returns its content along with its extension
+    async fn fetch_thumbnail(&self, url: &str) -> Result<(Vec<u8>, String), DownloaderError>;
+}
+
+#[derive(Debug)]
+pub enum DownloaderError {
+    Io(std::io::Error),
+    Http(reqwest::Error),
+    Json(serde_json::Error),
+    /// the video or collection has been removed, made private or its account terminated
+    Unavailable(String),
+    /// yt-dlp failed for another reason, like a network error or rate limiting, which may not persist
+    Failed(String),
+}
+
+impl std::fmt::Display for DownloaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloaderError::Io(e) => write!(f, "io error: {}", e),
+            DownloaderError::Http(e) => write!(f, "http error: {}", e),
+            DownloaderError::Json(e) => write!(f, "invalid metadata: {}", e),
+            DownloaderError::Unavailable(e) => write!(f, "unavailable: {}", e),
+            DownloaderError::Failed(e) => write!(f, "yt-dlp failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DownloaderError {}
+
+impl From<std::io::Error> for DownloaderError {
+    fn from(e: std::io::Error) -> Self {
+        DownloaderError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for DownloaderError {
+    fn from(e: reqwest::Error) -> Self {
+        DownloaderError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for DownloaderError {
+    fn from(e: serde_json::Error) -> Self {
+        DownloaderError::Json(e)
+    }
+}
+
+/// parses the output of yt-dlp -J. Entries which couldn't be extracted are null and skipped
+pub fn collection_entries(
+    mut value: serde_json::Value,
+) -> Result<Vec<VideoMetadata>, DownloaderError> {
+    if value["_type"] != serde_json::json!("playlist") {
+        return Ok(vec![VideoMetadata::from_value(value)?]);
+    }
+
+    match value["entries"].take() {
+        serde_json::Value::Array(entries) => entries
+            .into_iter()
+            .filter(|entry| !entry.is_null())
+            .map(|entry| VideoMetadata::from_value(entry).map_err(DownloaderError::from))
+            .collect(),
+        _ => Ok(Vec::new()),
+    }
+}
@@ -0,0 +1,24 @@
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// Spreads requests evenly over a minute. Waiting requests are admitted in the order they arrived
+pub struct RequestLimiter {
+    interval: Duration,
+    next_request: Mutex<Instant>,
+}
+
+impl RequestLimiter {
+    pub fn new(requests_per_minute: u32) -> RequestLimiter {
+        RequestLimiter {
+            interval: Duration::from_secs(60) / requests_per_minute.max(1),
+            next_request: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// waits until the next request is allowed
+    pub async fn acquire(&self) {
+        let mut next_request = self.next_request.lock().await;
+        tokio::time::sleep_until(*next_request).await;
+        *next_request = Instant::now().max(*next_request) + self.interval;
+    }
+}
@@ -0,0 +1,203 @@
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_process::Command;
+use async_trait::async_trait;
+use tracing::{info_span, Instrument};
+
+use super::request_limiter::RequestLimiter;
+use super::{collection_entries, Downloader, DownloaderError};
+use crate::env_var_config::{redact_credentials, YtDlpConfig};
+use crate::metrics::YT_DLP_EXIT_CODES;
+use crate::video_metadata::VideoMetadata;
+
+/// parts of the errors yt-dlp prints for videos and collections which are gone for good, as opposed to failures which may not persist
+const UNAVAILABLE_ERRORS: [&str; 9] = [
+    "video unavailable",
+    "private video",
+    "this video is private",
+    "has been removed",
+    "has been terminated",
+    "is no longer available",
+    "this channel does not exist",
+    "this playlist does not exist",
+    "the playlist does not exist",
+];
+
+/// Downloader which invokes the yt-dlp binary
+pub struct YtDlpDownloader {
+    config: YtDlpConfig,
+    next_proxy: AtomicUsize,
+    request_limiter: Option<RequestLimiter>,
+}
+
+impl YtDlpDownloader {
+    pub fn new(config: YtDlpConfig) -> YtDlpDownloader {
+        YtDlpDownloader {
+            request_limiter: config.yt_dlp_requests_per_minute.map(RequestLimiter::new),
+            config,
+            next_proxy: AtomicUsize::new(0),
+        }
+    }
+
+    /// creates an invocation of yt-dlp with the configured cookies, proxy and sleep interval. Waits until the request limit allows another invocation
+    async fn command(&self, url: &str) -> Command {
+        if let Some(request_limiter) = &self.request_limiter {
+            request_limiter.acquire().await;
+        }
+
+        let mut command = Command::new("yt-dlp");
+        // archivals cancelled during a shutdown don't leave yt-dlp running
+        command.arg(url).kill_on_drop(true);
+        if let Some(cookies_file) = &self.config.yt_dlp_cookies_file {
+            command.arg("--cookies").arg(cookies_file);
+        }
+        if !self.config.yt_dlp_proxies.is_empty() {
+            let proxy =
+                self.next_proxy.fetch_add(1, Ordering::Relaxed) % self.config.yt_dlp_proxies.len();
+            command
+                .arg("--proxy")
+                .arg(&self.config.yt_dlp_proxies[proxy]);
+        }
+        if let Some(sleep_requests_seconds) = self.config.yt_dlp_sleep_requests_seconds {
+            command
+                .arg("--sleep-requests")
+                .arg(sleep_requests_seconds.to_string());
+        }
+        command
+    }
+
+    /// Tells videos which are gone apart from failures which may not persist. yt-dlp may print the proxy it failed to connect to, so its credentials are removed from the error
+    fn error(&self, stderr: &[u8]) -> DownloaderError {
+        let mut message = String::from_utf8_lossy(stderr).into_owned();
+        for proxy in &self.config.yt_dlp_proxies {
+            message = message.replace(proxy, &redact_credentials(proxy));
+        }
+        if is_unavailable(&message) {
+            DownloaderError::Unavailable(message)
+        } else {
+            DownloaderError::Failed(message)
+        }
+    }
+
+    /// counts the exit code of an invocation, command tells metadata extractions and downloads apart
+    fn record_exit(command: &str, status: ExitStatus) {
+        let code = status
+            .code()
+            .map_or_else(|| "signal".to_string(), |code| code.to_string());
+        YT_DLP_EXIT_CODES.with_label_values(&[command, &code]).inc();
+    }
+
+    /// runs yt-dlp -J. Scheduled streams and premieres have no formats yet, without --ignore-no-formats-error yt-dlp fails for them
+    async fn dump_json(&self, url: &str) -> Result<serde_json::Value, DownloaderError> {
+        let output = self
+            .command(url)
+            .await
+            .arg("-J")
+            .arg("--ignore-no-formats-error")
+            .output()
+            .instrument(info_span!("yt_dlp", command = "metadata", url))
+            .await?;
+        Self::record_exit("metadata", output.status);
+
+        // yt-dlp exits with 1 if single entries of a collection fail, but still prints the collection
+        if !output.status.success() && output.stdout.is_empty() {
+            return Err(self.error(&output.stderr));
+        }
+
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+}
+
+#[async_trait]
+impl Downloader for YtDlpDownloader {
+    async fn fetch_metadata(&self, url: &str) -> Result<VideoMetadata, DownloaderError> {
+        Ok(VideoMetadata::from_value(self.dump_json(url).await?)?)
+    }
+
+    async fn list_collection(&self, url: &str) -> Result<Vec<VideoMetadata>, DownloaderError> {
+        collection_entries(self.dump_json(url).await?)
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        destination: &Path,
+        limit_rate: Option<u64>,
+    ) -> Result<(), DownloaderError> {
+        let mut command = self.command(url).await;
+        if let Some(limit_rate) = limit_rate {
+            command.arg("--limit-rate").arg(limit_rate.to_string());
+        } else if let Some(limit_rate) = &self.config.yt_dlp_limit_rate {
+            command.arg("--limit-rate").arg(limit_rate);
+        }
+
+        let output = command
+            .arg("-o")
+            .arg(destination)
+            .arg("--embed-thumbnail") // webm doesnt support embedded thumbnails, so we should get .mkv files
+            .arg("--embed-metadata")
+            .arg("--embed-chapters")
+            .arg("--embed-info-json")
+            .arg("--embed-subs")
+            .arg("--wait-for-video")
+            .arg("60")
+            .arg("--live-from-start")
+            .arg("--no-simulate")
+            .output()
+            .instrument(info_span!("yt_dlp", command = "download", url))
+            .await?;
+        Self::record_exit("download", output.status);
+
+        if !output.status.success() {
+            return Err(self.error(&output.stderr));
+        }
+        Ok(())
+    }
+
+    async fn fetch_thumbnail(&self, url: &str) -> Result<(Vec<u8>, String), DownloaderError> {
+        let resp = reqwest::get(url).await?.error_for_status()?;
+        let mut thumbnail_extension = url.split('.').last().unwrap();
+        thumbnail_extension = &thumbnail_extension[0..thumbnail_extension
+            .find('?')
+            .unwrap_or(thumbnail_extension.len())]; // trim params that may follow the extension
+
+        // the image has to be fully downloaded to compute its checksum before it can be stored. Thumbnails are small, so it is kept in memory
+        let resp = resp.bytes().await?;
+
+        Ok((resp.to_vec(), thumbnail_extension.into()))
+    }
+}
+
+fn is_unavailable(message: &str) -> bool {
+    let message = message.to_lowercase();
+    UNAVAILABLE_ERRORS
+        .iter()
+        .any(|unavailable| message.contains(unavailable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_unavailable;
+
+    #[test]
+    fn test_is_unavailable() {
+        assert!(is_unavailable(
+            "ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. This video has been removed by the uploader"
+        ));
+        assert!(is_unavailable(
+            "ERROR: [youtube] dQw4w9WgXcQ: Private video. Sign in if you've been granted access to this video"
+        ));
+        assert!(is_unavailable("ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. This video is no longer available because the YouTube account associated with this video has been terminated."));
+        assert!(!is_unavailable(
+            "ERROR: [youtube] dQw4w9WgXcQ: Sign in to confirm you're not a bot"
+        ));
+        assert!(!is_unavailable(
+            "ERROR: Unable to download webpage: HTTP Error 429: Too Many Requests"
+        ));
+        assert!(!is_unavailable(
+            "ERROR: Unable to download webpage: <urlopen error [Errno -3] Temporary failure in name resolution>"
+        ));
+    }
+}
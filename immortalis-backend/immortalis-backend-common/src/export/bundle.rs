@@ -0,0 +1,172 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use diesel::{OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use super::{load_catalogue_page, ExportError, ExportFilter, EXPORT_PAGE_SIZE};
+use crate::database_models::tracked_collection::TrackedCollection;
+use crate::database_models::video_status::VideoStatus;
+use crate::schema::tracked_collections;
+use crate::storage::content_addressed;
+use crate::storage::Storage;
+
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+/// describes the bundle, written to manifest.json
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleManifest {
+    pub format: &'static str,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub filter: ExportFilter,
+    /// the collection selected by the filter
+    pub tracked_collection: Option<TrackedCollection>,
+    pub video_count: usize,
+    /// size of all videos and thumbnails in bytes
+    pub media_size: u64,
+}
+
+/// Writes the archived videos matching filter as a tar to output. The tar contains
+/// - videos/{id}/metadata.json, the entry of the video in the catalogue
+/// - videos/{id}/video.{extension} and videos/{id}/thumbnail.{extension}
+/// - manifest.json, describing the bundle
+/// - SHA256SUMS, the checksums of all other files in the format of sha256sum
+///
+/// Videos and thumbnails are copied to temp_file_storage_location one at a time before they are added, the tar is written on a blocking thread
+pub async fn write_bundle(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    filter: &ExportFilter,
+    output: &Path,
+    temp_file_storage_location: &Path,
+) -> Result<BundleManifest, ExportError> {
+    let mut builder = tar::Builder::new(File::create(output)?);
+    let mut checksums = String::new();
+    let mut manifest = BundleManifest {
+        format: "immortalis-bundle",
+        version: BUNDLE_FORMAT_VERSION,
+        created_at: Utc::now(),
+        filter: filter.clone(),
+        tracked_collection: match filter.tracked_collection_id {
+            Some(id) => tracked_collections::table
+                .find(id)
+                .first::<TrackedCollection>(db_connection)
+                .await
+                .optional()?,
+            None => None,
+        },
+        video_count: 0,
+        media_size: 0,
+    };
+
+    let mut after_id = 0;
+    loop {
+        let page = load_catalogue_page(db_connection, filter, after_id, EXPORT_PAGE_SIZE).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after_id = last.video.id;
+
+        for entry in page {
+            // videos which are still being archived have no complete files yet
+            if entry.video.status != VideoStatus::Archived {
+                continue;
+            }
+            let (Some(file), Some(thumbnail)) = (&entry.file, &entry.thumbnail) else {
+                continue;
+            };
+
+            let directory = format!("videos/{}", entry.video.id);
+            let metadata = serde_json::to_vec_pretty(&entry)?;
+            append_bytes(
+                &mut builder,
+                &mut checksums,
+                &format!("{}/metadata.json", directory),
+                &metadata,
+            )?;
+
+            for (name, file) in [("video", file), ("thumbnail", thumbnail)] {
+                let path = format!("{}/{}.{}", directory, name, file.file_extension);
+                let temp_file =
+                    TempFile(temp_file_storage_location.join(format!("export-{}", file.id)));
+                storage.get_file(&file.storage_key(), &temp_file.0).await?;
+                let (checksum, size) = content_addressed::checksum_file(&temp_file.0).await?;
+                let name = path.clone();
+                builder = blocking(builder, move |builder| {
+                    builder.append_path_with_name(&temp_file.0, &name)
+                })
+                .await?
+                .0;
+
+                checksums.push_str(&format!("{}  {}\n", checksum, path));
+                manifest.media_size += size as u64;
+            }
+            manifest.video_count += 1;
+        }
+    }
+
+    append_bytes(
+        &mut builder,
+        &mut checksums,
+        "manifest.json",
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    let mut sums = tar::Header::new_gnu();
+    sums.set_size(checksums.len() as u64);
+    sums.set_mode(0o644);
+    sums.set_mtime(Utc::now().timestamp() as u64);
+    builder.append_data(&mut sums, "SHA256SUMS", checksums.as_bytes())?;
+    tokio::task::spawn_blocking(move || builder.into_inner()?.sync_all())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))??;
+
+    Ok(manifest)
+}
+
+/// a file copied from the storage, which is removed once it has been added or the export failed
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// runs f on a blocking thread, so writing gigabytes doesn't block the runtime. Returns the builder for the next call
+async fn blocking<T: Send + 'static>(
+    mut builder: tar::Builder<File>,
+    f: impl FnOnce(&mut tar::Builder<File>) -> Result<T, std::io::Error> + Send + 'static,
+) -> Result<(tar::Builder<File>, T), ExportError> {
+    let (builder, result) = tokio::task::spawn_blocking(move || {
+        let result = f(&mut builder);
+        (builder, result)
+    })
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    Ok((builder, result?))
+}
+
+/// adds bytes as a file at path and records its checksum
+fn append_bytes<W: Write>(
+    builder: &mut tar::Builder<W>,
+    checksums: &mut String,
+    path: &str,
+    bytes: &[u8],
+) -> Result<(), std::io::Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, path, bytes)?;
+    checksums.push_str(&format!(
+        "{}  {}\n",
+        content_addressed::checksum_bytes(bytes),
+        path
+    ));
+    Ok(())
+}
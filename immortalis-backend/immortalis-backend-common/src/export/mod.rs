@@ -0,0 +1,286 @@
+pub mod bundle;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::str::FromStr;
+
+use diesel::{ExpressionMethods, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
+
+use crate::database_models::file::File;
+use crate::database_models::video::Video;
+use crate::schema::{files, videos};
+use crate::storage::StorageError;
+
+/// number of videos loaded at once while exporting
+pub const EXPORT_PAGE_SIZE: i64 = 500;
+
+/// a video along with its files, as written to exports
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogueEntry {
+    #[serde(flatten)]
+    pub video: Video,
+    pub file: Option<File>,
+    pub thumbnail: Option<File>,
+}
+
+/// Selects the videos to export. Videos have to match all given criteria, an empty filter selects every video
+#[derive(Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportFilter {
+    pub video_ids: Vec<i32>,
+    pub tracked_collection_id: Option<i32>,
+    pub channel: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatalogueFormat {
+    /// one JSON object per line
+    Jsonl,
+    Csv,
+}
+
+impl FromStr for CatalogueFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(CatalogueFormat::Jsonl),
+            "csv" => Ok(CatalogueFormat::Csv),
+            _ => Err(format!("unknown format {}, expected jsonl or csv", s)),
+        }
+    }
+}
+
+const CSV_COLUMNS: [&str; 22] = [
+    "id",
+    "title",
+    "channel",
+    "views",
+    "upload_date",
+    "archived_date",
+    "duration",
+    "original_url",
+    "status",
+    "live_status",
+    "release_timestamp",
+    "is_live_recording",
+    "tracked_collection_id",
+    "file_id",
+    "file_name",
+    "file_extension",
+    "file_size",
+    "file_checksum",
+    "thumbnail_id",
+    "thumbnail_extension",
+    "thumbnail_size",
+    "thumbnail_checksum",
+];
+
+impl CatalogueFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CatalogueFormat::Jsonl => "jsonl",
+            CatalogueFormat::Csv => "csv",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            CatalogueFormat::Jsonl => "application/x-ndjson",
+            CatalogueFormat::Csv => "text/csv",
+        }
+    }
+
+    /// the line written before the first entry, if the format has one
+    pub fn header(&self) -> Option<String> {
+        match self {
+            CatalogueFormat::Jsonl => None,
+            CatalogueFormat::Csv => Some(CSV_COLUMNS.join(",") + "\n"),
+        }
+    }
+
+    /// formats the entry as a line, including its line break
+    pub fn format(&self, entry: &CatalogueEntry) -> Result<String, serde_json::Error> {
+        match self {
+            CatalogueFormat::Jsonl => Ok(serde_json::to_string(entry)? + "\n"),
+            CatalogueFormat::Csv => Ok(csv_row(entry)),
+        }
+    }
+}
+
+fn csv_row(entry: &CatalogueEntry) -> String {
+    let video = &entry.video;
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    let fields = [
+        video.id.to_string(),
+        video.title.clone(),
+        video.channel.clone(),
+        video.views.to_string(),
+        video.upload_date.to_rfc3339(),
+        video.archived_date.to_rfc3339(),
+        video.duration.to_string(),
+        video.original_url.clone(),
+        format!("{:?}", video.status),
+        optional(video.live_status.map(|s| format!("{:?}", s))),
+        optional(video.release_timestamp.map(|t| t.to_rfc3339())),
+        video.is_live_recording.to_string(),
+        optional(video.tracked_collection_id.map(|id| id.to_string())),
+        video.file_id.to_string(),
+        optional(entry.file.as_ref().map(|f| f.file_name.clone())),
+        optional(entry.file.as_ref().map(|f| f.file_extension.clone())),
+        optional(entry.file.as_ref().map(|f| f.size.to_string())),
+        optional(entry.file.as_ref().and_then(|f| f.checksum.clone())),
+        video.thumbnail_id.to_string(),
+        optional(entry.thumbnail.as_ref().map(|f| f.file_extension.clone())),
+        optional(entry.thumbnail.as_ref().map(|f| f.size.to_string())),
+        optional(entry.thumbnail.as_ref().and_then(|f| f.checksum.clone())),
+    ];
+    fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",")
+        + "\n"
+}
+
+/// quotes the field if it contains a separator, quote or line break (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// writes the catalogue entries matching filter to writer. Returns the number of written entries
+pub async fn write_catalogue<W: Write>(
+    db_connection: &mut AsyncPgConnection,
+    format: CatalogueFormat,
+    filter: &ExportFilter,
+    writer: &mut W,
+) -> Result<usize, ExportError> {
+    if let Some(header) = format.header() {
+        writer.write_all(header.as_bytes())?;
+    }
+    let mut written = 0;
+    let mut after_id = 0;
+    loop {
+        let page = load_catalogue_page(db_connection, filter, after_id, EXPORT_PAGE_SIZE).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after_id = last.video.id;
+        for entry in &page {
+            writer.write_all(format.format(entry)?.as_bytes())?;
+        }
+        written += page.len();
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+/// loads up to limit videos matching filter with an id greater than after_id, ordered by id
+pub async fn load_catalogue_page(
+    db_connection: &mut AsyncPgConnection,
+    filter: &ExportFilter,
+    after_id: i32,
+    limit: i64,
+) -> QueryResult<Vec<CatalogueEntry>> {
+    let mut query = videos::table
+        .filter(videos::id.gt(after_id))
+        .order(videos::id)
+        .limit(limit)
+        .into_boxed();
+    if !filter.video_ids.is_empty() {
+        query = query.filter(videos::id.eq_any(filter.video_ids.clone()));
+    }
+    if let Some(tracked_collection_id) = filter.tracked_collection_id {
+        query = query.filter(videos::tracked_collection_id.eq(tracked_collection_id));
+    }
+    if let Some(channel) = &filter.channel {
+        query = query.filter(videos::channel.eq(channel.clone()));
+    }
+    let videos = query.load::<Video>(db_connection).await?;
+
+    let file_ids: Vec<uuid::Uuid> = videos
+        .iter()
+        .flat_map(|video| [video.file_id, video.thumbnail_id])
+        .collect();
+    let files: HashMap<uuid::Uuid, File> = files::table
+        .filter(files::id.eq_any(&file_ids))
+        .load::<File>(db_connection)
+        .await?
+        .into_iter()
+        .map(|file| (file.id, file))
+        .collect();
+
+    Ok(videos
+        .into_iter()
+        .map(|video| CatalogueEntry {
+            file: files.get(&video.file_id).cloned(),
+            thumbnail: files.get(&video.thumbnail_id).cloned(),
+            video,
+        })
+        .collect())
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Database(diesel::result::Error),
+    Storage(StorageError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Database(e) => write!(f, "database error: {}", e),
+            ExportError::Storage(e) => write!(f, "storage error: {}", e),
+            ExportError::Io(e) => write!(f, "io error: {}", e),
+            ExportError::Json(e) => write!(f, "json error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<diesel::result::Error> for ExportError {
+    fn from(e: diesel::result::Error) -> Self {
+        ExportError::Database(e)
+    }
+}
+
+impl From<StorageError> for ExportError {
+    fn from(e: StorageError) -> Self {
+        ExportError::Storage(e)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> Self {
+        ExportError::Json(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
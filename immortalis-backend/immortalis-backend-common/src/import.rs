@@ -0,0 +1,306 @@
+use std::path::{Path, PathBuf};
+
+use diesel::{insert_into, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use tokio::fs;
+use tracing::{error, info, warn};
+
+use crate::database_models::file::File;
+use crate::database_models::video::InsertableVideo;
+use crate::database_models::video_status::VideoStatus;
+use crate::downloader::Downloader;
+use crate::schema::{files, scheduled_archivals, videos};
+use crate::storage::content_addressed::{self, BlobError};
+use crate::storage::Storage;
+use crate::video_metadata::VideoMetadata;
+
+const INFO_JSON_SUFFIX: &str = ".info.json";
+/// extensions of media files, tried if the file with the extension of the metadata doesn't exist
+const MEDIA_EXTENSIONS: [&str; 9] = [
+    "mkv", "mp4", "webm", "mov", "flv", "m4a", "mp3", "opus", "ogg",
+];
+const THUMBNAIL_EXTENSIONS: [&str; 4] = ["webp", "jpg", "jpeg", "png"];
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// archived or scheduled already
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+enum ImportResult {
+    Imported,
+    Skipped,
+}
+
+/// Imports a directory downloaded with yt-dlp --write-info-json. Every video whose .info.json is found within directory or its subdirectories is
+/// registered as archived, along with the media file and thumbnail next to it. Videos which are archived or scheduled already are skipped.
+///
+/// Files are staged in temp_file_storage_location before they are stored. If copy is set the directory stays untouched, otherwise the files of a video are removed once it has been registered.
+/// A video without a thumbnail on disk gets the thumbnail referenced by its metadata, if a downloader is given
+pub async fn import_directory(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    downloader: Option<&dyn Downloader>,
+    directory: &Path,
+    temp_file_storage_location: &Path,
+    copy: bool,
+) -> Result<ImportSummary, std::io::Error> {
+    let mut summary = ImportSummary::default();
+    let mut directories = vec![directory.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        let mut entries = fs::read_dir(&directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                directories.push(path);
+                continue;
+            }
+            if !path.to_string_lossy().ends_with(INFO_JSON_SUFFIX) {
+                continue;
+            }
+
+            match import_video(
+                db_connection,
+                storage,
+                downloader,
+                &path,
+                temp_file_storage_location,
+                copy,
+            )
+            .await
+            {
+                Ok(Some(ImportResult::Imported)) => summary.imported += 1,
+                Ok(Some(ImportResult::Skipped)) => summary.skipped += 1,
+                // playlists and channels have an info.json as well
+                Ok(None) => (),
+                Err(e) => {
+                    error!("Failed to import {}, {}", path.display(), e);
+                    summary.failed += 1;
+                }
+            }
+        }
+    }
+    Ok(summary)
+}
+
+async fn import_video(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    downloader: Option<&dyn Downloader>,
+    info_json: &Path,
+    temp_file_storage_location: &Path,
+    copy: bool,
+) -> Result<Option<ImportResult>, String> {
+    let value: serde_json::Value = serde_json::from_slice(
+        &fs::read(info_json)
+            .await
+            .map_err(|e| format!("could not read the metadata: {}", e))?,
+    )
+    .map_err(|e| format!("invalid metadata: {}", e))?;
+    if value["_type"] == serde_json::json!("playlist") {
+        return Ok(None);
+    }
+    let metadata =
+        VideoMetadata::from_value(value).map_err(|e| format!("invalid metadata: {}", e))?;
+    let (Some(url), Some(_)) = (metadata.video.webpage_url.clone(), &metadata.video.channel) else {
+        return Err("the metadata has no webpage_url or channel".to_string());
+    };
+
+    if is_known(db_connection, &url)
+        .await
+        .map_err(|e| format!("database error: {}", e))?
+    {
+        info!("Skipped {}, it is archived or scheduled already", url);
+        return Ok(Some(ImportResult::Skipped));
+    }
+
+    // yt-dlp names the media and thumbnail like the info.json, without its suffix
+    let info_json_name = info_json.to_string_lossy();
+    let stem = &info_json_name[..info_json_name.len() - INFO_JSON_SUFFIX.len()];
+    let media_extensions = metadata
+        .video
+        .ext
+        .iter()
+        .map(String::as_str)
+        .chain(MEDIA_EXTENSIONS);
+    let Some((media_source, media_extension)) = find_sibling(stem, media_extensions).await else {
+        return Err("no media file found".to_string());
+    };
+    let thumbnail_source = find_sibling(stem, THUMBNAIL_EXTENSIONS).await;
+    let thumbnail = match &thumbnail_source {
+        Some((path, extension)) => Thumbnail::File(
+            stage(path, extension, temp_file_storage_location, !copy)
+                .await
+                .map_err(|e| format!("could not copy the thumbnail: {}", e))?,
+            extension.clone(),
+        ),
+        None => {
+            let thumbnail_url = metadata.video.thumbnail.clone().unwrap_or_default();
+            match downloader {
+                Some(downloader) if !thumbnail_url.is_empty() => {
+                    let (bytes, extension) = downloader
+                        .fetch_thumbnail(&thumbnail_url)
+                        .await
+                        .map_err(|e| format!("could not fetch the thumbnail: {}", e))?;
+                    Thumbnail::Bytes(bytes, extension)
+                }
+                _ => return Err("no thumbnail found".to_string()),
+            }
+        }
+    };
+    let media = match stage(
+        &media_source,
+        &media_extension,
+        temp_file_storage_location,
+        !copy,
+    )
+    .await
+    {
+        Ok(media) => media,
+        Err(e) => {
+            if let Thumbnail::File(path, _) = &thumbnail {
+                fs::remove_file(path).await.ok();
+            }
+            return Err(format!("could not copy the media: {}", e));
+        }
+    };
+    // storing keeps the staged files, they are removed once the transaction has finished
+    let staged = match &thumbnail {
+        Thumbnail::File(path, _) => vec![media.clone(), path.clone()],
+        Thumbnail::Bytes(..) => vec![media.clone()],
+    };
+
+    let file_id = uuid::Uuid::new_v4();
+    let thumbnail_id = uuid::Uuid::new_v4();
+    let video = InsertableVideo::new(metadata, VideoStatus::Archived, file_id, thumbnail_id);
+
+    let result = db_connection
+        .transaction::<(), BlobError, _>(|db_connection| {
+            async move {
+                let (thumbnail_blob, thumbnail_extension) = match thumbnail {
+                    Thumbnail::File(path, extension) => (
+                        content_addressed::store_file(db_connection, storage, &path).await?,
+                        extension,
+                    ),
+                    Thumbnail::Bytes(bytes, extension) => (
+                        content_addressed::store_bytes(db_connection, storage, &bytes).await?,
+                        extension,
+                    ),
+                };
+                let media_blob =
+                    content_addressed::store_file(db_connection, storage, &media).await?;
+
+                insert_into(files::table)
+                    .values(vec![
+                        File {
+                            id: thumbnail_id,
+                            file_name: video.title.to_string(),
+                            file_extension: thumbnail_extension,
+                            size: thumbnail_blob.size,
+                            checksum: Some(thumbnail_blob.checksum),
+                        },
+                        File {
+                            id: file_id,
+                            file_name: video.title.to_string(),
+                            file_extension: media_extension,
+                            size: media_blob.size,
+                            checksum: Some(media_blob.checksum),
+                        },
+                    ])
+                    .execute(db_connection)
+                    .await?;
+
+                insert_into(videos::table)
+                    .values(&video)
+                    .execute(db_connection)
+                    .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await;
+    for path in staged {
+        fs::remove_file(path).await.ok();
+    }
+    if let Err(e) = result {
+        return Err(format!("could not store the video: {}", e));
+    }
+
+    // the sources are only removed once the video has been registered, so a failed import loses nothing
+    if !copy {
+        let sources = std::iter::once(media_source).chain(thumbnail_source.map(|(path, _)| path));
+        for source in sources {
+            if let Err(e) = fs::remove_file(&source).await {
+                warn!("Could not remove {}, {}", source.display(), e);
+            }
+        }
+    }
+    info!("Imported {} from {}", url, info_json.display());
+    Ok(Some(ImportResult::Imported))
+}
+
+enum Thumbnail {
+    File(PathBuf, String),
+    Bytes(Vec<u8>, String),
+}
+
+async fn is_known(
+    db_connection: &mut AsyncPgConnection,
+    url: &str,
+) -> Result<bool, diesel::result::Error> {
+    let archived = videos::table
+        .filter(videos::original_url.eq(url))
+        .select(videos::id)
+        .first::<i32>(db_connection)
+        .await
+        .optional()?
+        .is_some();
+    let scheduled = scheduled_archivals::table
+        .filter(scheduled_archivals::url.eq(url))
+        .select(scheduled_archivals::id)
+        .first::<i32>(db_connection)
+        .await
+        .optional()?
+        .is_some();
+    Ok(archived || scheduled)
+}
+
+/// returns the first existing file named {stem}.{extension} along with its extension
+async fn find_sibling<'a>(
+    stem: &str,
+    extensions: impl IntoIterator<Item = &'a str>,
+) -> Option<(PathBuf, String)> {
+    for extension in extensions {
+        let path = PathBuf::from(format!("{}.{}", stem, extension));
+        if fs::metadata(&path).await.map_or(false, |m| m.is_file()) {
+            return Some((path, extension.to_string()));
+        }
+    }
+    None
+}
+
+/// Returns the path to store, a copy of the file within temp_file_storage_location. The disk storage links the files it stores, so files of the directory are never stored themselves.
+/// If link is set, the copy is a hard link if possible. Only files which are removed afterwards may be linked, the stored object would change along with them otherwise
+async fn stage(
+    path: &Path,
+    extension: &str,
+    temp_file_storage_location: &Path,
+    link: bool,
+) -> Result<PathBuf, std::io::Error> {
+    let temp_file =
+        temp_file_storage_location.join(format!("import-{}.{}", uuid::Uuid::new_v4(), extension));
+    // a link avoids copying large media if the directory is on the same filesystem
+    if link && fs::hard_link(path, &temp_file).await.is_ok() {
+        return Ok(temp_file);
+    }
+    if let Err(e) = fs::copy(path, &temp_file).await {
+        warn!("Could not copy {}, {}", path.display(), e);
+        fs::remove_file(&temp_file).await.ok();
+        return Err(e);
+    }
+    Ok(temp_file)
+}
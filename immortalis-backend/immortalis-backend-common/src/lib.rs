@@ -1,5 +1,17 @@
+pub mod config;
 pub mod data_transfer_models;
 pub mod database_models;
+pub mod downloader;
 pub mod env_var_config;
+pub mod export;
+pub mod import;
+pub mod lifecycle_event;
+pub mod metrics;
+pub mod migrations;
 pub mod schema;
-pub mod utilities;
\ No newline at end of file
+pub mod shutdown;
+pub mod storage;
+pub mod telemetry;
+pub mod tracking;
+pub mod utilities;
+pub mod video_metadata;
\ No newline at end of file
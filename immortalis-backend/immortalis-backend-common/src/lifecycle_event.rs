@@ -0,0 +1,149 @@
+use diesel::sql_query;
+use diesel::sql_types::Text;
+use diesel::QueryResult;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+/// the channel lifecycle events are published on, see publish_lifecycle_event()
+pub const LIFECYCLE_CHANNEL: &str = "lifecycle_events";
+
+/// the types of lifecycle events webhooks can ask for
+pub const LIFECYCLE_EVENT_TYPES: [&str; 4] = [
+    "video.archived",
+    "video.failed",
+    "video.unavailable",
+    "collection.new_videos",
+];
+
+/// Milestones of an archival, published by the archiver and tracker. These are delivered to webhooks and sent on the lifecycle_events channel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum LifecycleEvent {
+    #[serde(rename = "video.archived", rename_all = "camelCase")]
+    VideoArchived {
+        video_id: i32,
+        url: String,
+        title: String,
+        tracked_collection_id: Option<i32>,
+    },
+    /// the archiver gave up after archiver_max_failed_attempts
+    #[serde(rename = "video.failed", rename_all = "camelCase")]
+    VideoFailed {
+        video_id: Option<i32>,
+        url: String,
+        tracked_collection_id: Option<i32>,
+        attempts: i32,
+    },
+    /// the archiver gave up, as the video has been removed upstream or isn't accessible anymore. Also published by the tracker once an archived video is removed or its collection doesn't list it anymore
+    #[serde(rename = "video.unavailable", rename_all = "camelCase")]
+    VideoUnavailable {
+        video_id: Option<i32>,
+        url: String,
+        tracked_collection_id: Option<i32>,
+        reason: String,
+    },
+    /// the tracker scheduled videos of the collection it hasn't seen before
+    #[serde(rename = "collection.new_videos", rename_all = "camelCase")]
+    CollectionNewVideos {
+        tracked_collection_id: i32,
+        url: String,
+        video_urls: Vec<String>,
+    },
+}
+
+impl LifecycleEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            LifecycleEvent::VideoArchived { .. } => "video.archived",
+            LifecycleEvent::VideoFailed { .. } => "video.failed",
+            LifecycleEvent::VideoUnavailable { .. } => "video.unavailable",
+            LifecycleEvent::CollectionNewVideos { .. } => "collection.new_videos",
+        }
+    }
+
+    pub fn video_id(&self) -> Option<i32> {
+        match self {
+            LifecycleEvent::VideoArchived { video_id, .. } => Some(*video_id),
+            LifecycleEvent::VideoFailed { video_id, .. }
+            | LifecycleEvent::VideoUnavailable { video_id, .. } => *video_id,
+            LifecycleEvent::CollectionNewVideos { .. } => None,
+        }
+    }
+
+    pub fn tracked_collection_id(&self) -> Option<i32> {
+        match self {
+            LifecycleEvent::VideoArchived {
+                tracked_collection_id,
+                ..
+            }
+            | LifecycleEvent::VideoFailed {
+                tracked_collection_id,
+                ..
+            }
+            | LifecycleEvent::VideoUnavailable {
+                tracked_collection_id,
+                ..
+            } => *tracked_collection_id,
+            LifecycleEvent::CollectionNewVideos {
+                tracked_collection_id,
+                ..
+            } => Some(*tracked_collection_id),
+        }
+    }
+
+    /// a summary for chat services, which display the text field of a payload
+    pub fn text(&self) -> String {
+        match self {
+            LifecycleEvent::VideoArchived { url, title, .. } => {
+                format!("Archived \"{}\" ({})", title, url)
+            }
+            LifecycleEvent::VideoFailed { url, attempts, .. } => {
+                format!("Gave up archiving {} after {} attempts", url, attempts)
+            }
+            LifecycleEvent::VideoUnavailable { url, reason, .. } => {
+                format!("{} is unavailable: {}", url, reason)
+            }
+            LifecycleEvent::CollectionNewVideos {
+                url, video_urls, ..
+            } => format!("Found {} new videos in {}", video_urls.len(), url),
+        }
+    }
+}
+
+/// Stores the event, queues its deliveries to the webhooks asking for it and notifies the api
+pub async fn publish(
+    db_connection: &mut AsyncPgConnection,
+    event: &LifecycleEvent,
+) -> QueryResult<()> {
+    let payload = serde_json::to_string(event).expect("could not serialize lifecycle event");
+    sql_query("SELECT publish_lifecycle_event($1, $2)")
+        .bind::<Text, _>(event.event_type())
+        .bind::<Text, _>(payload)
+        .execute(db_connection)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LifecycleEvent, LIFECYCLE_EVENT_TYPES};
+
+    #[test]
+    fn test_serialize() {
+        let event = LifecycleEvent::VideoFailed {
+            video_id: Some(1),
+            url: "https://www.youtube.com/watch?v=video".to_string(),
+            tracked_collection_id: None,
+            attempts: 3,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "video.failed");
+        assert_eq!(json["videoId"], 1);
+        assert_eq!(json["attempts"], 3);
+        assert_eq!(
+            serde_json::from_value::<LifecycleEvent>(json).unwrap(),
+            event
+        );
+        assert!(LIFECYCLE_EVENT_TYPES.contains(&event.event_type()));
+    }
+}
@@ -0,0 +1,284 @@
+use diesel::dsl::{count_star, sql};
+use diesel::sql_types::BigInt;
+use diesel::{QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info};
+
+use crate::database_models::archival_stage::ArchivalStage;
+use crate::schema::{blobs, scheduled_archivals};
+
+// metrics are registered once they are used first, so every service only exports the series it records
+
+/// archivals take from seconds for short videos up to hours for long streams
+const ARCHIVAL_DURATION_BUCKETS: &[f64] = &[
+    5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0, 7200.0, 14400.0,
+];
+const TRACKER_CHECK_DURATION_BUCKETS: &[f64] =
+    &[0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+pub static SCHEDULED_ARCHIVALS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "immortalis_scheduled_archivals",
+        "scheduled archivals by the stage they have reached",
+        &["stage"]
+    )
+    .unwrap()
+});
+
+pub static STORAGE_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "immortalis_storage_bytes",
+        "bytes of the blobs kept in the storage",
+        &["backend"]
+    )
+    .unwrap()
+});
+
+pub static DEQUEUE_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "immortalis_dequeue_duration_seconds",
+        "time taken to lease the next scheduled archival"
+    )
+    .unwrap()
+});
+
+pub static QUEUE_WAIT: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "immortalis_queue_wait_seconds",
+        "time a scheduled archival has been due before it was dequeued",
+        ARCHIVAL_DURATION_BUCKETS.to_vec()
+    )
+    .unwrap()
+});
+
+pub static ARCHIVAL_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "immortalis_archival_duration_seconds",
+        "time taken by an attempt to archive a scheduled archival",
+        &["archived"],
+        ARCHIVAL_DURATION_BUCKETS.to_vec()
+    )
+    .unwrap()
+});
+
+pub static ARCHIVAL_OUTCOMES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "immortalis_archival_outcomes_total",
+        "attempts to archive a scheduled archival by their outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static DOWNLOADED_BYTES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "immortalis_downloaded_bytes_total",
+        "bytes of the videos downloaded and stored by the archiver"
+    )
+    .unwrap()
+});
+
+pub static YT_DLP_EXIT_CODES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "immortalis_yt_dlp_exit_codes_total",
+        "invocations of yt-dlp by their exit code, signal if it has been killed",
+        &["command", "code"]
+    )
+    .unwrap()
+});
+
+pub static TRACKER_CHECK_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "immortalis_tracker_check_duration_seconds",
+        "time taken to check a tracked collection for new videos",
+        &["success"],
+        TRACKER_CHECK_DURATION_BUCKETS.to_vec()
+    )
+    .unwrap()
+});
+
+pub static WEBSOCKET_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "immortalis_websocket_connections",
+        "open websocket connections"
+    )
+    .unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "immortalis_http_request_duration_seconds",
+        "time taken to respond to requests to the api by their route",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+/// the outcomes counted by ARCHIVAL_OUTCOMES
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchivalOutcome {
+    Archived,
+    /// the attempt failed and the archival is retried later
+    Failed,
+    /// the archival has been given up after archiver_max_failed_attempts
+    GivenUp,
+    /// the video hasn't started or hasn't been processed yet
+    Postponed,
+}
+
+impl ArchivalOutcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArchivalOutcome::Archived => "archived",
+            ArchivalOutcome::Failed => "failed",
+            ArchivalOutcome::GivenUp => "given_up",
+            ArchivalOutcome::Postponed => "postponed",
+        }
+    }
+
+    pub fn record(&self) {
+        ARCHIVAL_OUTCOMES.with_label_values(&[self.name()]).inc();
+    }
+}
+
+/// Updates the gauges derived from the database, the queue depth by stage and the bytes in the storage.
+/// These are shared by all replicas, so only the api exports them
+pub async fn refresh_database_gauges(
+    db_connection: &mut AsyncPgConnection,
+    storage_backend: &str,
+) -> QueryResult<()> {
+    let stages = scheduled_archivals::table
+        .group_by(scheduled_archivals::stage)
+        .select((scheduled_archivals::stage, count_star()))
+        .load::<(ArchivalStage, i64)>(db_connection)
+        .await?;
+    // stages without schedules are reported as 0 instead of keeping their last value
+    for stage in [
+        ArchivalStage::Pending,
+        ArchivalStage::Registered,
+        ArchivalStage::Downloaded,
+        ArchivalStage::AwaitingVod,
+    ] {
+        let count = stages
+            .iter()
+            .find(|(s, _)| *s == stage)
+            .map_or(0, |(_, count)| *count);
+        SCHEDULED_ARCHIVALS
+            .with_label_values(&[stage_name(stage)])
+            .set(count);
+    }
+
+    // sums of bigints are numeric in postgres, the bytes fit into a bigint
+    let stored_bytes = blobs::table
+        .select(sql::<BigInt>("COALESCE(SUM(size), 0)::bigint"))
+        .first::<i64>(db_connection)
+        .await?;
+    STORAGE_BYTES
+        .with_label_values(&[storage_backend])
+        .set(stored_bytes);
+    Ok(())
+}
+
+fn stage_name(stage: ArchivalStage) -> &'static str {
+    match stage {
+        ArchivalStage::Pending => "pending",
+        ArchivalStage::Registered => "registered",
+        ArchivalStage::Downloaded => "downloaded",
+        ArchivalStage::AwaitingVod => "awaiting_vod",
+    }
+}
+
+/// the storage backend label of STORAGE_BYTES
+pub fn storage_backend(use_s3: bool) -> &'static str {
+    if use_s3 {
+        "s3"
+    } else {
+        "disk"
+    }
+}
+
+/// all registered metrics in the prometheus text format
+pub fn encode() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("could not encode metrics");
+    String::from_utf8(buffer).expect("metrics are valid utf-8")
+}
+
+/// the content type of encode()
+pub fn content_type() -> String {
+    TextEncoder::new().format_type().to_string()
+}
+
+/// Serves the metrics at /metrics of the port, for the services without an http server. 0 disables the listener
+pub async fn serve(port: u16) {
+    if port == 0 {
+        return;
+    }
+    let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Failed to listen for metrics on port {}, encountered error {}",
+                port, e
+            );
+            return;
+        }
+    };
+    info!("Serving metrics on port {}", port);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(respond(stream));
+            }
+            Err(e) => error!("Failed to accept metrics request, encountered error {}", e),
+        }
+    }
+}
+
+/// answers a single request, the connection is closed afterwards
+async fn respond(mut stream: TcpStream) {
+    // only the request line is needed, scrapers send short requests
+    let mut buffer = [0; 1024];
+    let read = match stream.read(&mut buffer).await {
+        Ok(read) => read,
+        Err(_) => return,
+    };
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+    let (status, content_type, body) = if path == "/metrics" {
+        ("200 OK", content_type(), encode())
+    } else {
+        ("404 Not Found", "text/plain".to_string(), String::new())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, ArchivalOutcome};
+
+    #[test]
+    fn test_encode() {
+        ArchivalOutcome::Postponed.record();
+        assert!(encode().contains("immortalis_archival_outcomes_total{outcome=\"postponed\"}"));
+    }
+}
@@ -0,0 +1,4 @@
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+
+/// the migrations in the migrations directory, embedded at compile time
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
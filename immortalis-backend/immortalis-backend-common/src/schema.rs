@@ -1,9 +1,38 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "archival_stage"))]
+    pub struct ArchivalStage;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "live_status"))]
+    pub struct LiveStatus;
+
     #[derive(diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "video_status"))]
     pub struct VideoStatus;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "worker_kind"))]
+    pub struct WorkerKind;
+}
+
+diesel::table! {
+    blobs (checksum) {
+        checksum -> Varchar,
+        size -> Int8,
+        reference_count -> Int4,
+    }
+}
+
+diesel::table! {
+    events (id) {
+        id -> Int8,
+        channel -> Varchar,
+        payload -> Text,
+        created_at -> Timestamptz,
+    }
 }
 
 diesel::table! {
@@ -12,15 +41,35 @@ diesel::table! {
         file_name -> Varchar,
         file_extension -> Varchar,
         size -> Int8,
+        checksum -> Nullable<Varchar>,
     }
 }
 
 diesel::table! {
+    leases (scheduled_archival_id) {
+        scheduled_archival_id -> Int4,
+        worker_id -> Uuid,
+        domain -> Nullable<Varchar>,
+        acquired_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ArchivalStage;
+
     scheduled_archivals (id) {
         id -> Int4,
         url -> Varchar,
         scheduled_at -> Timestamptz,
         not_before -> Timestamptz,
+        stage -> ArchivalStage,
+        video_id -> Nullable<Int4>,
+        domain -> Nullable<Varchar>,
+        priority -> Int2,
+        tracked_collection_id -> Nullable<Int4>,
+        failed_attempts -> Int4,
+        trace_context -> Nullable<Text>,
     }
 }
 
@@ -33,9 +82,18 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    unavailable_videos (video_id) {
+        video_id -> Int4,
+        reason -> Text,
+        detected_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::VideoStatus;
+    use super::sql_types::LiveStatus;
 
     videos (id) {
         id -> Int4,
@@ -49,12 +107,74 @@ diesel::table! {
         status -> VideoStatus,
         file_id -> Uuid,
         thumbnail_id -> Uuid,
+        release_timestamp -> Nullable<Timestamptz>,
+        live_status -> Nullable<LiveStatus>,
+        is_live_recording -> Bool,
+        tracked_collection_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        webhook_id -> Int4,
+        event_type -> Varchar,
+        payload -> Text,
+        attempts -> Int4,
+        next_attempt_at -> Timestamptz,
+        delivered_at -> Nullable<Timestamptz>,
+        failed_at -> Nullable<Timestamptz>,
+        last_status_code -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Int4,
+        url -> Varchar,
+        secret -> Varchar,
+        event_types -> Array<Text>,
+        created_at -> Timestamptz,
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::WorkerKind;
+
+    workers (id) {
+        id -> Uuid,
+        host -> Varchar,
+        registered_at -> Timestamptz,
+        last_heartbeat -> Timestamptz,
+        kind -> WorkerKind,
+        version -> Varchar,
+        current_job -> Nullable<Varchar>,
+        job_started_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::joinable!(files -> blobs (checksum));
+diesel::joinable!(leases -> scheduled_archivals (scheduled_archival_id));
+diesel::joinable!(leases -> workers (worker_id));
+diesel::joinable!(scheduled_archivals -> tracked_collections (tracked_collection_id));
+diesel::joinable!(scheduled_archivals -> videos (video_id));
+diesel::joinable!(unavailable_videos -> videos (video_id));
+diesel::joinable!(videos -> tracked_collections (tracked_collection_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    blobs,
+    events,
     files,
+    leases,
     scheduled_archivals,
     tracked_collections,
+    unavailable_videos,
     videos,
+    webhook_deliveries,
+    webhooks,
+    workers,
 );
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Tells the tasks of a service that it has been asked to stop, cloned into every task
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// a Shutdown requested by sending true
+    pub fn channel() -> (watch::Sender<bool>, Shutdown) {
+        let (sender, requested) = watch::channel(false);
+        (sender, Shutdown { requested })
+    }
+
+    /// a Shutdown requested by SIGTERM or ctrl-c. Has to be called within a tokio runtime
+    pub fn listen() -> Shutdown {
+        let (sender, shutdown) = Shutdown::channel();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            info!("Received shutdown signal");
+            let _ = sender.send(true);
+        });
+        shutdown
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// resolves once the shutdown has been requested
+    pub async fn requested(&mut self) {
+        while !*self.requested.borrow_and_update() {
+            if self.requested.changed().await.is_err() {
+                // the sender is gone without requesting a shutdown, so it never will be
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("could not listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("could not listen for ctrl-c");
+}
+
+/// Waits up to grace_period for the tasks to finish. The remaining ones are cancelled, which kills the yt-dlp processes they are waiting for.
+/// Returns the number of cancelled tasks
+pub async fn join_within<T>(tasks: Vec<JoinHandle<T>>, grace_period: Duration) -> usize {
+    let deadline = tokio::time::Instant::now() + grace_period;
+    let mut cancelled = 0;
+    for mut task in tasks {
+        if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+            task.abort();
+            // the task has been dropped once it returns, along with the processes it started
+            let _ = task.await;
+            cancelled += 1;
+        }
+    }
+    cancelled
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{join_within, Shutdown};
+
+    #[tokio::test]
+    async fn test_running_tasks_are_cancelled_after_the_grace_period() {
+        let (sender, shutdown) = Shutdown::channel();
+        let mut finishing = shutdown.clone();
+        let tasks = vec![
+            tokio::spawn(async move { finishing.requested().await }),
+            tokio::spawn(std::future::pending::<()>()),
+        ];
+
+        sender.send(true).unwrap();
+        assert!(shutdown.is_requested());
+        assert_eq!(join_within(tasks, Duration::from_millis(50)).await, 1);
+    }
+}
@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use diesel::{delete, insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+use super::{Storage, StorageError};
+use crate::database_models::blob::Blob;
+use crate::database_models::file::File;
+use crate::schema::{blobs, files};
+
+#[derive(Debug)]
+pub enum BlobError {
+    Database(diesel::result::Error),
+    Storage(StorageError),
+}
+
+impl std::fmt::Display for BlobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlobError::Database(e) => write!(f, "database error: {}", e),
+            BlobError::Storage(e) => write!(f, "storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+impl From<diesel::result::Error> for BlobError {
+    fn from(e: diesel::result::Error) -> Self {
+        BlobError::Database(e)
+    }
+}
+
+impl From<StorageError> for BlobError {
+    fn from(e: StorageError) -> Self {
+        BlobError::Storage(e)
+    }
+}
+
+impl From<std::io::Error> for BlobError {
+    fn from(e: std::io::Error) -> Self {
+        BlobError::Storage(StorageError::Io(e))
+    }
+}
+
+/// returns the hex encoded sha256 checksum and the size of the file at path
+pub async fn checksum_file(path: &Path) -> Result<(String, i64), std::io::Error> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as i64;
+    }
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+/// returns the hex encoded sha256 checksum of bytes
+pub fn checksum_bytes(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Stores the file at path, keyed by its checksum. If an identical blob exists already, only a reference to it is added.
+/// The file is kept, so it isn't lost if the transaction storing it is rolled back. It is removed by the caller once the transaction has committed
+#[tracing::instrument(skip(db_connection, storage))]
+pub async fn store_file(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    path: &Path,
+) -> Result<Blob, BlobError> {
+    let (checksum, size) = checksum_file(path).await?;
+
+    if let Some(blob) = add_reference(db_connection, &checksum).await? {
+        return Ok(blob);
+    }
+
+    storage.put_file(path, &checksum).await?;
+    Ok(insert_blob(db_connection, &checksum, size).await?)
+}
+
+/// stores bytes in the storage, keyed by their checksum. If an identical blob exists already, only a reference to it is added
+#[tracing::instrument(skip_all)]
+pub async fn store_bytes(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    bytes: &[u8],
+) -> Result<Blob, BlobError> {
+    let checksum = checksum_bytes(bytes);
+
+    if let Some(blob) = add_reference(db_connection, &checksum).await? {
+        return Ok(blob);
+    }
+
+    storage.put_bytes(bytes, &checksum).await?;
+    Ok(insert_blob(db_connection, &checksum, bytes.len() as i64).await?)
+}
+
+/// removes a reference from the blob. Once the last reference is gone, the blob and its object are deleted
+pub async fn release_blob(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    checksum: &str,
+) -> Result<(), BlobError> {
+    db_connection
+        .transaction::<(), BlobError, _>(|db_connection| {
+            async move {
+                let reference_count: i32 = update(blobs::table.find(checksum))
+                    .set(blobs::reference_count.eq(blobs::reference_count - 1))
+                    .returning(blobs::reference_count)
+                    .get_result(db_connection)
+                    .await?;
+
+                if reference_count <= 0 {
+                    // the object is deleted while the row is still locked, so a concurrent store can't add a reference to it in the meantime
+                    storage.delete(checksum).await?;
+                    delete(blobs::table.find(checksum))
+                        .execute(db_connection)
+                        .await?;
+                }
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// deletes the file and releases the blob it references
+pub async fn delete_file(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    file: &File,
+) -> Result<(), BlobError> {
+    delete(files::table.find(file.id))
+        .execute(db_connection)
+        .await?;
+
+    match &file.checksum {
+        Some(checksum) => release_blob(db_connection, storage, checksum).await,
+        None => Ok(storage.delete(&file.storage_key()).await?),
+    }
+}
+
+/// adds a reference to the blob with this checksum, returns None if no such blob exists
+async fn add_reference(
+    db_connection: &mut AsyncPgConnection,
+    checksum: &str,
+) -> Result<Option<Blob>, diesel::result::Error> {
+    update(blobs::table.find(checksum))
+        .set(blobs::reference_count.eq(blobs::reference_count + 1))
+        .get_result::<Blob>(db_connection)
+        .await
+        .optional()
+}
+
+/// inserts a blob with a single reference. If it has been inserted concurrently, a reference is added to the existing one instead
+async fn insert_blob(
+    db_connection: &mut AsyncPgConnection,
+    checksum: &str,
+    size: i64,
+) -> Result<Blob, diesel::result::Error> {
+    insert_into(blobs::table)
+        .values((
+            blobs::checksum.eq(checksum),
+            blobs::size.eq(size),
+            blobs::reference_count.eq(1),
+        ))
+        .on_conflict(blobs::checksum)
+        .do_update()
+        .set(blobs::reference_count.eq(blobs::reference_count + 1))
+        .get_result(db_connection)
+        .await
+}
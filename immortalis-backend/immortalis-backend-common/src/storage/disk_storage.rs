@@ -0,0 +1,62 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use tokio::fs;
+
+use super::{Storage, StorageError, StoredObject};
+
+pub struct DiskStorage {
+    file_storage_location: PathBuf,
+}
+
+impl DiskStorage {
+    pub fn new(file_storage_location: impl Into<PathBuf>) -> DiskStorage {
+        DiskStorage {
+            file_storage_location: file_storage_location.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for DiskStorage {
+    async fn put_file(&self, source: &Path, key: &str) -> Result<(), StorageError> {
+        let target = self.file_storage_location.join(key);
+        // linking fails if temp and file storage are on different mounts or the object exists already, in that case the file is copied
+        if fs::hard_link(source, &target).await.is_err() {
+            fs::copy(source, &target).await?;
+        }
+        Ok(())
+    }
+
+    async fn put_bytes(&self, bytes: &[u8], key: &str) -> Result<(), StorageError> {
+        fs::write(self.file_storage_location.join(key), bytes).await?;
+        Ok(())
+    }
+
+    async fn get_file(&self, key: &str, destination: &Path) -> Result<(), StorageError> {
+        fs::copy(self.file_storage_location.join(key), destination).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match fs::remove_file(self.file_storage_location.join(key)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, StorageError> {
+        let mut objects = Vec::new();
+        let mut entries = fs::read_dir(&self.file_storage_location).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                objects.push(StoredObject {
+                    key: entry.file_name().to_string_lossy().into_owned(),
+                    last_modified: metadata.modified()?.into(),
+                });
+            }
+        }
+        Ok(objects)
+    }
+}
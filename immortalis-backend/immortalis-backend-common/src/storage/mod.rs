@@ -0,0 +1,78 @@
+pub mod content_addressed;
+pub mod disk_storage;
+pub mod reconciliation;
+pub mod s3_storage;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::env_var_config::StorageConfig;
+
+/// Location where archived files are kept. Keys are relative to the root of the storage
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// stores a copy of the file at source, which is kept
+    async fn put_file(&self, source: &Path, key: &str) -> Result<(), StorageError>;
+    async fn put_bytes(&self, bytes: &[u8], key: &str) -> Result<(), StorageError>;
+    /// copies the object to destination
+    async fn get_file(&self, key: &str, destination: &Path) -> Result<(), StorageError>;
+    /// deletes the object. Deleting an object that doesn't exist is not an error
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+    async fn list(&self) -> Result<Vec<StoredObject>, StorageError>;
+}
+
+#[derive(Debug)]
+pub struct StoredObject {
+    pub key: String,
+    pub last_modified: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    S3(s3::error::S3Error),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "io error: {}", e),
+            StorageError::S3(e) => write!(f, "s3 error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+impl From<s3::error::S3Error> for StorageError {
+    fn from(e: s3::error::S3Error) -> Self {
+        StorageError::S3(e)
+    }
+}
+
+/// creates the storage configured by use_s3. s3_url is the endpoint used to store data (usually s3_internal_url)
+pub fn create_storage(
+    use_s3: bool,
+    storage_config: &StorageConfig,
+    s3_url: &str,
+) -> Arc<dyn Storage> {
+    if use_s3 {
+        Arc::new(s3_storage::S3Storage::new(s3_storage::create_bucket(
+            storage_config,
+            s3_url,
+        )))
+    } else {
+        Arc::new(disk_storage::DiskStorage::new(
+            storage_config.file_storage_location.clone(),
+        ))
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::sql_types::{BigInt, Bool};
+use diesel::{
+    delete, insert_into, sql_query, BoolExpressionMethods, ExpressionMethods, OptionalExtension,
+    QueryDsl, QueryableByName,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use tokio::fs;
+use tracing::{info, warn};
+
+use super::content_addressed::{self, BlobError};
+use super::{Storage, StorageError};
+use crate::database_models::file::File;
+use crate::database_models::video::Video;
+use crate::database_models::video_status::VideoStatus;
+use crate::schema::{blobs, files, scheduled_archivals, videos};
+
+/// key of the advisory lock that ensures only one process reconciles at a time
+const RECONCILIATION_LOCK_KEY: i64 = 4_226_581;
+
+#[derive(Debug, Default)]
+pub struct ReconciliationReport {
+    /// files whose object does not exist in the storage
+    pub missing_objects: Vec<File>,
+    /// objects in the storage that no file references
+    pub orphaned_objects: Vec<String>,
+    /// files left behind in temp_file_storage_location
+    pub stale_temp_files: Vec<PathBuf>,
+    /// videos which are BeingArchived without a ScheduledArchival that would finish them
+    pub stuck_videos: Vec<Video>,
+}
+
+#[derive(Debug)]
+pub enum ReconciliationError {
+    Database(diesel::result::Error),
+    Storage(StorageError),
+}
+
+impl std::fmt::Display for ReconciliationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReconciliationError::Database(e) => write!(f, "database error: {}", e),
+            ReconciliationError::Storage(e) => write!(f, "storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReconciliationError {}
+
+impl From<diesel::result::Error> for ReconciliationError {
+    fn from(e: diesel::result::Error) -> Self {
+        ReconciliationError::Database(e)
+    }
+}
+
+impl From<StorageError> for ReconciliationError {
+    fn from(e: StorageError) -> Self {
+        ReconciliationError::Storage(e)
+    }
+}
+
+impl From<std::io::Error> for ReconciliationError {
+    fn from(e: std::io::Error) -> Self {
+        ReconciliationError::Storage(StorageError::Io(e))
+    }
+}
+
+impl From<BlobError> for ReconciliationError {
+    fn from(e: BlobError) -> Self {
+        match e {
+            BlobError::Database(e) => ReconciliationError::Database(e),
+            BlobError::Storage(e) => ReconciliationError::Storage(e),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct AdvisoryLock {
+    #[diesel(sql_type = Bool)]
+    locked: bool,
+}
+
+/// Cross-checks the files table against the storage and temp_file_storage_location.
+/// Anything younger than stale_after is ignored, as it may belong to an archival that is still in progress.
+/// If repair is true, orphaned objects and stale temp files are deleted. Videos with missing objects and stuck videos are deleted along with their files and scheduled again.
+/// Returns None if another process is reconciling at the moment
+pub async fn reconcile(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    temp_file_storage_location: &Path,
+    stale_after: Duration,
+    repair: bool,
+) -> Result<Option<ReconciliationReport>, ReconciliationError> {
+    let lock = sql_query("SELECT pg_try_advisory_lock($1) AS locked")
+        .bind::<BigInt, _>(RECONCILIATION_LOCK_KEY)
+        .get_result::<AdvisoryLock>(db_connection)
+        .await?;
+    if !lock.locked {
+        return Ok(None);
+    }
+
+    let result = reconcile_locked(
+        db_connection,
+        storage,
+        temp_file_storage_location,
+        Utc::now() - stale_after,
+        repair,
+    )
+    .await;
+
+    sql_query("SELECT pg_advisory_unlock($1) AS locked")
+        .bind::<BigInt, _>(RECONCILIATION_LOCK_KEY)
+        .get_result::<AdvisoryLock>(db_connection)
+        .await?;
+
+    result.map(Some)
+}
+
+async fn reconcile_locked(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    temp_file_storage_location: &Path,
+    stale_before: DateTime<Utc>,
+    repair: bool,
+) -> Result<ReconciliationReport, ReconciliationError> {
+    let mut report = ReconciliationReport {
+        stale_temp_files: find_stale_temp_files(temp_file_storage_location, stale_before).await?,
+        ..Default::default()
+    };
+
+    let objects = storage.list().await?;
+    let all_files = files::table.load::<File>(db_connection).await?;
+    let blob_checksums = blobs::table
+        .select(blobs::checksum)
+        .load::<String>(db_connection)
+        .await?;
+    let being_archived = videos::table
+        .filter(videos::status.eq(VideoStatus::BeingArchived))
+        .load::<Video>(db_connection)
+        .await?;
+    let scheduled_urls = scheduled_archivals::table
+        .select(scheduled_archivals::url)
+        .load::<String>(db_connection)
+        .await?
+        .into_iter()
+        .collect::<HashSet<String>>();
+
+    // the video file of an archival in progress is only stored once the download finishes
+    let pending_file_ids = being_archived
+        .iter()
+        .map(|v| v.file_id)
+        .collect::<HashSet<uuid::Uuid>>();
+
+    let stored_keys = objects
+        .iter()
+        .map(|o| o.key.as_str())
+        .collect::<HashSet<&str>>();
+
+    let mut known_keys = all_files
+        .iter()
+        .map(|f| f.storage_key())
+        .collect::<HashSet<String>>();
+    known_keys.extend(blob_checksums);
+
+    report.orphaned_objects = objects
+        .iter()
+        .filter(|o| o.last_modified < stale_before && !known_keys.contains(&o.key))
+        .map(|o| o.key.to_owned())
+        .collect();
+
+    report.missing_objects = all_files
+        .into_iter()
+        .filter(|f| !(f.checksum.is_none() && pending_file_ids.contains(&f.id)))
+        .filter(|f| !stored_keys.contains(f.storage_key().as_str()))
+        .collect();
+
+    report.stuck_videos = being_archived
+        .into_iter()
+        .filter(|v| v.archived_date < stale_before && !scheduled_urls.contains(&v.original_url))
+        .collect();
+
+    for file in &report.missing_objects {
+        warn!(file.id = %file.id, "File {} has no object with key {}", file.id, file.storage_key());
+    }
+    for key in &report.orphaned_objects {
+        warn!("Object {} is not referenced by any file", key);
+    }
+    for path in &report.stale_temp_files {
+        warn!("Temp file {} has been left behind", path.display());
+    }
+    for video in &report.stuck_videos {
+        warn!(
+            video.id = video.id,
+            "Video {} is being archived, but not scheduled", video.original_url
+        );
+    }
+
+    if repair {
+        repair_report(db_connection, storage, &report).await?;
+    }
+
+    info!(
+        "Reconciliation found {} missing objects, {} orphaned objects, {} stale temp files and {} stuck videos",
+        report.missing_objects.len(),
+        report.orphaned_objects.len(),
+        report.stale_temp_files.len(),
+        report.stuck_videos.len()
+    );
+    Ok(report)
+}
+
+async fn repair_report(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    report: &ReconciliationReport,
+) -> Result<(), ReconciliationError> {
+    for path in &report.stale_temp_files {
+        fs::remove_file(path).await?;
+    }
+
+    for key in &report.orphaned_objects {
+        storage.delete(key).await?;
+    }
+
+    let mut discarded_video_ids = HashSet::new();
+    for video in &report.stuck_videos {
+        discard_and_reschedule(db_connection, storage, video).await?;
+        discarded_video_ids.insert(video.id);
+    }
+
+    for file in &report.missing_objects {
+        let affected_videos = videos::table
+            .filter(
+                videos::file_id
+                    .eq(file.id)
+                    .or(videos::thumbnail_id.eq(file.id)),
+            )
+            .load::<Video>(db_connection)
+            .await?;
+
+        if affected_videos.is_empty() {
+            content_addressed::delete_file(db_connection, storage, file).await?;
+        }
+
+        for video in affected_videos {
+            if discarded_video_ids.insert(video.id) {
+                discard_and_reschedule(db_connection, storage, &video).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// deletes the video and its files, then schedules its url again so the archival starts over
+async fn discard_and_reschedule(
+    db_connection: &mut AsyncPgConnection,
+    storage: &dyn Storage,
+    video: &Video,
+) -> Result<(), ReconciliationError> {
+    delete(videos::table.find(video.id))
+        .execute(db_connection)
+        .await?;
+
+    for file_id in [video.file_id, video.thumbnail_id] {
+        // the file may have been deleted already if it is missing its object as well
+        if let Some(file) = files::table
+            .find(file_id)
+            .first::<File>(db_connection)
+            .await
+            .optional()?
+        {
+            content_addressed::delete_file(db_connection, storage, &file).await?;
+        }
+    }
+
+    insert_into(scheduled_archivals::table)
+        .values(scheduled_archivals::url.eq(&video.original_url))
+        .on_conflict_do_nothing()
+        .execute(db_connection)
+        .await?;
+    info!(
+        "Discarded video {} and scheduled it again",
+        video.original_url
+    );
+    Ok(())
+}
+
+async fn find_stale_temp_files(
+    temp_file_storage_location: &Path,
+    stale_before: DateTime<Utc>,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut stale_temp_files = Vec::new();
+    let mut entries = match fs::read_dir(temp_file_storage_location).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stale_temp_files),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() && DateTime::<Utc>::from(metadata.modified()?) < stale_before {
+            stale_temp_files.push(entry.path());
+        }
+    }
+    Ok(stale_temp_files)
+}
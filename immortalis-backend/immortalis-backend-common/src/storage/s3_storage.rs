@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use super::{Storage, StorageError, StoredObject};
+use crate::env_var_config::StorageConfig;
+
+pub struct S3Storage {
+    bucket: s3::Bucket,
+}
+
+impl S3Storage {
+    pub fn new(bucket: s3::Bucket) -> S3Storage {
+        S3Storage { bucket }
+    }
+}
+
+/// creates the bucket described by storage_config. url is either s3_internal_url (storing data) or s3_external_url (presigned links)
+/// If no s3 is configured this will not throw an error as long as the bucket isn't used
+pub fn create_bucket(storage_config: &StorageConfig, url: &str) -> s3::Bucket {
+    s3::Bucket::new(
+        &storage_config.s3_bucket_name,
+        s3::Region::Custom {
+            region: storage_config.s3_region.to_owned(),
+            endpoint: url.to_owned(),
+        },
+        s3::creds::Credentials::new(
+            Some(&storage_config.s3_access_key),
+            Some(&storage_config.s3_secret_key),
+            None,
+            None,
+            None,
+        )
+        .unwrap(),
+    )
+    .unwrap()
+    .with_path_style()
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put_file(&self, source: &Path, key: &str) -> Result<(), StorageError> {
+        self.bucket
+            .put_object_stream(&mut fs::File::open(source).await?, key)
+            .await?;
+        Ok(())
+    }
+
+    async fn put_bytes(&self, bytes: &[u8], key: &str) -> Result<(), StorageError> {
+        self.bucket.put_object(key, bytes).await?;
+        Ok(())
+    }
+
+    async fn get_file(&self, key: &str, destination: &Path) -> Result<(), StorageError> {
+        let mut file = fs::File::create(destination).await?;
+        let status_code = self.bucket.get_object_to_writer(key, &mut file).await?;
+        // writes of tokio files may still be in flight once they return
+        file.flush().await?;
+        if status_code != 200 {
+            fs::remove_file(destination).await?;
+            return Err(StorageError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("s3 responded with {} for object {}", status_code, key),
+            )));
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.bucket.delete_object(key).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, StorageError> {
+        Ok(self
+            .bucket
+            .list(String::new(), None)
+            .await?
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| StoredObject {
+                // an unparsable timestamp is treated as recent, so the object is never considered stale
+                last_modified: DateTime::parse_from_rfc3339(&object.last_modified)
+                    .map(|d| d.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now()),
+                key: object.key,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::env_var_config::TelemetryConfig;
+
+/// the header of the W3C trace context, which is what trace_context stores
+const TRACEPARENT: &str = "traceparent";
+
+/// Sets up JSON logs filtered by log_level, written to writer, and exports spans if an OTLP endpoint is configured.
+/// Has to be called within a tokio runtime
+pub fn init<W>(service_name: &str, config: &TelemetryConfig, writer: W)
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let filter = EnvFilter::try_new(&config.log_level)
+        .unwrap_or_else(|e| panic!("LOG_LEVEL {} is invalid: {}", config.log_level, e));
+    let tracer = config
+        .otel_exporter_otlp_endpoint
+        .as_deref()
+        .filter(|endpoint| !endpoint.is_empty())
+        .map(|endpoint| {
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", service_name.to_string()),
+                ])))
+                // exports from a thread of its own, which works with the runtimes of actix as well
+                .install_batch(opentelemetry::runtime::TokioCurrentThread)
+                .expect("could not set up the OTLP exporter")
+        });
+
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().json().with_writer(writer))
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+}
+
+/// exports the spans which haven't been exported yet, before the process exits
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// the trace context of the current span, to be stored along with work another service picks up. None if spans aren't exported
+pub fn current_trace_context() -> Option<String> {
+    let mut carrier = HashMap::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier.remove(TRACEPARENT)
+}
+
+/// continues the trace the trace context has been stored in, the span becomes a child of the stored one
+pub fn set_parent(span: &Span, trace_context: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), trace_context.to_string())]);
+    let context =
+        opentelemetry::global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    span.set_parent(context);
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::trace::TracerProvider as _;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{current_trace_context, set_parent};
+
+    #[test]
+    fn test_trace_context_is_continued() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = opentelemetry::sdk::trace::TracerProvider::builder()
+            .build()
+            .tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let trace_context = tracing::info_span!("schedule")
+                .in_scope(current_trace_context)
+                .unwrap();
+            let span = tracing::info_span!("archive");
+            set_parent(&span, &trace_context);
+            let continued = span.in_scope(current_trace_context).unwrap();
+
+            // 00-{trace id}-{span id}-{flags}, the trace is the same but the span isn't
+            assert_eq!(continued[..35], trace_context[..35]);
+            assert_ne!(continued, trace_context);
+        });
+    }
+}
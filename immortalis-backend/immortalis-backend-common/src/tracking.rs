@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use diesel::{insert_into, ExpressionMethods, QueryDsl, QueryResult};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use tracing::{error, info, warn};
+
+use crate::database_models::scheduled_archival::TRACKED_PRIORITY;
+use crate::database_models::tracked_collection::TrackedCollection;
+use crate::database_models::video_status::VideoStatus;
+use crate::downloader::{Downloader, DownloaderError};
+use crate::lifecycle_event::{self, LifecycleEvent};
+use crate::schema::{scheduled_archivals, tracked_collections, unavailable_videos, videos};
+use crate::telemetry;
+use crate::utilities::{get_url_type, UrlType};
+
+/// schedules the videos of the collection which haven't been archived or scheduled yet and starts tracking nested collections. Returns false if the collection couldn't be listed.
+/// The archivals of the scheduled videos continue the trace of the check. Archived videos the collection doesn't list anymore are published as unavailable
+#[tracing::instrument(
+    skip_all,
+    fields(
+        tracked_collection.id = tracked_collection.id,
+        tracked_collection.url = tracked_collection.url
+    )
+)]
+pub async fn check_collection(
+    db_connection: &mut AsyncPgConnection,
+    downloader: &dyn Downloader,
+    tracked_collection: &TrackedCollection,
+) -> bool {
+    info!(
+        "Checking collection id: {} url: {}",
+        tracked_collection.id, tracked_collection.url
+    );
+
+    let entries = match downloader.list_collection(&tracked_collection.url).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!(
+                "Failed to list collection {}, encountered error {}",
+                tracked_collection.url, e
+            );
+            return false;
+        }
+    };
+
+    let mut archived_or_scheduled_video_urls = videos::table
+        .select(videos::original_url)
+        .load::<String>(db_connection)
+        .await
+        .unwrap();
+
+    let scheduled_video_urls = scheduled_archivals::table
+        .select(scheduled_archivals::url)
+        .load::<String>(db_connection)
+        .await
+        .unwrap();
+
+    archived_or_scheduled_video_urls.extend(scheduled_video_urls);
+
+    let archived_or_scheduled_video_urls =
+        HashSet::<String>::from_iter(archived_or_scheduled_video_urls);
+
+    let trace_context = telemetry::current_trace_context();
+    let mut new_video_urls = vec![];
+    let mut listed_urls = HashSet::new();
+    for video in entries {
+        let url = video.video.webpage_url.clone().unwrap();
+        listed_urls.insert(url.clone());
+
+        match get_url_type(&url) {
+            UrlType::Collection | UrlType::VideoOrCollection => {
+                insert_into(tracked_collections::table)
+                    .values(tracked_collections::url.eq(&url))
+                    .on_conflict_do_nothing()
+                    .execute(db_connection)
+                    .await
+                    .unwrap();
+                info!("Inserted {} into TrackedCollections", url);
+                continue;
+            }
+            _ => (),
+        }
+
+        if archived_or_scheduled_video_urls.contains(&url) {
+            info!(
+                "{} has already been archived or is scheduled for archival and will not be scheduled again",
+                url
+            )
+        }
+
+        // upcoming streams and premieres are archived once they start
+        let not_before = video.scheduled_start().unwrap_or_else(Utc::now);
+        let inserted = insert_into(scheduled_archivals::table)
+            .values((
+                scheduled_archivals::url.eq(&url),
+                scheduled_archivals::not_before.eq(not_before),
+                scheduled_archivals::priority.eq(TRACKED_PRIORITY),
+                scheduled_archivals::tracked_collection_id.eq(tracked_collection.id),
+                scheduled_archivals::trace_context.eq(&trace_context),
+            ))
+            .on_conflict_do_nothing()
+            .execute(db_connection)
+            .await
+            .unwrap();
+        info!("Scheduled {} for archival at {}", url, not_before);
+        if inserted > 0 && !archived_or_scheduled_video_urls.contains(&url) {
+            new_video_urls.push(url);
+        }
+    }
+
+    if !new_video_urls.is_empty() {
+        let event = LifecycleEvent::CollectionNewVideos {
+            tracked_collection_id: tracked_collection.id,
+            url: tracked_collection.url.clone(),
+            video_urls: new_video_urls,
+        };
+        if let Err(e) = lifecycle_event::publish(db_connection, &event).await {
+            error!(
+                "Failed to publish the new videos of {}, encountered error {}",
+                tracked_collection.url, e
+            );
+        }
+    }
+
+    // a listing without entries is more likely a failed extraction than an emptied collection
+    if listed_urls.is_empty() {
+        return true;
+    }
+    if let Err(e) =
+        publish_unavailable_videos(db_connection, downloader, tracked_collection, &listed_urls)
+            .await
+    {
+        error!(
+            "Failed to check for unavailable videos of {}, encountered error {}",
+            tracked_collection.url, e
+        );
+    }
+    true
+}
+
+/// Publishes video.unavailable once for each archived video of the collection which it doesn't list anymore. The reason is the error of extracting the video if it has been removed,
+/// otherwise that it isn't listed anymore. Videos which couldn't be extracted for another reason are checked again with the next check
+async fn publish_unavailable_videos(
+    db_connection: &mut AsyncPgConnection,
+    downloader: &dyn Downloader,
+    tracked_collection: &TrackedCollection,
+    listed_urls: &HashSet<String>,
+) -> QueryResult<()> {
+    let archived_videos = videos::table
+        .left_join(unavailable_videos::table)
+        .filter(unavailable_videos::video_id.is_null())
+        .filter(videos::tracked_collection_id.eq(tracked_collection.id))
+        .filter(videos::status.eq(VideoStatus::Archived))
+        .select((videos::id, videos::original_url))
+        .load::<(i32, String)>(db_connection)
+        .await?;
+
+    for (video_id, url) in archived_videos {
+        if listed_urls.contains(&url) {
+            continue;
+        }
+        let reason = match downloader.fetch_metadata(&url).await {
+            Ok(_) => format!("no longer listed in {}", tracked_collection.url),
+            Err(DownloaderError::Unavailable(reason)) => reason,
+            Err(e) => {
+                warn!(
+                    "Could not check whether {} is still available, encountered error {}",
+                    url, e
+                );
+                continue;
+            }
+        };
+
+        let event = LifecycleEvent::VideoUnavailable {
+            video_id: Some(video_id),
+            url: url.clone(),
+            tracked_collection_id: Some(tracked_collection.id),
+            reason: reason.clone(),
+        };
+        let (event, stored_reason) = (&event, &reason);
+        db_connection
+            .transaction::<(), diesel::result::Error, _>(|db_connection| {
+                async move {
+                    insert_into(unavailable_videos::table)
+                        .values((
+                            unavailable_videos::video_id.eq(video_id),
+                            unavailable_videos::reason.eq(stored_reason),
+                        ))
+                        .execute(db_connection)
+                        .await?;
+                    lifecycle_event::publish(db_connection, event).await
+                }
+                .scope_boxed()
+            })
+            .await?;
+        info!("{} is unavailable: {}", url, reason);
+    }
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use std::ops::Deref;
+
 #[derive(PartialEq, Eq, Debug)]
 pub enum UrlType {
     Invalid,
@@ -37,6 +39,39 @@ pub fn get_url_type(url: &str) -> UrlType {
     }
 }
 
+/// Receives an URL as &str and returns it with only query parameters with names contained in accepted_parameter_names
+/// ```rust
+/// use immortalis_backend_common::utilities::filter_query_pairs;
+/// assert_eq!(filter_query_pairs("https://example.net/?lang=en&foo=bar", vec!["lang"]).unwrap(), "https://example.net/?lang=en")
+/// ```
+pub fn filter_query_pairs(
+    initial_url: &str,
+    accepted_parameter_names: Vec<&str>,
+) -> Result<String, url::ParseError> {
+    // trim query params other than 'v' which is the video (trims for example playlists)
+    let url = url::Url::parse(initial_url)?;
+    let view_query_param = url
+        .query_pairs()
+        .filter(|x| accepted_parameter_names.contains(&x.0.deref()));
+    let mut new_url = url.clone();
+    new_url
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(view_query_param);
+    Ok(new_url.to_string())
+}
+
+/// returns the url the video is archived under, if url refers to a video
+pub fn canonicalize(url: &str) -> Option<String> {
+    match get_url_type(url) {
+        UrlType::Video | UrlType::VideoOrCollection => {
+            // v is youtubes query param for the video, so its the only thing that we want to keep here
+            filter_query_pairs(url, vec!["v"]).ok()
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utilities::UrlType;
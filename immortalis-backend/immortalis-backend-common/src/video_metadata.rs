@@ -0,0 +1,44 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::database_models::live_status::LiveStatus;
+
+/// metadata of a single video as printed by yt-dlp -J
+#[derive(Debug, Clone)]
+pub struct VideoMetadata {
+    pub video: youtube_dl::SingleVideo,
+    pub live_status: Option<LiveStatus>,
+    /// start of a scheduled stream or premiere
+    pub release_timestamp: Option<DateTime<Utc>>,
+}
+
+/// fields which youtube_dl::SingleVideo doesn't contain
+#[derive(Deserialize)]
+struct LiveInfo {
+    live_status: Option<String>,
+    release_timestamp: Option<i64>,
+}
+
+impl VideoMetadata {
+    pub fn from_value(value: serde_json::Value) -> Result<VideoMetadata, serde_json::Error> {
+        let live_info = LiveInfo::deserialize(&value)?;
+        Ok(VideoMetadata {
+            live_status: live_info
+                .live_status
+                .as_deref()
+                .and_then(LiveStatus::from_yt_dlp),
+            release_timestamp: live_info
+                .release_timestamp
+                .and_then(|t| Utc.timestamp_opt(t, 0).single()),
+            video: serde_json::from_value(value)?,
+        })
+    }
+
+    /// returns the start of the stream or premiere, if it hasn't started yet
+    pub fn scheduled_start(&self) -> Option<DateTime<Utc>> {
+        match self.live_status {
+            Some(LiveStatus::IsUpcoming) => self.release_timestamp,
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use chrono::{Duration, Utc};
+use diesel::{insert_into, update, ExpressionMethods, QueryDsl};
+use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+use immortalis_backend_common::database_models::archival_stage::ArchivalStage;
+use immortalis_backend_common::database_models::event::{
+    latest_event_id, load_events_after, oldest_event_id, prune_events,
+};
+use immortalis_backend_common::database_models::worker;
+use immortalis_backend_common::database_models::worker_kind::WorkerKind;
+use immortalis_backend_common::schema::scheduled_archivals;
+use immortalis_backend_test_support::database::TestDatabase;
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_events_are_stored() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let id: i32 = insert_into(scheduled_archivals::table)
+        .values(scheduled_archivals::url.eq("https://www.youtube.com/watch?v=stored"))
+        .returning(scheduled_archivals::id)
+        .get_result(db_connection)
+        .await
+        .unwrap();
+    update(scheduled_archivals::table.find(id))
+        .set(scheduled_archivals::stage.eq(ArchivalStage::Registered))
+        .execute(db_connection)
+        .await
+        .unwrap();
+
+    let events = load_events_after(db_connection, 0, &["scheduled_archivals"], 10)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(events[0].id < events[1].id);
+    let insert_payload: serde_json::Value = serde_json::from_str(&events[0].payload).unwrap();
+    assert_eq!(insert_payload["action"], "insert");
+    let update_payload: serde_json::Value = serde_json::from_str(&events[1].payload).unwrap();
+    assert_eq!(update_payload["action"], "update");
+    assert_eq!(update_payload["record"]["stage"], "registered");
+    assert_eq!(update_payload["old_record"]["stage"], "pending");
+
+    assert!(
+        load_events_after(db_connection, events[1].id, &["scheduled_archivals"], 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(load_events_after(db_connection, 0, &["workers"], 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(
+        prune_events(db_connection, Utc::now() - Duration::hours(1))
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        prune_events(db_connection, Utc::now() + Duration::hours(1))
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(oldest_event_id(db_connection).await.unwrap(), None);
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_event_ids_follow_the_commit_order() {
+    let test_database = TestDatabase::create();
+    let committed_last = &mut test_database.connect().await;
+    let committed_first = &mut test_database.connect().await;
+
+    committed_last.batch_execute("BEGIN").await.unwrap();
+    insert_into(scheduled_archivals::table)
+        .values(scheduled_archivals::url.eq("https://www.youtube.com/watch?v=last"))
+        .execute(committed_last)
+        .await
+        .unwrap();
+    insert_into(scheduled_archivals::table)
+        .values(scheduled_archivals::url.eq("https://www.youtube.com/watch?v=first"))
+        .execute(committed_first)
+        .await
+        .unwrap();
+    let first_id = latest_event_id(committed_first).await.unwrap().unwrap();
+    committed_last.batch_execute("COMMIT").await.unwrap();
+
+    // stored before the other event, but a client resuming after first_id still receives it
+    let events = load_events_after(committed_first, first_id, &["scheduled_archivals"], 10)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    let payload: serde_json::Value = serde_json::from_str(&events[0].payload).unwrap();
+    assert_eq!(
+        payload["record"]["url"],
+        "https://www.youtube.com/watch?v=last"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_heartbeats_publish_no_events() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let worker_id = uuid::Uuid::new_v4();
+    for _ in 0..2 {
+        worker::heartbeat(
+            db_connection,
+            &[worker_id],
+            "host",
+            WorkerKind::Archiver,
+            "test",
+        )
+        .await
+        .unwrap();
+    }
+    worker::set_current_job(
+        db_connection,
+        worker_id,
+        Some("https://www.youtube.com/watch?v=job"),
+    )
+    .await
+    .unwrap();
+
+    let events = load_events_after(db_connection, 0, &["workers"], 10)
+        .await
+        .unwrap();
+    let actions: Vec<String> = events
+        .iter()
+        .map(|event| {
+            let payload: serde_json::Value = serde_json::from_str(&event.payload).unwrap();
+            payload["action"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(actions, vec!["insert", "update"]);
+}
@@ -0,0 +1,164 @@
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+use immortalis_backend_common::database_models::video_status::VideoStatus;
+use immortalis_backend_common::import::import_directory;
+use immortalis_backend_common::schema::{files, videos};
+use immortalis_backend_test_support::database::TestDatabase;
+use immortalis_backend_test_support::fixture_directory;
+use immortalis_backend_test_support::memory_storage::InMemoryStorage;
+use tempfile::TempDir;
+
+const VIDEO_URL: &str = "https://www.youtube.com/watch?v=fixtureVid1";
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_import_directory() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let storage = InMemoryStorage::new();
+    let temp_file_storage = TempDir::new().unwrap();
+
+    // laid out like yt-dlp -o "%(channel)s/%(title)s [%(id)s].%(ext)s" --write-info-json --write-thumbnail
+    let directory = TempDir::new().unwrap();
+    let channel_directory = directory.path().join("Immortalis Fixtures");
+    tokio::fs::create_dir(&channel_directory).await.unwrap();
+    tokio::fs::copy(
+        fixture_directory().join("video.json"),
+        channel_directory.join("Fixture [fixtureVid1].info.json"),
+    )
+    .await
+    .unwrap();
+    tokio::fs::write(
+        channel_directory.join("Fixture [fixtureVid1].webm"),
+        b"video",
+    )
+    .await
+    .unwrap();
+    tokio::fs::write(
+        channel_directory.join("Fixture [fixtureVid1].jpg"),
+        b"thumbnail",
+    )
+    .await
+    .unwrap();
+
+    let summary = import_directory(
+        db_connection,
+        &storage,
+        None,
+        directory.path(),
+        temp_file_storage.path(),
+        true,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        (summary.imported, summary.skipped, summary.failed),
+        (1, 0, 0)
+    );
+    // copied, so the directory is left untouched
+    assert!(channel_directory
+        .join("Fixture [fixtureVid1].webm")
+        .exists());
+
+    let (status, file_id): (VideoStatus, uuid::Uuid) = videos::table
+        .filter(videos::original_url.eq(VIDEO_URL))
+        .select((videos::status, videos::file_id))
+        .first(db_connection)
+        .await
+        .unwrap();
+    assert_eq!(status, VideoStatus::Archived);
+    let extension: String = files::table
+        .find(file_id)
+        .select(files::file_extension)
+        .first(db_connection)
+        .await
+        .unwrap();
+    assert_eq!(extension, "webm");
+
+    // the video is archived already, so it is skipped and the files are left where they are
+    let summary = import_directory(
+        db_connection,
+        &storage,
+        None,
+        directory.path(),
+        temp_file_storage.path(),
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        (summary.imported, summary.skipped, summary.failed),
+        (0, 1, 0)
+    );
+    assert!(channel_directory
+        .join("Fixture [fixtureVid1].webm")
+        .exists());
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_failed_import_keeps_the_moved_files() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let storage = InMemoryStorage::new();
+    let temp_file_storage = TempDir::new().unwrap();
+
+    let directory = TempDir::new().unwrap();
+    let media = directory.path().join("Fixture [fixtureVid1].webm");
+    let thumbnail = directory.path().join("Fixture [fixtureVid1].jpg");
+    tokio::fs::copy(
+        fixture_directory().join("video.json"),
+        directory.path().join("Fixture [fixtureVid1].info.json"),
+    )
+    .await
+    .unwrap();
+    tokio::fs::write(&media, b"video").await.unwrap();
+    tokio::fs::write(&thumbnail, b"thumbnail").await.unwrap();
+
+    // the video is stored but can't be registered, which rolls back the files and blobs as well
+    db_connection
+        .batch_execute(
+            "CREATE FUNCTION reject_video() RETURNS trigger AS $$ BEGIN RAISE EXCEPTION 'rejected'; END $$ LANGUAGE plpgsql;
+            CREATE TRIGGER reject_video BEFORE INSERT ON videos FOR EACH ROW EXECUTE PROCEDURE reject_video();",
+        )
+        .await
+        .unwrap();
+    let summary = import_directory(
+        db_connection,
+        &storage,
+        None,
+        directory.path(),
+        temp_file_storage.path(),
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        (summary.imported, summary.skipped, summary.failed),
+        (0, 0, 1)
+    );
+    assert_eq!(tokio::fs::read(&media).await.unwrap(), b"video");
+    assert_eq!(tokio::fs::read(&thumbnail).await.unwrap(), b"thumbnail");
+
+    // once the video could be registered, the files are gone
+    db_connection
+        .batch_execute("DROP TRIGGER reject_video ON videos;")
+        .await
+        .unwrap();
+    let summary = import_directory(
+        db_connection,
+        &storage,
+        None,
+        directory.path(),
+        temp_file_storage.path(),
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        (summary.imported, summary.skipped, summary.failed),
+        (1, 0, 0)
+    );
+    assert!(!media.exists());
+    assert!(!thumbnail.exists());
+}
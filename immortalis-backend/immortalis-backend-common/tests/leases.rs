@@ -0,0 +1,201 @@
+use chrono::{Duration, Utc};
+use diesel::{insert_into, update, ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::lease::{self, LeaseLimits};
+use immortalis_backend_common::database_models::scheduled_archival::MANUAL_PRIORITY;
+use immortalis_backend_common::database_models::worker;
+use immortalis_backend_common::database_models::worker_kind::WorkerKind;
+use immortalis_backend_common::schema::{leases, scheduled_archivals, workers};
+use immortalis_backend_test_support::database::TestDatabase;
+
+const UNLIMITED: LeaseLimits = LeaseLimits {
+    max_concurrent_archivals: 0,
+    max_concurrent_archivals_per_domain: 0,
+};
+
+async fn schedule(db_connection: &mut AsyncPgConnection, url: &str) -> i32 {
+    insert_into(scheduled_archivals::table)
+        .values((
+            scheduled_archivals::url.eq(url),
+            scheduled_archivals::not_before.eq(Utc::now() - Duration::minutes(1)),
+        ))
+        .returning(scheduled_archivals::id)
+        .get_result(db_connection)
+        .await
+        .unwrap()
+}
+
+async fn register_worker(db_connection: &mut AsyncPgConnection) -> uuid::Uuid {
+    let worker_id = uuid::Uuid::new_v4();
+    worker::heartbeat(
+        db_connection,
+        &[worker_id],
+        "test",
+        WorkerKind::Archiver,
+        "test",
+    )
+    .await
+    .unwrap();
+    worker_id
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_dequeue_respects_global_limit() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let worker_id = register_worker(db_connection).await;
+    schedule(db_connection, "https://www.youtube.com/watch?v=first").await;
+    schedule(db_connection, "https://www.youtube.com/watch?v=second").await;
+
+    let limits = LeaseLimits {
+        max_concurrent_archivals: 1,
+        ..UNLIMITED
+    };
+    let first = lease::dequeue(db_connection, worker_id, limits, 600)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(lease::dequeue(db_connection, worker_id, limits, 600)
+        .await
+        .unwrap()
+        .is_none());
+
+    // releasing the lease frees the slot
+    lease::release(db_connection, worker_id, first.id)
+        .await
+        .unwrap();
+    assert!(lease::dequeue(db_connection, worker_id, limits, 600)
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_dequeue_respects_domain_limit() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let worker_id = register_worker(db_connection).await;
+    schedule(db_connection, "https://www.youtube.com/watch?v=first").await;
+    schedule(db_connection, "https://youtube.com/watch?v=second").await;
+    let other_domain = schedule(db_connection, "https://example.com/video").await;
+
+    let limits = LeaseLimits {
+        max_concurrent_archivals_per_domain: 1,
+        ..UNLIMITED
+    };
+    let mut dequeued = Vec::new();
+    while let Some(entry) = lease::dequeue(db_connection, worker_id, limits, 600)
+        .await
+        .unwrap()
+    {
+        dequeued.push(entry);
+    }
+
+    // www. is ignored, so both youtube urls share a slot
+    assert_eq!(dequeued.len(), 2);
+    assert!(dequeued.iter().any(|entry| entry.id == other_domain));
+    assert_eq!(
+        dequeued
+            .iter()
+            .filter(|entry| entry.domain.as_deref() == Some("youtube.com"))
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_dequeue_orders_by_priority_then_age() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let worker_id = register_worker(db_connection).await;
+    let oldest = schedule(db_connection, "https://www.youtube.com/watch?v=first").await;
+    let newest = schedule(db_connection, "https://www.youtube.com/watch?v=second").await;
+    let urgent = schedule(db_connection, "https://www.youtube.com/watch?v=third").await;
+    update(scheduled_archivals::table)
+        .set(scheduled_archivals::priority.eq(MANUAL_PRIORITY))
+        .filter(scheduled_archivals::id.eq(urgent))
+        .execute(db_connection)
+        .await
+        .unwrap();
+
+    let mut dequeued = Vec::new();
+    while let Some(entry) = lease::dequeue(db_connection, worker_id, UNLIMITED, 600)
+        .await
+        .unwrap()
+    {
+        dequeued.push(entry.id);
+    }
+    assert_eq!(dequeued, vec![urgent, oldest, newest]);
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_dead_workers_are_removed_and_their_archivals_requeued() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let dead_worker = register_worker(db_connection).await;
+    let live_worker = register_worker(db_connection).await;
+    let scheduled_archival_id =
+        schedule(db_connection, "https://www.youtube.com/watch?v=first").await;
+
+    lease::dequeue(db_connection, dead_worker, UNLIMITED, 600)
+        .await
+        .unwrap()
+        .unwrap();
+    update(workers::table)
+        .set(workers::last_heartbeat.eq(Utc::now() - Duration::minutes(5)))
+        .filter(workers::id.eq(dead_worker))
+        .execute(db_connection)
+        .await
+        .unwrap();
+
+    let removed = worker::remove_dead_workers(db_connection, Duration::minutes(1))
+        .await
+        .unwrap();
+    assert_eq!(removed, 1);
+
+    let lease_count: i64 = leases::table
+        .count()
+        .get_result(db_connection)
+        .await
+        .unwrap();
+    assert_eq!(lease_count, 0);
+
+    // the archival is available right away instead of after the processing timeout
+    let requeued = lease::dequeue(db_connection, live_worker, UNLIMITED, 600)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(requeued.id, scheduled_archival_id);
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_deregistered_workers_hand_off_their_archivals() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+    let stopping_worker = register_worker(db_connection).await;
+    let other_worker = register_worker(db_connection).await;
+    let scheduled_archival_id =
+        schedule(db_connection, "https://www.youtube.com/watch?v=first").await;
+
+    lease::dequeue(db_connection, stopping_worker, UNLIMITED, 600)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        worker::deregister(db_connection, &[stopping_worker])
+            .await
+            .unwrap(),
+        1
+    );
+
+    let requeued = lease::dequeue(db_connection, other_worker, UNLIMITED, 600)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(requeued.id, scheduled_archival_id);
+}
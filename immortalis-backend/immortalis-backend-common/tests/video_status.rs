@@ -0,0 +1,129 @@
+use diesel::QueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::video_status::{
+    transition, TransitionError, VideoStatus,
+};
+use immortalis_backend_common::schema::videos;
+use immortalis_backend_test_support::database::{insert_video, TestDatabase};
+
+async fn status_of(db_connection: &mut AsyncPgConnection, video_id: i32) -> VideoStatus {
+    videos::table
+        .find(video_id)
+        .select(videos::status)
+        .first(db_connection)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_transition_only_updates_its_own_video() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+
+    let first = insert_video(db_connection, "first", VideoStatus::BeingArchived).await;
+    let second = insert_video(db_connection, "second", VideoStatus::BeingArchived).await;
+
+    let from = transition(db_connection, first, VideoStatus::Archived)
+        .await
+        .unwrap();
+
+    assert_eq!(from, VideoStatus::BeingArchived);
+    assert_eq!(status_of(db_connection, first).await, VideoStatus::Archived);
+    assert_eq!(
+        status_of(db_connection, second).await,
+        VideoStatus::BeingArchived
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_invalid_transition_is_rejected() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+
+    let archived = insert_video(db_connection, "archived", VideoStatus::Archived).await;
+
+    assert!(matches!(
+        transition(db_connection, archived, VideoStatus::BeingArchived).await,
+        Err(TransitionError::Invalid { .. })
+    ));
+    assert_eq!(
+        status_of(db_connection, archived).await,
+        VideoStatus::Archived
+    );
+
+    assert!(matches!(
+        transition(db_connection, archived + 1, VideoStatus::Archived).await,
+        Err(TransitionError::NotFound { .. })
+    ));
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_concurrent_workers_only_update_their_own_videos() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+
+    let mut video_ids = Vec::new();
+    for i in 0..10 {
+        video_ids
+            .push(insert_video(db_connection, &i.to_string(), VideoStatus::BeingArchived).await);
+    }
+
+    // every worker uses its own connection, like the archiver tasks do. Even workers archive their video, odd workers fail it
+    let mut workers = Vec::new();
+    for (i, video_id) in video_ids.iter().copied().enumerate() {
+        let mut worker_connection = test_database.connect().await;
+        workers.push(tokio::spawn(async move {
+            let to = if i % 2 == 0 {
+                VideoStatus::Archived
+            } else {
+                VideoStatus::ArchivationFailed
+            };
+            transition(&mut worker_connection, video_id, to)
+                .await
+                .unwrap();
+        }));
+    }
+    for worker in workers {
+        worker.await.unwrap();
+    }
+
+    for (i, video_id) in video_ids.iter().copied().enumerate() {
+        let expected = if i % 2 == 0 {
+            VideoStatus::Archived
+        } else {
+            VideoStatus::ArchivationFailed
+        };
+        assert_eq!(status_of(db_connection, video_id).await, expected);
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_concurrent_transitions_of_the_same_video() {
+    let test_database = TestDatabase::create();
+    let db_connection = &mut test_database.connect().await;
+
+    let video_id = insert_video(db_connection, "contested", VideoStatus::BeingArchived).await;
+
+    // both transitions start from BeingArchived, so only the first one to lock the row may succeed
+    let mut workers = Vec::new();
+    for to in [VideoStatus::Archived, VideoStatus::ArchivationFailed] {
+        let mut worker_connection = test_database.connect().await;
+        workers.push(tokio::spawn(async move {
+            transition(&mut worker_connection, video_id, to)
+                .await
+                .is_ok()
+        }));
+    }
+
+    let mut succeeded = 0;
+    for worker in workers {
+        if worker.await.unwrap() {
+            succeeded += 1;
+        }
+    }
+    assert_eq!(succeeded, 1);
+}
@@ -0,0 +1,115 @@
+use chrono::Utc;
+use diesel::{insert_into, sql_query, Connection, PgConnection};
+use diesel_async::pooled_connection::deadpool::Pool;
+use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use diesel_migrations::MigrationHarness;
+use immortalis_backend_common::database_models::file::File;
+use immortalis_backend_common::database_models::video::InsertableVideo;
+use immortalis_backend_common::database_models::video_status::VideoStatus;
+use immortalis_backend_common::migrations::MIGRATIONS;
+use immortalis_backend_common::schema::{files, videos};
+
+/// A throwaway schema in the database at TEST_DATABASE_URL with all migrations applied. The schema is dropped once this is dropped
+pub struct TestDatabase {
+    /// connects to the database with the search_path set to the schema
+    pub database_url: String,
+    base_url: String,
+    schema: String,
+}
+
+impl TestDatabase {
+    /// Panics if TEST_DATABASE_URL isn't set. Tests requiring a database are ignored, so they are only run with --include-ignored
+    pub fn create() -> TestDatabase {
+        let base_url = std::env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL has to be set for tests requiring a database");
+        let schema = format!("test_{}", uuid::Uuid::new_v4().simple());
+
+        let mut connection = PgConnection::establish(&base_url).unwrap();
+        diesel::RunQueryDsl::execute(
+            sql_query(format!("CREATE SCHEMA {}", schema)),
+            &mut connection,
+        )
+        .unwrap();
+
+        let separator = if base_url.contains('?') { '&' } else { '?' };
+        let database_url = format!(
+            "{}{}options=-csearch_path%3D{}",
+            base_url, separator, schema
+        );
+        PgConnection::establish(&database_url)
+            .unwrap()
+            .run_pending_migrations(MIGRATIONS)
+            .unwrap();
+
+        TestDatabase {
+            database_url,
+            base_url,
+            schema,
+        }
+    }
+
+    pub async fn connect(&self) -> AsyncPgConnection {
+        <AsyncPgConnection as diesel_async::AsyncConnection>::establish(&self.database_url)
+            .await
+            .unwrap()
+    }
+
+    pub fn pool(&self) -> Pool<AsyncPgConnection> {
+        let config = AsyncDieselConnectionManager::<AsyncPgConnection>::new(&self.database_url);
+        Pool::builder(config).build().unwrap()
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let mut connection = PgConnection::establish(&self.base_url).unwrap();
+        diesel::RunQueryDsl::execute(
+            sql_query(format!("DROP SCHEMA {} CASCADE", self.schema)),
+            &mut connection,
+        )
+        .unwrap();
+    }
+}
+
+/// inserts a video with the given status along with a file that is used for the video and its thumbnail
+pub async fn insert_video(
+    db_connection: &mut AsyncPgConnection,
+    url: &str,
+    status: VideoStatus,
+) -> i32 {
+    let file_id = uuid::Uuid::new_v4();
+    insert_into(files::table)
+        .values(File {
+            id: file_id,
+            file_name: url.to_string(),
+            file_extension: "mkv".to_string(),
+            size: 0,
+            checksum: None,
+        })
+        .execute(db_connection)
+        .await
+        .unwrap();
+
+    insert_into(videos::table)
+        .values(InsertableVideo {
+            title: url.to_string(),
+            channel: "channel".to_string(),
+            views: 0,
+            upload_date: Utc::now(),
+            archived_date: Utc::now(),
+            duration: 0,
+            original_url: url.to_string(),
+            status,
+            file_id,
+            thumbnail_id: file_id,
+            release_timestamp: None,
+            live_status: None,
+            is_live_recording: false,
+            tracked_collection_id: None,
+        })
+        .returning(videos::id)
+        .get_result(db_connection)
+        .await
+        .unwrap()
+}
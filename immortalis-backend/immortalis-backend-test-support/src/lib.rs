@@ -0,0 +1,73 @@
+pub mod database;
+pub mod memory_storage;
+
+use std::path::PathBuf;
+
+use immortalis_backend_common::downloader::fake::FakeDownloader;
+use immortalis_backend_common::env_var_config::{
+    EnvVarConfigApi, EnvVarConfigArchiver, EnvVarConfigTracker,
+};
+use tempfile::TempDir;
+
+/// the directory containing the yt-dlp fixtures
+pub fn fixture_directory() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+/// a downloader serving every fixture in fixture_directory
+pub fn fixture_downloader() -> FakeDownloader {
+    FakeDownloader::from_fixture_directory(&fixture_directory()).unwrap()
+}
+
+/// the path of the directory as it is passed in a variable, like FILE_STORAGE_LOCATION
+pub fn directory_path(directory: &TempDir) -> String {
+    directory.path().display().to_string()
+}
+
+pub fn archiver_config(
+    database_url: &str,
+    file_storage_location: &str,
+    temp_file_storage_location: &str,
+) -> EnvVarConfigArchiver {
+    envy::from_iter(
+        [
+            ("DATABASE_URL", database_url),
+            ("FILE_STORAGE_LOCATION", file_storage_location),
+            ("TEMP_FILE_STORAGE_LOCATION", temp_file_storage_location),
+            ("SIMULATE_DOWNLOAD", "false"),
+            ("SIMULATED_DOWNLOAD_DURATION_SECONDS", "0"),
+            ("ARCHIVER_THREAD_COUNT", "1"),
+            ("ARCHIVER_ARCHIVING_TIMEOUT_SECONDS", "600"),
+            ("ARCHIVER_ERROR_BACKOFF_SECONDS", "600"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string())),
+    )
+    .unwrap()
+}
+
+pub fn api_config(
+    database_url: &str,
+    file_storage_location: &str,
+    temp_file_storage_location: &str,
+) -> EnvVarConfigApi {
+    envy::from_iter(
+        [
+            ("DATABASE_URL", database_url),
+            ("FILE_STORAGE_LOCATION", file_storage_location),
+            ("TEMP_FILE_STORAGE_LOCATION", temp_file_storage_location),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string())),
+    )
+    .unwrap()
+}
+
+pub fn tracker_config(database_url: &str) -> EnvVarConfigTracker {
+    envy::from_iter(
+        [
+            ("DATABASE_URL", database_url),
+            ("TRACKER_THREAD_COUNT", "1"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string())),
+    )
+    .unwrap()
+}
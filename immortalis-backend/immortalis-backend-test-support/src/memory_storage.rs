@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use immortalis_backend_common::storage::{Storage, StorageError, StoredObject};
+
+/// Storage which keeps objects in memory, a stand-in for s3
+#[derive(Default)]
+pub struct InMemoryStorage {
+    objects: Mutex<HashMap<String, (Vec<u8>, DateTime<Utc>)>>,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> InMemoryStorage {
+        InMemoryStorage::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|(content, _)| content.clone())
+    }
+}
+
+#[async_trait]
+impl Storage for InMemoryStorage {
+    async fn put_file(&self, source: &Path, key: &str) -> Result<(), StorageError> {
+        let content = tokio::fs::read(source).await?;
+        self.put_bytes(&content, key).await
+    }
+
+    async fn put_bytes(&self, bytes: &[u8], key: &str) -> Result<(), StorageError> {
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (bytes.to_vec(), Utc::now()));
+        Ok(())
+    }
+
+    async fn get_file(&self, key: &str, destination: &Path) -> Result<(), StorageError> {
+        let content = self.get(key).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, format!("no object {}", key))
+        })?;
+        tokio::fs::write(destination, content).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, StorageError> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, (_, last_modified))| StoredObject {
+                key: key.clone(),
+                last_modified: *last_modified,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,425 @@
+use std::sync::Arc;
+
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App};
+use diesel::QueryDsl;
+use diesel_async::RunQueryDsl;
+use immortalis_backend_api::event_hub::EventHub;
+use immortalis_backend_api::metrics::record_request;
+use immortalis_backend_api::{configure, AppState};
+use immortalis_backend_archiver::archive;
+use immortalis_backend_common::data_transfer_models::video_dto::VideoDto;
+use immortalis_backend_common::database_models::event::load_events_after;
+use immortalis_backend_common::database_models::tracked_collection::TrackedCollection;
+use immortalis_backend_common::database_models::video_status::VideoStatus;
+use immortalis_backend_common::database_models::worker;
+use immortalis_backend_common::database_models::worker_kind::WorkerKind;
+use immortalis_backend_common::downloader::Downloader;
+use immortalis_backend_common::lifecycle_event::LIFECYCLE_CHANNEL;
+use immortalis_backend_common::schema::tracked_collections;
+use immortalis_backend_common::storage::disk_storage::DiskStorage;
+use immortalis_backend_common::storage::s3_storage::create_bucket;
+use immortalis_backend_common::storage::Storage;
+use immortalis_backend_common::tracking::check_collection;
+use immortalis_backend_test_support::database::TestDatabase;
+use immortalis_backend_test_support::{
+    api_config, archiver_config, directory_path, fixture_directory, fixture_downloader,
+    tracker_config,
+};
+use immortalis_backend_tracker::track;
+use serde_json::json;
+use tempfile::TempDir;
+
+const VIDEO_URL: &str = "https://www.youtube.com/watch?v=fixtureVid1";
+const PLAYLIST_URL: &str = "https://www.youtube.com/playlist?list=PLfixturePlaylist";
+const REMOVED_VIDEO_URL: &str = "https://www.youtube.com/watch?v=fixtureVid2";
+
+/// the api, archiver and tracker sharing a test database and a storage on disk
+struct TestEnvironment {
+    test_database: TestDatabase,
+    app_state: web::Data<AppState>,
+    archiver_config: Arc<immortalis_backend_common::env_var_config::EnvVarConfigArchiver>,
+    tracker_config: Arc<immortalis_backend_common::env_var_config::EnvVarConfigTracker>,
+    storage: Arc<dyn Storage>,
+    downloader: Arc<dyn Downloader>,
+    worker_id: uuid::Uuid,
+    _file_storage: TempDir,
+    _temp_file_storage: TempDir,
+}
+
+impl TestEnvironment {
+    async fn create() -> TestEnvironment {
+        let test_database = TestDatabase::create();
+        let file_storage = TempDir::new().unwrap();
+        let temp_file_storage = TempDir::new().unwrap();
+        let file_storage_location = directory_path(&file_storage);
+        let temp_file_storage_location = directory_path(&temp_file_storage);
+
+        let api_config = Arc::new(api_config(
+            &test_database.database_url,
+            &file_storage_location,
+            &temp_file_storage_location,
+        ));
+        let app_state = web::Data::new(AppState {
+            db_connection_pool: test_database.pool(),
+            file_storage_location: file_storage_location.clone().into(),
+            event_hub: EventHub::new(api_config.websocket_queue_size),
+            bucket: Arc::new(create_bucket(&api_config.storage_config, "")),
+            env_var_config: api_config,
+        });
+
+        let worker_id = uuid::Uuid::new_v4();
+        worker::heartbeat(
+            &mut test_database.connect().await,
+            &[worker_id],
+            "test",
+            WorkerKind::Archiver,
+            "test",
+        )
+        .await
+        .unwrap();
+
+        TestEnvironment {
+            archiver_config: Arc::new(archiver_config(
+                &test_database.database_url,
+                &file_storage_location,
+                &temp_file_storage_location,
+            )),
+            tracker_config: Arc::new(tracker_config(&test_database.database_url)),
+            storage: Arc::new(DiskStorage::new(file_storage_location)),
+            downloader: Arc::new(fixture_downloader()),
+            worker_id,
+            test_database,
+            app_state,
+            _file_storage: file_storage,
+            _temp_file_storage: temp_file_storage,
+        }
+    }
+
+    async fn archive(&self) -> bool {
+        archive(
+            self.test_database.pool(),
+            self.archiver_config.clone(),
+            self.storage.clone(),
+            self.downloader.clone(),
+            self.worker_id,
+        )
+        .await
+    }
+}
+
+#[actix_web::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_schedule_archive_search_and_download() {
+    let environment = TestEnvironment::create().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(environment.app_state.clone())
+            .wrap_fn(record_request)
+            .configure(configure),
+    )
+    .await;
+
+    let request = test::TestRequest::post()
+        .uri("/schedule")
+        .set_json(json!({ "url": VIDEO_URL }))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::CREATED
+    );
+
+    assert!(environment.archive().await);
+    // the schedule is deleted once the video is archived
+    assert!(!environment.archive().await);
+
+    // the worker is idle again once the archival is done
+    let request = test::TestRequest::get().uri("/workers").to_request();
+    // models read from postgres notifications are only renamed when serialized
+    let workers: Vec<serde_json::Value> = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(workers.len(), 1);
+    assert_eq!(workers[0]["id"], json!(environment.worker_id));
+    assert_eq!(workers[0]["currentJob"], serde_json::Value::Null);
+
+    let request = test::TestRequest::get()
+        .uri("/search?term=Fixture")
+        .to_request();
+    let videos: Vec<VideoDto> = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(videos.len(), 1);
+    let video = &videos[0].video;
+    assert_eq!(video.original_url, VIDEO_URL);
+    assert_eq!(video.status, VideoStatus::Archived);
+    assert_eq!(video.duration, 212);
+
+    let request = test::TestRequest::get()
+        .uri(&format!(
+            "/file?file_id={}&is_thumbnail=false",
+            video.file_id
+        ))
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert_eq!(body, format!("video of {}", VIDEO_URL).as_bytes());
+    assert_eq!(videos[0].video_size, body.len() as i64);
+
+    let request = test::TestRequest::get()
+        .uri("/export/catalogue?format=jsonl")
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["originalUrl"], VIDEO_URL);
+    assert_eq!(lines[0]["file"]["size"], videos[0].video_size);
+
+    let request = test::TestRequest::get()
+        .uri("/feed?format=json")
+        .to_request();
+    let feed: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(feed["items"].as_array().unwrap().len(), 1);
+    assert_eq!(feed["items"][0]["url"], VIDEO_URL);
+    assert!(feed["items"][0]["attachments"][0]["url"]
+        .as_str()
+        .unwrap()
+        .ends_with(&format!(
+            "/api/file?file_id={}&is_thumbnail=false",
+            video.file_id
+        )));
+    // podcast feeds only list audio files
+    let request = test::TestRequest::get()
+        .uri("/feed?format=podcast")
+        .to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    assert!(!std::str::from_utf8(&body).unwrap().contains("<item>"));
+
+    // the registry is shared by all tests, so only the presence of the series is checked
+    let request = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&app, request).await;
+    let metrics = std::str::from_utf8(&body).unwrap();
+    assert!(metrics.contains("immortalis_scheduled_archivals{stage=\"pending\"} 0"));
+    assert!(metrics.contains("immortalis_storage_bytes{backend=\"disk\"}"));
+    assert!(metrics.contains("immortalis_archival_outcomes_total{outcome=\"archived\"}"));
+    assert!(metrics.contains("route=\"/search\",status=\"200\""));
+
+    // scheduling an archived video again doesn't create a schedule
+    let request = test::TestRequest::post()
+        .uri("/schedule")
+        .set_json(json!({ "url": VIDEO_URL }))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::OK
+    );
+}
+
+#[actix_web::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_track_and_archive_collection() {
+    let environment = TestEnvironment::create().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(environment.app_state.clone())
+            .configure(configure),
+    )
+    .await;
+
+    let request = test::TestRequest::post()
+        .uri("/tracked_collection")
+        .set_json(json!({ "url": PLAYLIST_URL }))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::CREATED
+    );
+
+    assert!(
+        track(
+            environment.test_database.pool(),
+            environment.tracker_config.clone(),
+            environment.downloader.clone(),
+            environment.worker_id
+        )
+        .await
+    );
+
+    // the entry which couldn't be extracted is skipped
+    let request = test::TestRequest::get().uri("/schedule").to_request();
+    let schedules: Vec<serde_json::Value> = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(schedules.len(), 2);
+
+    assert!(environment.archive().await);
+    assert!(environment.archive().await);
+    assert!(!environment.archive().await);
+
+    // completing an archival only updates the status of its own video
+    let request = test::TestRequest::get().uri("/search").to_request();
+    let videos: Vec<VideoDto> = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(videos.len(), 2);
+    assert!(videos
+        .iter()
+        .all(|v| v.video.status == VideoStatus::Archived));
+}
+
+#[actix_web::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_removed_videos_are_published_as_unavailable() {
+    let environment = TestEnvironment::create().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(environment.app_state.clone())
+            .configure(configure),
+    )
+    .await;
+
+    let request = test::TestRequest::post()
+        .uri("/tracked_collection")
+        .set_json(json!({ "url": PLAYLIST_URL }))
+        .to_request();
+    test::call_service(&app, request).await;
+    assert!(
+        track(
+            environment.test_database.pool(),
+            environment.tracker_config.clone(),
+            environment.downloader.clone(),
+            environment.worker_id
+        )
+        .await
+    );
+    assert!(environment.archive().await);
+    assert!(environment.archive().await);
+
+    // the first entry is removed from the playlist and can't be extracted anymore
+    let downloader = fixture_downloader();
+    let mut playlist: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(fixture_directory().join("playlist.json")).unwrap())
+            .unwrap();
+    playlist["entries"].as_array_mut().unwrap().remove(0);
+    downloader.add_fixture(playlist);
+    downloader.remove_fixture(REMOVED_VIDEO_URL);
+
+    let db_connection = &mut environment.test_database.connect().await;
+    let tracked_collection = tracked_collections::table
+        .first::<TrackedCollection>(db_connection)
+        .await
+        .unwrap();
+    // the video is only published once
+    for _ in 0..2 {
+        assert!(check_collection(db_connection, &downloader, &tracked_collection).await);
+    }
+
+    let unavailable: Vec<serde_json::Value> =
+        load_events_after(db_connection, 0, &[LIFECYCLE_CHANNEL], 100)
+            .await
+            .unwrap()
+            .iter()
+            .map(|event| serde_json::from_str::<serde_json::Value>(&event.payload).unwrap())
+            .filter(|payload| payload["type"] == "video.unavailable")
+            .collect();
+    assert_eq!(unavailable.len(), 1);
+    assert_eq!(unavailable[0]["url"], REMOVED_VIDEO_URL);
+    assert_eq!(unavailable[0]["trackedCollectionId"], tracked_collection.id);
+}
+
+#[actix_web::test]
+#[ignore = "requires a database at TEST_DATABASE_URL"]
+async fn test_webhook_deliveries_are_queued() {
+    let environment = TestEnvironment::create().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(environment.app_state.clone())
+            .configure(configure),
+    )
+    .await;
+
+    let request = test::TestRequest::post()
+        .uri("/webhooks")
+        .set_json(json!({ "url": "ftp://example.com" }))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+    let request = test::TestRequest::post()
+        .uri("/webhooks")
+        .set_json(json!({ "url": "https://example.com/hook", "eventTypes": ["video.deleted"] }))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    let request = test::TestRequest::post()
+        .uri("/webhooks")
+        .set_json(json!({
+            "url": "https://example.com/hook",
+            "eventTypes": ["video.archived", "collection.new_videos"]
+        }))
+        .to_request();
+    let webhook: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+    assert!(!webhook["secret"].as_str().unwrap().is_empty());
+    let request = test::TestRequest::post()
+        .uri("/webhooks")
+        .set_json(json!({ "url": "https://example.com/failures", "eventTypes": ["video.failed"] }))
+        .to_request();
+    let failures_webhook: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+
+    // secrets are only returned when the webhook is created
+    let request = test::TestRequest::get().uri("/webhooks").to_request();
+    let webhooks: Vec<serde_json::Value> = test::call_and_read_body_json(&app, request).await;
+    assert_eq!(webhooks.len(), 2);
+    assert!(webhooks
+        .iter()
+        .all(|webhook| webhook.get("secret").is_none()));
+
+    let request = test::TestRequest::post()
+        .uri("/tracked_collection")
+        .set_json(json!({ "url": PLAYLIST_URL }))
+        .to_request();
+    test::call_service(&app, request).await;
+    assert!(
+        track(
+            environment.test_database.pool(),
+            environment.tracker_config.clone(),
+            environment.downloader.clone(),
+            environment.worker_id
+        )
+        .await
+    );
+    assert!(environment.archive().await);
+    assert!(environment.archive().await);
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/webhooks/{}/deliveries", webhook["id"]))
+        .to_request();
+    let deliveries: Vec<serde_json::Value> = test::call_and_read_body_json(&app, request).await;
+    let event_types: Vec<&str> = deliveries
+        .iter()
+        .map(|delivery| delivery["eventType"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        event_types,
+        vec!["video.archived", "video.archived", "collection.new_videos"]
+    );
+    assert!(deliveries
+        .iter()
+        .all(|delivery| delivery["deliveredAt"].is_null() && delivery["attempts"] == 0));
+    let new_videos: serde_json::Value =
+        serde_json::from_str(deliveries[2]["payload"].as_str().unwrap()).unwrap();
+    assert_eq!(new_videos["videoUrls"].as_array().unwrap().len(), 2);
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/webhooks/{}/deliveries", failures_webhook["id"]))
+        .to_request();
+    let deliveries: Vec<serde_json::Value> = test::call_and_read_body_json(&app, request).await;
+    assert!(deliveries.is_empty());
+
+    let request = test::TestRequest::delete()
+        .uri(&format!("/webhooks/{}", webhook["id"]))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, request).await.status(),
+        StatusCode::NO_CONTENT
+    );
+}
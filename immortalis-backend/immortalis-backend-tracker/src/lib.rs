@@ -0,0 +1,208 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Duration;
+use diesel::QueryDsl;
+use diesel::{
+    update, BoolExpressionMethods, ExpressionMethods, NullableExpressionMethods, OptionalExtension,
+};
+use diesel_async::pooled_connection::deadpool::{self, Pool};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use immortalis_backend_common::database_models::tracked_collection::TrackedCollection;
+use immortalis_backend_common::database_models::worker;
+use immortalis_backend_common::database_models::worker_kind::WorkerKind;
+use immortalis_backend_common::downloader::Downloader;
+use immortalis_backend_common::env_var_config::EnvVarConfigTracker;
+use immortalis_backend_common::metrics::TRACKER_CHECK_DURATION;
+use immortalis_backend_common::schema::{tracked_collections, workers};
+use immortalis_backend_common::tracking::check_collection;
+use tracing::{error, info, warn};
+
+/// dequeues a TrackedCollection which hasn't been checked for check_interval_seconds. The Entry will become available again once the processing_timeout has passed, if it hasn't been deleted by then
+async fn dequeue(
+    db_connection: &mut deadpool::Object<AsyncPgConnection>,
+    check_interval_seconds: i64,
+    processing_timeout_seconds: i64,
+) -> Result<Option<TrackedCollection>, diesel::result::Error> {
+    db_connection
+        .transaction::<Option<TrackedCollection>, diesel::result::Error, _>(|db_connection| {
+            async move {
+                let not_checked_after = chrono::Utc::now()
+                    .naive_utc()
+                    .checked_sub_signed(Duration::seconds(check_interval_seconds))
+                    .unwrap();
+
+                let result = tracked_collections::table
+                    .limit(1)
+                    .filter(
+                        tracked_collections::last_checked
+                            .lt(not_checked_after)
+                            .or(tracked_collections::last_checked.is_null()),
+                    )
+                    .for_update()
+                    .skip_locked()
+                    .first::<TrackedCollection>(db_connection)
+                    .await
+                    .optional()?;
+
+                if let Some(entry) = result {
+                    // set not_before to now + timeout. This prevents other processes from trying to preform it as well and allows retry in case this process crashes
+                    update(tracked_collections::table)
+                        .set(
+                            tracked_collections::last_checked.eq(chrono::Utc::now()
+                                .naive_utc()
+                                .checked_add_signed(Duration::seconds(processing_timeout_seconds))
+                                .unwrap()),
+                        )
+                        .filter(tracked_collections::id.eq(entry.id))
+                        .execute(db_connection)
+                        .await?;
+                    Ok(Some(entry))
+                } else {
+                    Ok(None)
+                }
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// Registers the workers or updates their heartbeat and removes dead workers of all replicas.
+/// Returns false if the heartbeat couldn't be sent
+pub async fn heartbeat(
+    pool: Pool<AsyncPgConnection>,
+    env_var_config: Arc<EnvVarConfigTracker>,
+    worker_ids: &[uuid::Uuid],
+    host: &str,
+) -> bool {
+    let db_connection = &mut match pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Encountered Database error: {}", e);
+            return false;
+        }
+    };
+
+    if let Err(e) = worker::heartbeat(
+        db_connection,
+        worker_ids,
+        host,
+        WorkerKind::Tracker,
+        env!("CARGO_PKG_VERSION"),
+    )
+    .await
+    {
+        error!("Failed to send heartbeat, encountered error {}", e);
+        return false;
+    }
+
+    match worker::remove_dead_workers(
+        db_connection,
+        Duration::seconds(env_var_config.tracker_worker_timeout_seconds),
+    )
+    .await
+    {
+        Ok(0) => (),
+        Ok(removed) => warn!("Removed {} dead workers", removed),
+        Err(e) => error!("Failed to remove dead workers, encountered error {}", e),
+    }
+    true
+}
+
+/// Makes the collections the workers are checking due again, so another replica checks them instead of waiting for the check timeout.
+/// Has to be called before the workers are deregistered, returns the number of released collections
+pub async fn release_collections(
+    db_connection: &mut AsyncPgConnection,
+    env_var_config: &EnvVarConfigTracker,
+    worker_ids: &[uuid::Uuid],
+) -> Result<usize, diesel::result::Error> {
+    update(tracked_collections::table)
+        .set(
+            tracked_collections::last_checked.eq(chrono::Utc::now()
+                .naive_utc()
+                .checked_sub_signed(Duration::seconds(
+                    env_var_config.tracker_check_interval_seconds,
+                ))
+                .unwrap()),
+        )
+        .filter(
+            tracked_collections::url.nullable().eq_any(
+                workers::table
+                    .select(workers::current_job)
+                    .filter(workers::id.eq_any(worker_ids)),
+            ),
+        )
+        .execute(db_connection)
+        .await
+}
+
+/// returns true if a tracked_collection has been processed, returns false if there were no due tracked_collections or an error occured
+pub async fn track(
+    pool: Pool<AsyncPgConnection>,
+    env_var_config: Arc<EnvVarConfigTracker>,
+    downloader: Arc<dyn Downloader>,
+    worker_id: uuid::Uuid,
+) -> bool {
+    // try getting db connection, retry if it fails
+    let db_connection = &mut loop {
+        match pool.get().await {
+            Ok(c) => break c,
+            Err(e) => {
+                error!("Encountered Database error: {}", e);
+                tokio::time::sleep(tokio::time::Duration::from_secs(
+                    env_var_config.tracker_idle_sleep_seconds,
+                ))
+                .await;
+                continue;
+            }
+        }
+    };
+
+    let tracked_collection = match dequeue(
+        db_connection,
+        env_var_config.tracker_check_interval_seconds,
+        env_var_config.tracker_check_timeout_seconds,
+    )
+    .await
+    {
+        Ok(x) => match x {
+            Some(scheduled_archival) => {
+                info!(
+                    scheduled_archival.id = scheduled_archival.id,
+                    scheduled_archival.url = scheduled_archival.url,
+                    "Dequeued entry: {} with url: {}",
+                    scheduled_archival.id,
+                    scheduled_archival.url
+                );
+                scheduled_archival
+            }
+            None => {
+                info!("No due TrackedCollections found");
+                return false;
+            }
+        },
+        Err(x) => {
+            error!(
+                "Failed to Dequeue TrackedCollections, encountered error {}",
+                x
+            );
+            return false;
+        }
+    };
+
+    if let Err(e) =
+        worker::set_current_job(db_connection, worker_id, Some(&tracked_collection.url)).await
+    {
+        warn!("Failed to set current job, encountered error {}", e);
+    }
+    let check_start = Instant::now();
+    let checked = check_collection(db_connection, downloader.as_ref(), &tracked_collection).await;
+    TRACKER_CHECK_DURATION
+        .with_label_values(&[if checked { "true" } else { "false" }])
+        .observe(check_start.elapsed().as_secs_f64());
+    if let Err(e) = worker::set_current_job(db_connection, worker_id, None).await {
+        warn!("Failed to clear current job, encountered error {}", e);
+    }
+    checked
+}
@@ -1,6 +1,8 @@
 export default {
   WebSocketChannels: {
     ScheduledArchivals: "scheduled_archivals",
-    TrackedCollections: "tracked_collections"
+    TrackedCollections: "tracked_collections",
+    Workers: "workers",
+    Videos: "videos"
   }
 }
\ No newline at end of file
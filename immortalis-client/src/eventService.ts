@@ -3,10 +3,14 @@ import { WebSocketEvent } from './models/webSocketEvent';
 import { DataChangeEvent } from './models/dataChangeEvent';
 import { ScheduledArchival } from './models/scheduledArchival';
 import { TrackedCollection } from './models/trackedCollection';
+import { Worker } from './models/worker';
+import { VideoEvent } from './models/videoEvent';
 
 type Events = {
     webSocketScheduledArchival: WebSocketEvent<DataChangeEvent<ScheduledArchival>>,
     webSocketTrackedCollection: WebSocketEvent<DataChangeEvent<TrackedCollection>>,
+    webSocketWorker: WebSocketEvent<DataChangeEvent<Worker>>,
+    webSocketVideo: WebSocketEvent<VideoEvent>,
 };
 
 export const emitter: Emitter<Events> = mitt<Events>();
\ No newline at end of file
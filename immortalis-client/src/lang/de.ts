@@ -36,10 +36,20 @@ export const messages = {
         "alreadyTracked": "Die Adresse {0} wird bereits verfolgt"
       },
     },
+    workersView: {
+      "host": "Host",
+      "kind": "Art",
+      "version": "Version",
+      "currentJob": "Aktuelle Aufgabe",
+      "jobStartedAt": "Aufgabe gestartet am",
+      "lastHeartbeat": "Letzter Heartbeat",
+      "idle": "Untätig",
+    },
     navigation: {
       "home": "Zuhause",
       "schedules": "Geplante Archivierungen",
-      "trackedCollections": "Verfolgte Playlists/Kanäle"
+      "trackedCollections": "Verfolgte Playlists/Kanäle",
+      "workers": "Worker"
     },
     videoEntryComponent: {
       "download": "Herunterladen",
@@ -36,10 +36,20 @@ export const messages = {
         "alreadyTracked": "The Url {0} is already being tracked"
       },
     },
+    workersView: {
+      "host": "Host",
+      "kind": "Kind",
+      "version": "Version",
+      "currentJob": "Current job",
+      "jobStartedAt": "Job started at",
+      "lastHeartbeat": "Last heartbeat",
+      "idle": "Idle",
+    },
     navigation: {
       "home": "Home",
       "schedules": "Planned archivals",
-      "trackedCollections": "Tracked Playlists/Channels"
+      "trackedCollections": "Tracked Playlists/Channels",
+      "workers": "Workers"
     },
     videoEntryComponent: {
       "download": "Download",
@@ -1,4 +1,6 @@
 export interface DataChangeEvent<T> {
     action: "delete" | "insert" | "update",
     record: T,
-}
\ No newline at end of file
+    // the record before an update
+    oldRecord?: T,
+}
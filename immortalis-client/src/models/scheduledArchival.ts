@@ -3,4 +3,5 @@ export interface ScheduledArchival {
     url: String,
     scheduled_at: Date,
     not_before: Date,
+    priority: number,
 }
\ No newline at end of file
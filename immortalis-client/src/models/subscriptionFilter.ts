@@ -0,0 +1,5 @@
+export interface SubscriptionFilter {
+    videoId?: number,
+    trackedCollectionId?: number,
+    statusChangesOnly?: boolean,
+}
@@ -1,7 +1,5 @@
-import { UUID } from "crypto";
-
 export interface Video {
-    id: UUID;
+    id: number;
     title: string;
     channel: string;
     views: number;
@@ -13,5 +11,9 @@ export interface Video {
     status: string;
     fileId: string;
     thumbnailId: string;
+    releaseTimestamp?: Date;
+    liveStatus?: string;
+    isLiveRecording: boolean;
+    trackedCollectionId?: number;
 }
 
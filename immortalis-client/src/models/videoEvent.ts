@@ -0,0 +1,8 @@
+import { Video } from './video';
+
+export interface VideoEvent {
+    action: "delete" | "insert" | "update",
+    id: number,
+    // not sent for deletions
+    record?: Video,
+}
\ No newline at end of file
@@ -1,4 +1,6 @@
 export interface WebSocketEvent<T> {
+    type: "event",
+    id: number,
     channel: string,
     data: T
-}
\ No newline at end of file
+}
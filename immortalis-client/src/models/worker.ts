@@ -0,0 +1,10 @@
+export interface Worker {
+    id: string,
+    host: string,
+    kind: "archiver" | "tracker",
+    version: string,
+    registeredAt: Date,
+    lastHeartbeat: Date,
+    currentJob?: string,
+    jobStartedAt?: Date,
+}
@@ -29,6 +29,10 @@ const routes = [
   {
     path: "/tracked-collections",
     component: () => import("@/views/TrackedCollections.vue"),
+  },
+  {
+    path: "/workers",
+    component: () => import("@/views/Workers.vue"),
   }
 ]
 
@@ -0,0 +1,36 @@
+import { SubscriptionFilter } from './models/subscriptionFilter';
+
+// the subscriptions of the views, they are sent again whenever the websocket (re)connects
+const subscriptions: Map<string, SubscriptionFilter> = new Map();
+// the id of the last event received per channel, so the events missed while reconnecting are replayed
+const lastEventIds: Map<string, number> = new Map();
+let webSocket: WebSocket | undefined;
+
+function send(message: object) {
+  if (webSocket?.readyState === WebSocket.OPEN) {
+    webSocket.send(JSON.stringify(message));
+  }
+}
+
+export function subscribe(channel: string, filter: SubscriptionFilter = {}) {
+  subscriptions.set(channel, filter);
+  // views load the current state when they subscribe, so nothing has to be replayed
+  lastEventIds.delete(channel);
+  send({ type: "subscribe", channel, filter });
+}
+
+export function unsubscribe(channel: string) {
+  lastEventIds.delete(channel);
+  if (subscriptions.delete(channel)) {
+    send({ type: "unsubscribe", channel });
+  }
+}
+
+export function onEventReceived(channel: string, id: number) {
+  lastEventIds.set(channel, id);
+}
+
+export function onWebSocketOpened(openedWebSocket: WebSocket) {
+  webSocket = openedWebSocket;
+  subscriptions.forEach((filter, channel) => send({ type: "subscribe", channel, filter, lastEventId: lastEventIds.get(channel) }));
+}